@@ -0,0 +1,108 @@
+#!/usr/bin/env python3
+"""Generates src/strings/unicode_tables.rs from the Unicode Character Database that
+comes with Python's unicodedata module.
+
+Usage: python3 scripts/unicode_tables.py > src/strings/unicode_tables.rs
+"""
+
+import sys
+import unicodedata
+
+CHARS = [chr(code) for code in range(sys.maxunicode + 1) if not 0xD800 <= code <= 0xDFFF]
+
+
+def rust_char(char):
+    return f"'\\u{{{ord(char):X}}}'"
+
+
+def rust_chars(chars):
+    return "&[" + ", ".join(rust_char(char) for char in chars) + "]"
+
+
+def rust_string(string):
+    return '"' + "".join(f"\\u{{{ord(char):X}}}" for char in string) + '"'
+
+
+def decompositions():
+    """Single-level decompositions, split into canonical and compatibility ones. Hangul
+    syllables are decomposed algorithmically and have none."""
+    canonical, compatibility = [], []
+    for char in CHARS:
+        decomposition = unicodedata.decomposition(char)
+        if not decomposition:
+            continue
+        fields = decomposition.split()
+        table = canonical
+        if fields[0].startswith("<"):
+            table = compatibility
+            fields = fields[1:]
+        table.append((char, [chr(int(field, 16)) for field in fields]))
+    return canonical, compatibility
+
+
+def compositions(canonical):
+    """Pairs composed by NFC: canonical decompositions into two characters, other than
+    those excluded from composition (which NFC leaves decomposed)."""
+    pairs = []
+    for char, parts in canonical:
+        if len(parts) == 2 and unicodedata.normalize("NFC", "".join(parts)) == char:
+            pairs.append((parts[0], parts[1], char))
+    return sorted(pairs)
+
+
+def ranges(chars, value):
+    """Ranges of consecutive characters with the same value, skipping those without one."""
+    result = []
+    for char in chars:
+        current = value(char)
+        if current is None:
+            continue
+        if result and ord(result[-1][1]) + 1 == ord(char) and result[-1][2] == current:
+            result[-1][1] = char
+        else:
+            result.append([char, char, current])
+    return result
+
+
+def main():
+    canonical, compatibility = decompositions()
+    lines = [
+        "// Generated by scripts/unicode_tables.py from the Unicode Character Database"
+        f" {unicodedata.unidata_version};",
+        "// do not edit manually.",
+        "",
+    ]
+
+    def table(name, kind, rows):
+        lines.append(f"pub const {name}: &[{kind}] = &[")
+        lines.extend(f"    ({', '.join(row)})," for row in rows)
+        lines.extend(["];", ""])
+
+    table("CANONICAL_DECOMPOSITIONS", "(char, &[char])", [
+        (rust_char(char), rust_chars(parts)) for char, parts in canonical
+    ])
+    table("COMPATIBILITY_DECOMPOSITIONS", "(char, &[char])", [
+        (rust_char(char), rust_chars(parts)) for char, parts in compatibility
+    ])
+    table("COMPOSITIONS", "(char, char, char)", [
+        tuple(rust_char(char) for char in row) for row in compositions(canonical)
+    ])
+    table("COMBINING_CLASSES", "(char, char, u8)", [
+        (rust_char(first), rust_char(last), str(value)) for first, last, value in ranges(
+            CHARS, lambda char: unicodedata.combining(char) or None
+        )
+    ])
+    table("CASE_FOLDING_EXCEPTIONS", "(char, &str)", [
+        (rust_char(char), rust_string(char.casefold()))
+        for char in CHARS if char.casefold() != char.lower()
+    ])
+    table("PUNCTUATION", "(char, char)", [
+        (rust_char(first), rust_char(last)) for first, last, _ in ranges(
+            CHARS, lambda char: True if unicodedata.category(char).startswith("P") else None
+        )
+    ])
+    sys.stdout.write("\n".join(lines))
+
+
+if __name__ == "__main__":
+    main()
@@ -1,48 +1,220 @@
 use std::path::PathBuf;
 
-use anyhow::{ensure, Result};
+use anyhow::{bail, ensure, Context, Result};
 use clap::{AppSettings, IntoApp, Parser};
 
-use crate::params::{ParamNames, Params};
+use crate::files::is_same_file;
+use crate::params::{
+    Aggregation,
+    Aggregator,
+    CoalescePolicy,
+    Column,
+    Delimiter,
+    Encoding,
+    Join,
+    MergeParams,
+    MultiMerge,
+    Normalizer,
+    OutputFormat,
+    ParamNames,
+    Params,
+    ProvenanceColumn,
+    RaggedPolicy,
+    ReportFormat,
+    Worksheet,
+    DEFAULT_MAX_COMBINATIONS,
+    STANDARD_STREAM,
+};
+use crate::strings::countable::Countable;
+
+const AUTO_DELIMITER: &str = "auto";
+const DEFAULT_AGGREGATE_SEPARATOR: &str = ";";
 
 #[derive(Parser)]
 #[clap(global_setting(AppSettings::AllowNegativeNumbers))]
 struct Cli {
-    /// CSV/TSV files to consolidate (at least two).
+    /// CSV/TSV files, xlsx/xls/ods spreadsheets or JSON files (arrays of objects or an object
+    /// per line) to consolidate (at least two); one of them can be "-" to read from stdin.
     #[clap(short, long, parse(from_os_str))]
     #[structopt(required = true, min_values = 2)]
     inputs: Vec<PathBuf>,
 
     /// Path to the consolidated CSV/TSV file (must be different from all
-    /// the input files; will be overridden if exists).
+    /// the input files; will be overridden if exists, but only once the output is
+    /// complete), or "-" to write to stdout.
     #[clap(short, long, parse(from_os_str))]
     output: PathBuf,
 
-    /// Delimiter character.
-    #[clap(short, long, default_value_t = '\t')]
-    delimiter: char,
+    /// Format of the output: "delimited", "xlsx", "json" (an array of objects) or "ndjson"
+    /// (an object per line). If not provided, the format is told by the extension of
+    /// the output file (.xlsx, .json, .ndjson or .jsonl), defaulting to delimited.
+    #[clap(long)]
+    output_format: Option<String>,
+
+    /// Exit with an error instead of overwriting the output file if it already exists.
+    #[clap(long)]
+    no_clobber: bool,
+
+    /// Delimiter (one or more characters), or "auto" to detect the delimiter of each input file
+    /// separately (by its extension and by the consistency of the number of fields
+    /// across its first records; ',', '\t', ';' and '|' are considered).
+    #[clap(short, long, default_value = "\t")]
+    delimiter: String,
+
+    /// Delimiter (one or more characters) for the output file. If not provided,
+    /// the delimiter of the first input file will be used.
+    #[clap(long)]
+    output_delimiter: Option<String>,
+
+    /// Character encoding of the input files: utf-8 (the default), utf-16le, utf-16be,
+    /// latin1 or windows-1252; prefix it with N= to set the encoding of the N-th input
+    /// file only; can be repeated. An input file starting with a byte order mark is decoded
+    /// accordingly regardless (the byte order mark is skipped).
+    #[clap(long)]
+    encoding: Vec<String>,
+
+    /// Worksheet of the spreadsheet inputs to read, by name or by 1-based index (the first
+    /// worksheet by default); prefix it with N= to choose the worksheet of the N-th input
+    /// file only; can be repeated.
+    #[clap(long)]
+    sheet: Vec<String>,
+
+    /// Character encoding of the output file: utf-8, utf-8-bom (UTF-8 starting with a byte
+    /// order mark), utf-16 (UTF-16LE starting with a byte order mark, as expected by Excel),
+    /// utf-16le, utf-16be, latin1 or windows-1252.
+    #[clap(long, default_value = "utf-8")]
+    output_encoding: String,
+
+    /// Treat the first record of each input file as a header row; the output
+    /// will then start with a single header row as well.
+    #[clap(long)]
+    headers: bool,
 
     /// Indices of columns containing data that the records should be identified by
     /// (1-based; use positive values to refer to columns left-to-right, negative
     /// values to refer to columns right-to-left, zero to refer to a special column
-    /// whose values are considered unique for each individual record).
+    /// whose values are considered unique for each individual record); when
+    /// --headers is set, columns can also be referred to by their header names.
+    #[clap(short, long)]
+    shared: Vec<String>,
+
+    /// Shared columns of a particular input file, overriding --shared for that file
+    /// (format: INPUT=COLUMNS, where INPUT is the 1-based index of the input file and
+    /// COLUMNS is a comma-separated list of columns, each in the same format as
+    /// accepted by --shared); can be repeated for different input files.
     #[clap(short, long)]
-    shared: Vec<i32>,
+    key: Vec<String>,
+
+    /// Normalizations applied to values of shared columns before records are matched
+    /// (comma-separated list of: trim, collapse-whitespace, fold-case, nfc, nfkc,
+    /// strip-diacritics, remove-punctuation; applied in the given order); prefix the
+    /// list with N= to apply it to the N-th shared column only; can be repeated.
+    /// The output contains the original values.
+    #[clap(long)]
+    normalize: Vec<String>,
+
+    /// Non-shared columns present in several input files that should be output as a single
+    /// column (at the position the column has in the first input file that has it), in
+    /// the same format as accepted by --shared; input files lacking a column referred to
+    /// by name are skipped.
+    #[clap(long)]
+    coalesce: Vec<String>,
+
+    /// How to choose the value of a coalesced column when the merged records have
+    /// different values in it: "first" (first non-empty value), "last" (last non-empty
+    /// value), a 1-based input index N (the non-empty value from input file N, if any,
+    /// otherwise the first non-empty value), or "fail" (exit with an error). Conflicting
+    /// values are reported in any case.
+    #[clap(long, default_value = "first")]
+    coalesce_policy: String,
+
+    /// Collapse the records with the same shared values within each input file into
+    /// a single record (instead of matching them one by one with those of the other
+    /// input files), aggregating the values of each non-shared column with one of: sum,
+    /// min, max (numeric; empty values are ignored, and non-numeric ones are an error),
+    /// count (the number of non-empty values), concat[:SEPARATOR] (the non-empty values
+    /// joined with SEPARATOR, ";" by default), distinct-join[:SEPARATOR] (the same for
+    /// distinct values), first, last (the value of the first/last record). Format:
+    /// [COLUMN=]AGGREGATOR, where COLUMN is in the same format as accepted by --shared;
+    /// without COLUMN=, sets the aggregator of all the other columns ("first" by default).
+    /// Can be repeated.
+    #[clap(long, conflicts_with = "multi")]
+    aggregate: Vec<String>,
 
     /// Allow consolidation when all the input files contain a single column.
     #[clap(long)]
     single: bool,
 
-    /// Still allow consolidation when there are multiple ways to match records.
+    /// Still allow consolidation when there are multiple ways to match records, i.e., when
+    /// some input files have several records with the same shared values: "zip" (the
+    /// default) matches such records in the order they appear in the input files, while
+    /// "cartesian" outputs every combination of them.
+    #[clap(
+        long,
+        value_name = "MODE",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "zip",
+    )]
+    multi: Option<String>,
+
+    /// Maximum number of records that --multi=cartesian may output for the same shared
+    /// values; exceeding it is an error.
+    #[clap(long, default_value_t = DEFAULT_MAX_COMBINATIONS)]
+    max_combinations: usize,
+
+    /// Which records to output: "outer" (all), "inner" (only those present in all
+    /// the input files), "left"/"right" (only those present in the first/last input
+    /// file), a 1-based input index N (only those present in input file N), or
+    /// "anti" (only those present in the first input file but in no other).
+    #[clap(long, default_value = "outer")]
+    join: String,
+
+    /// Assume that each input file is sorted by the values of its shared columns
+    /// (after normalization; compared column by column and character by character)
+    /// and merge the inputs on the fly, holding in memory only the records with the same
+    /// shared values at a time; records are then output in the order of their shared
+    /// values. Similarity warnings additionally keep every distinct key in memory and are
+    /// reported at the end. Cannot be combined with fuzzy merging, which may match records
+    /// that are far apart in this order.
     #[clap(long)]
-    multi: bool,
+    sorted: bool,
+
+    /// Sort the input files on disk (in temporary files) before merging them as with
+    /// --sorted, holding at most the given number of records of each input in memory
+    /// at once; meant for inputs that do not fit into memory. Records are then output
+    /// in the order of their shared values, and the same restrictions as for --sorted
+    /// apply.
+    #[clap(long, conflicts_with = "sorted")]
+    external_sort: Option<usize>,
 
     /// Filler string for output cells with otherwise missing values (which would
     /// occur for records missing from some of the input files but present in others).
-    /// If not provided, an empty string will be used.
+    /// If not provided, an empty string will be used. A JSON output has null values
+    /// instead.
     #[clap(long)]
     filler: Option<String>,
 
+    /// Highlight the output cells with otherwise missing values (those filled with
+    /// the filler) when the output is an xlsx workbook.
+    #[clap(long)]
+    highlight_filler: bool,
+
+    /// What to do with records whose number of columns differs from that of the first
+    /// record (the header row, if any) of their input file: "error" (exit with an error),
+    /// or a comma-separated list of: pad (fill the missing trailing cells of shorter
+    /// records with the filler), truncate (drop the extra trailing values of longer
+    /// records), skip (skip and report the records that are neither padded nor truncated).
+    #[clap(long, default_value = "error")]
+    ragged: String,
+
+    /// If the combined edit distance between records' values does not exceed this
+    /// value, the records are merged as if their values were identical (0 means
+    /// exact matching only); only values in columns declared as shared are compared.
+    /// Each such merge is reported.
+    #[clap(long, default_value_t = 0)]
+    fuzzy_merge: u32,
+
     /// If the combined edit distance between mismatched records' values
     /// does not exceed this value, a warning is displayed (0 means no warnings);
     /// only values in columns declared as shared are compared.
@@ -52,55 +224,453 @@ struct Cli {
     /// Warn about any unmatched records.
     #[clap(long)]
     warn_unmatched: bool,
+
+    /// Extra columns to append to the output, describing where each record comes from
+    /// (comma-separated list of: sources, the 1-based indices of the input files that
+    /// contributed to the record; rows, the 1-based numbers of the contributing data
+    /// records within those files, as INPUT:RECORD pairs separated by semicolons; match,
+    /// "full" if all the input files contributed to the record, "partial" otherwise).
+    /// The columns are named _sources, _rows and _match, respectively.
+    #[clap(long)]
+    provenance: Option<String>,
+
+    /// Directory to write the unmatched records of each input file to (those having
+    /// no counterpart in some other input file), in a file of the same name, format
+    /// and encoding as the input file; spreadsheets are written as xlsx, with the
+    /// extension of the file changed accordingly (e.g. data.ods.gz becomes data.xlsx.gz).
+    /// The directory will be created if it does not exist, and files in it will be
+    /// overridden.
+    #[clap(long, parse(from_os_str))]
+    unmatched_dir: Option<PathBuf>,
+
+    /// Format of the warnings and other diagnostics: "text" (human-readable) or "json"
+    /// (a JSON array of objects, each with the kind of the diagnostic, the records
+    /// involved, their shared values and the edit distance between them, if applicable).
+    #[clap(long, default_value = "text")]
+    report: String,
+
+    /// File to write the diagnostics to instead of stderr (will be overridden if exists).
+    #[clap(long, parse(from_os_str))]
+    report_file: Option<PathBuf>,
 }
 
 macro_rules! argument_name {
     ($app:expr, $struct:ident.$field:ident) => {
         {
             let _ = $struct.$field;
-            let name = stringify!($field);
+            let name = stringify!($field).replace('_', "-");
             let result = $app.get_arguments()
                 .find(|arg| arg.get_name() == name)
-                .and_then(|arg| Some(format!("{arg}")));
+                .and_then(|arg| arg.get_long())
+                .map(|long| format!("--{long}"));
             result
         }
     };
 }
 
-fn check_convert_delimiter(delimiter: char) -> Result<u8> {
-    ensure!(delimiter.is_ascii(), "'{delimiter}' is not an ASCII character; \
-                                   only ASCII delimiters are currently supported.");
-    Ok(delimiter as u8)
+fn check_delimiter(delimiter: String) -> Result<String> {
+    ensure!(!delimiter.is_empty(), "The delimiter cannot be empty.");
+    ensure!(
+        !delimiter.contains(['"', '\r', '\n']),
+        "The delimiter cannot contain quotes or line breaks.",
+    );
+    Ok(delimiter)
+}
+
+fn check_convert_input_delimiter(delimiter: String) -> Result<Delimiter> {
+    Ok(if delimiter == AUTO_DELIMITER {
+        Delimiter::Auto
+    } else {
+        Delimiter::Fixed(check_delimiter(delimiter)?)
+    })
+}
+
+/// Converts the name of an encoding, returning whether a byte order mark should be written
+/// along with the encoding.
+fn convert_encoding(name: &str) -> Result<(Encoding, bool)> {
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "utf-8" | "utf8" => (Encoding::Utf8, false),
+        "utf-8-bom" => (Encoding::Utf8, true),
+        "utf-16" => (Encoding::Utf16Le, true),
+        "utf-16le" => (Encoding::Utf16Le, false),
+        "utf-16be" => (Encoding::Utf16Be, false),
+        "latin1" | "latin-1" | "iso-8859-1" => (Encoding::Latin1, false),
+        "windows-1252" | "cp1252" => (Encoding::Windows1252, false),
+        _ => bail!("'{name}' is not a known encoding."),
+    })
+}
+
+fn convert_encodings(values: Vec<String>, input_count: usize) -> Result<Vec<Encoding>> {
+    let mut default = None;
+    let mut result = vec![None; input_count];
+    for value in values {
+        let (input, name) = match value.split_once('=') {
+            Some((input, name)) => (Some(input), name),
+            None => (None, value.as_str()),
+        };
+        let (encoding, _) = convert_encoding(name)?;
+        let target = if let Some(input) = input {
+            let input_index = input.parse::<usize>().ok()
+                .filter(|index| (1..=input_count).contains(index))
+                .with_context(|| format!("'{input}' is not a valid input index \
+                                          (total inputs: {input_count})."))?;
+            &mut result[input_index - 1]
+        } else {
+            &mut default
+        };
+        ensure!(target.is_none(), "The encoding of the same inputs is specified more than once.");
+        *target = Some(encoding);
+    }
+    Ok(result.into_iter().map(
+        |encoding| encoding.or(default).unwrap_or(Encoding::Utf8)
+    ).collect())
+}
+
+fn convert_worksheet(value: &str) -> Worksheet {
+    match value.parse::<usize>() {
+        Ok(index) => Worksheet::Index(index),
+        Err(_) => Worksheet::Name(String::from(value)),
+    }
+}
+
+fn convert_worksheets(values: Vec<String>, input_count: usize) -> Result<Vec<Worksheet>> {
+    let mut default = None;
+    let mut result = vec![None; input_count];
+    for value in values {
+        // Worksheet names may contain '=' themselves, so only a number is taken as an input.
+        let (input, worksheet) = match value.split_once('=') {
+            Some((input, worksheet)) if input.parse::<usize>().is_ok() => (Some(input), worksheet),
+            _ => (None, value.as_str()),
+        };
+        let worksheet = convert_worksheet(worksheet);
+        ensure!(
+            worksheet != Worksheet::Index(0),
+            "Worksheet indices are 1-based, so 0 is not a valid worksheet.",
+        );
+        let target = if let Some(input) = input {
+            let input_index = input.parse::<usize>().ok()
+                .filter(|index| (1..=input_count).contains(index))
+                .with_context(|| format!("'{input}' is not a valid input index \
+                                          (total inputs: {input_count})."))?;
+            &mut result[input_index - 1]
+        } else {
+            &mut default
+        };
+        ensure!(target.is_none(), "The worksheet of the same inputs is specified more than once.");
+        *target = Some(worksheet);
+    }
+    Ok(result.into_iter().map(
+        |worksheet| worksheet.or(default.clone()).unwrap_or(Worksheet::Index(1))
+    ).collect())
 }
 
-fn check_similarity_warn_level(level: u32, shared_columns: &[i32]) -> Result<()> {
+fn convert_column(value: String, has_headers: bool) -> Result<Column> {
+    Ok(if let Ok(index) = value.parse::<i32>() {
+        Column::Index(index)
+    } else {
+        ensure!(
+            has_headers,
+            "'{value}' is not a column index; columns can only be referred to by name \
+             when the inputs have headers.",
+        );
+        Column::Name(value)
+    })
+}
+
+fn convert_shared_columns(shared: Vec<String>, has_headers: bool) -> Result<Vec<Column>> {
+    shared.into_iter().map(|value| convert_column(value, has_headers)).collect()
+}
+
+fn convert_key_columns(
+    keys: Vec<String>,
+    shared_columns: Vec<Column>,
+    input_count: usize,
+    has_headers: bool,
+) -> Result<Vec<Vec<Column>>> {
+    let mut result = vec![None; input_count];
+    for key in keys {
+        let (input, columns) = key.split_once('=').with_context(
+            || format!("'{key}' does not follow the INPUT=COLUMNS format.")
+        )?;
+        let input_index = input.parse::<usize>().ok()
+            .filter(|index| (1..=input_count).contains(index))
+            .with_context(|| format!("'{input}' is not a valid input index \
+                                      (total inputs: {input_count})."))?;
+        ensure!(
+            result[input_index - 1].is_none(),
+            "Shared columns of input #{input_index} are specified more than once.",
+        );
+        result[input_index - 1] = Some(columns.split(',').map(
+            |value| convert_column(String::from(value), has_headers)
+        ).collect::<Result<Vec<_>>>()?);
+    }
+    let result: Vec<_> = result.into_iter().map(
+        |columns| columns.unwrap_or_else(|| shared_columns.clone())
+    ).collect();
+    if let Some(first) = result.first() {
+        for (index, columns) in result.iter().enumerate() {
+            ensure!(
+                columns.len() == first.len(),
+                "Input #1 has {first_columns}, but input #{n} has {nth_columns}.",
+                first_columns = first.len().count_with("shared column"),
+                nth_columns = columns.len().count_with("shared column"),
+                n = index + 1,
+            );
+        }
+    }
+    Ok(result)
+}
+
+fn convert_normalizer(name: &str) -> Result<Normalizer> {
+    Ok(match name {
+        "trim" => Normalizer::Trim,
+        "collapse-whitespace" => Normalizer::CollapseWhitespace,
+        "fold-case" => Normalizer::FoldCase,
+        "nfc" => Normalizer::Nfc,
+        "nfkc" => Normalizer::Nfkc,
+        "strip-diacritics" => Normalizer::StripDiacritics,
+        "remove-punctuation" => Normalizer::RemovePunctuation,
+        _ => bail!("'{name}' is not a known normalization."),
+    })
+}
+
+fn convert_normalizers(values: Vec<String>, shared_count: usize) -> Result<Vec<Vec<Normalizer>>> {
+    ensure!(
+        values.is_empty() || shared_count > 0,
+        "Normalization makes no sense when data has no shared columns.",
+    );
+    let mut default = None;
+    let mut result = vec![None; shared_count];
+    for value in values {
+        let (column, names) = match value.split_once('=') {
+            Some((column, names)) => (Some(column), names),
+            None => (None, value.as_str()),
+        };
+        let normalizers = names.split(',').map(convert_normalizer).collect::<Result<Vec<_>>>()?;
+        let target = if let Some(column) = column {
+            let column_index = column.parse::<usize>().ok()
+                .filter(|index| (1..=shared_count).contains(index))
+                .with_context(|| format!("'{column}' is not a valid shared column index \
+                                          (total shared columns: {shared_count})."))?;
+            &mut result[column_index - 1]
+        } else {
+            &mut default
+        };
+        ensure!(
+            target.is_none(),
+            "Normalizations of the same columns are specified more than once.",
+        );
+        *target = Some(normalizers);
+    }
+    Ok(result.into_iter().map(
+        |normalizers| normalizers.or_else(|| default.clone()).unwrap_or_default()
+    ).collect())
+}
+
+fn check_edit_distance_level(
+    level: u32,
+    shared_columns: &[Vec<Column>],
+    option: &str,
+) -> Result<()> {
     if level > 0 {
         ensure!(
-            !shared_columns.contains(&0),
-            "Non-zero similarity warn level makes no sense \
+            !shared_columns.iter().flatten().any(|column| matches!(column, Column::Index(0))),
+            "Non-zero {option} level makes no sense \
              when shared columns contain 0 (the unique column).",
         );
         ensure!(
-            !shared_columns.is_empty(),
-            "Non-zero similarity warn level makes no sense when data has no shared columns.",
+            !shared_columns.iter().all(Vec::is_empty),
+            "Non-zero {option} level makes no sense when data has no shared columns.",
         );
     }
     Ok(())
 }
 
-fn check_inputs(inputs: &[PathBuf], output: &PathBuf) -> Result<()> {
-    for input in inputs {
+fn check_streaming(streaming: bool, description: &str, fuzzy_merge_level: u32) -> Result<()> {
+    ensure!(
+        !streaming || fuzzy_merge_level == 0,
+        "{description} cannot be merged fuzzily.",
+    );
+    Ok(())
+}
+
+fn check_run_size(run_size: Option<usize>) -> Result<()> {
+    ensure!(run_size != Some(0), "The number of records to sort in memory must be positive.");
+    Ok(())
+}
+
+fn check_inputs(inputs: &[PathBuf], output: &PathBuf, report_file: Option<&PathBuf>) -> Result<()> {
+    let is_standard_stream = |path: &PathBuf| path.as_os_str() == STANDARD_STREAM;
+    let are_same_files = |a: &PathBuf, b: Option<&PathBuf>| b.is_some_and(
+        |b| !is_standard_stream(a) && !is_standard_stream(b) && is_same_file(a, b)
+    );
+    ensure!(
+        inputs.iter().filter(|input| is_standard_stream(input)).count() <= 1,
+        "Stdin can only be used as one of the inputs.",
+    );
+    for input in inputs.iter().filter(|input| !is_standard_stream(input)) {
         ensure!(input.exists(), "{} does not exist.", input.display());
         ensure!(input.is_file(), "{} is not a file.", input.display());
         ensure!(
-            input != output,
+            !are_same_files(input, Some(output)),
             "{} is used both as an input and as the output.",
             input.display(),
         );
+        ensure!(
+            !are_same_files(input, report_file),
+            "{} is used both as an input and as the report file.",
+            input.display(),
+        );
+    }
+    ensure!(
+        !are_same_files(output, report_file),
+        "{} is used both as the output and as the report file.",
+        output.display(),
+    );
+    Ok(())
+}
+
+fn convert_join(join: &str, input_count: usize) -> Result<Join> {
+    Ok(match join {
+        "inner" => Join::Inner,
+        "outer" => Join::Outer,
+        "left" => Join::Input(0),
+        "right" => Join::Input(input_count - 1),
+        "anti" => Join::Anti,
+        _ => Join::Input(join.parse::<usize>().ok()
+            .filter(|index| (1..=input_count).contains(index))
+            .with_context(|| format!("'{join}' is neither a join type nor a valid input index \
+                                      (total inputs: {input_count})."))? - 1),
+    })
+}
+
+fn convert_provenance(provenance: Option<String>) -> Result<Vec<ProvenanceColumn>> {
+    let mut result = vec![];
+    for name in provenance.iter().flat_map(|provenance| provenance.split(',')) {
+        let column = match name {
+            "sources" => ProvenanceColumn::Sources,
+            "rows" => ProvenanceColumn::Rows,
+            "match" => ProvenanceColumn::Match,
+            _ => bail!("'{name}' is not a known provenance column."),
+        };
+        ensure!(
+            !result.contains(&column),
+            "Provenance column '{name}' is specified more than once.",
+        );
+        result.push(column);
+    }
+    Ok(result)
+}
+
+fn convert_output_format(format: &str) -> Result<OutputFormat> {
+    Ok(match format {
+        "delimited" => OutputFormat::Delimited,
+        "xlsx" => OutputFormat::Xlsx,
+        "json" => OutputFormat::Json,
+        "ndjson" => OutputFormat::JsonLines,
+        _ => bail!("'{format}' is not a known output format."),
+    })
+}
+
+fn convert_report(report: &str) -> Result<ReportFormat> {
+    Ok(match report {
+        "text" => ReportFormat::Text,
+        "json" => ReportFormat::Json,
+        _ => bail!("'{report}' is not a known report format."),
+    })
+}
+
+fn convert_coalesce_policy(policy: &str, input_count: usize) -> Result<CoalescePolicy> {
+    Ok(match policy {
+        "first" => CoalescePolicy::FirstNonEmpty,
+        "last" => CoalescePolicy::LastNonEmpty,
+        "fail" => CoalescePolicy::Fail,
+        _ => CoalescePolicy::Input(policy.parse::<usize>().ok()
+            .filter(|index| (1..=input_count).contains(index))
+            .with_context(|| format!("'{policy}' is neither a coalesce policy nor a valid \
+                                      input index (total inputs: {input_count})."))? - 1),
+    })
+}
+
+fn convert_aggregator(value: &str) -> Result<Aggregator> {
+    let (name, separator) = match value.split_once(':') {
+        Some((name, separator)) => (name, Some(separator)),
+        None => (value, None),
+    };
+    let joined = |aggregator: fn(String) -> Aggregator| {
+        aggregator(String::from(separator.unwrap_or(DEFAULT_AGGREGATE_SEPARATOR)))
+    };
+    let aggregator = match name {
+        "sum" => Aggregator::Sum,
+        "min" => Aggregator::Min,
+        "max" => Aggregator::Max,
+        "count" => Aggregator::Count,
+        "first" => Aggregator::First,
+        "last" => Aggregator::Last,
+        "concat" => return Ok(joined(Aggregator::Concat)),
+        "distinct-join" => return Ok(joined(Aggregator::DistinctJoin)),
+        _ => bail!("'{name}' is not a known aggregator."),
+    };
+    ensure!(separator.is_none(), "Aggregator '{name}' does not take a separator.");
+    Ok(aggregator)
+}
+
+fn convert_aggregation(values: Vec<String>, has_headers: bool) -> Result<Option<Aggregation>> {
+    if values.is_empty() {
+        return Ok(None);
     }
+    let mut default = None;
+    let mut columns: Vec<(Column, Aggregator)> = vec![];
+    for value in values {
+        let aggregator = convert_aggregator(&value);
+        if let (Err(_), Some((name, aggregator))) = (&aggregator, value.split_once('=')) {
+            let column = convert_column(String::from(name), has_headers)?;
+            ensure!(
+                columns.iter().all(|(another_column, _)| *another_column != column),
+                "The aggregator of column {name} is specified more than once.",
+            );
+            columns.push((column, convert_aggregator(aggregator)?));
+        } else {
+            ensure!(default.is_none(), "The default aggregator is specified more than once.");
+            default = Some(aggregator?);
+        }
+    }
+    Ok(Some(Aggregation {
+        default: default.unwrap_or(Aggregator::First),
+        columns,
+    }))
+}
+
+fn convert_multi_merge(multi: Option<String>) -> Result<Option<MultiMerge>> {
+    multi.map(|mode| Ok(match mode.as_str() {
+        "zip" => MultiMerge::Zip,
+        "cartesian" => MultiMerge::Cartesian,
+        _ => bail!("'{mode}' is not a known multiway merge mode."),
+    })).transpose()
+}
+
+fn check_max_combinations(max_combinations: usize) -> Result<()> {
+    ensure!(max_combinations > 0, "The maximum number of combinations must be positive.");
     Ok(())
 }
 
+fn convert_ragged(ragged: &str) -> Result<RaggedPolicy> {
+    let mut policy = RaggedPolicy::default();
+    if ragged != "error" {
+        for name in ragged.split(',') {
+            let flag = match name {
+                "pad" => &mut policy.pad,
+                "truncate" => &mut policy.truncate,
+                "skip" => &mut policy.skip,
+                _ => bail!("'{name}' is not a known way to handle ragged records."),
+            };
+            ensure!(!*flag, "'{name}' is specified more than once.");
+            *flag = true;
+        }
+    }
+    Ok(policy)
+}
+
 fn convert_filler(filler: Option<String>) -> String {
     filler.unwrap_or(String::from(""))
 }
@@ -108,23 +678,76 @@ fn convert_filler(filler: Option<String>) -> String {
 pub fn get_params() -> Result<Params> {
     let cli: Cli = Cli::parse();
     let app = Cli::into_app();
-    let delimiter = check_convert_delimiter(cli.delimiter)?;
-    check_similarity_warn_level(cli.warn_similar, &cli.shared)?;
-    check_inputs(&cli.inputs, &cli.output)?;
+    let delimiter = check_convert_input_delimiter(cli.delimiter)?;
+    let output_delimiter = cli.output_delimiter.map(check_delimiter).transpose()?;
+    let encodings = convert_encodings(cli.encoding, cli.inputs.len())?;
+    let (output_encoding, output_bom) = convert_encoding(&cli.output_encoding)?;
+    let worksheets = convert_worksheets(cli.sheet, cli.inputs.len())?;
+    let output_format = cli.output_format.as_deref().map(convert_output_format).transpose()?;
+    let shared_columns = convert_key_columns(
+        cli.key,
+        convert_shared_columns(cli.shared, cli.headers)?,
+        cli.inputs.len(),
+        cli.headers,
+    )?;
+    let key_normalizers = convert_normalizers(cli.normalize, shared_columns[0].len())?;
+    let coalesced_columns = convert_shared_columns(cli.coalesce, cli.headers)?;
+    let coalesce_policy = convert_coalesce_policy(&cli.coalesce_policy, cli.inputs.len())?;
+    let aggregation = convert_aggregation(cli.aggregate, cli.headers)?;
+    check_edit_distance_level(cli.fuzzy_merge, &shared_columns, "fuzzy merge")?;
+    check_edit_distance_level(cli.warn_similar, &shared_columns, "similarity warn")?;
+    check_streaming(cli.sorted, "Sorted inputs", cli.fuzzy_merge)?;
+    check_streaming(cli.external_sort.is_some(), "Externally sorted inputs", cli.fuzzy_merge)?;
+    check_run_size(cli.external_sort)?;
+    check_max_combinations(cli.max_combinations)?;
+    check_inputs(&cli.inputs, &cli.output, cli.report_file.as_ref())?;
+    let join = convert_join(&cli.join, cli.inputs.len())?;
+    let multi_merge = convert_multi_merge(cli.multi)?;
     let filler = convert_filler(cli.filler);
+    let ragged = convert_ragged(&cli.ragged)?;
+    let report = convert_report(&cli.report)?;
+    let provenance = convert_provenance(cli.provenance)?;
     Ok(Params {
         inputs: cli.inputs,
         output: cli.output,
         delimiter,
-        shared_columns: cli.shared,
-        allow_single_column: cli.single,
-        allow_multi_merge: cli.multi,
-        filler,
-        similarity_warn_level: cli.warn_similar,
-        warn_unmatched: cli.warn_unmatched,
-        names: ParamNames {
-            allow_single_column: argument_name!(app, cli.single).unwrap(),
-            allow_multi_merge: argument_name!(app, cli.multi).unwrap(),
-        }
+        output_delimiter,
+        encodings,
+        output_encoding,
+        output_bom,
+        worksheets,
+        output_format,
+        highlight_filler: cli.highlight_filler,
+        no_clobber: cli.no_clobber,
+        sorted: cli.sorted,
+        external_sort: cli.external_sort,
+        report,
+        report_file: cli.report_file,
+        unmatched_dir: cli.unmatched_dir,
+        merge: MergeParams {
+            has_headers: cli.headers,
+            shared_columns,
+            key_normalizers,
+            coalesced_columns,
+            coalesce_policy,
+            aggregation,
+            allow_single_column: cli.single,
+            multi_merge,
+            max_combinations: cli.max_combinations,
+            join,
+            filler,
+            ragged,
+            fuzzy_merge_level: cli.fuzzy_merge,
+            similarity_warn_level: cli.warn_similar,
+            warn_unmatched: cli.warn_unmatched,
+            provenance,
+            names: ParamNames {
+                allow_single_column: argument_name!(app, cli.single).unwrap(),
+                allow_multi_merge: argument_name!(app, cli.multi).unwrap(),
+                max_combinations: argument_name!(app, cli.max_combinations).unwrap(),
+                encoding: argument_name!(app, cli.encoding).unwrap(),
+                has_headers: argument_name!(app, cli.headers).unwrap(),
+            },
+        },
     })
 }
@@ -0,0 +1,35 @@
+use std::fs::canonicalize;
+use std::path::{Path, PathBuf};
+
+/// Returns the absolute path of a file that may not exist yet (as long as its directory does),
+/// with all symbolic links resolved.
+pub fn canonical_path(path: &Path) -> Option<PathBuf> {
+    canonicalize(path).ok().or_else(|| {
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        Some(canonicalize(parent).ok()?.join(path.file_name()?))
+    })
+}
+
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::fs::metadata;
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Checks whether two paths refer to the same file: by device and inode numbers if both
+/// files exist (on Unix), or by their canonical paths otherwise.
+pub fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (file_id(a), file_id(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => canonical_path(a).is_some_and(|a| canonical_path(b) == Some(a)),
+    }
+}
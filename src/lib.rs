@@ -0,0 +1,57 @@
+//! Consolidation of CSV-like tables that share some of their columns.
+//!
+//! Besides the `solidify` command-line tool, the merging logic can be embedded through
+//! [`Solidifier`], which takes tables in memory (or any `io::Read` sources) and returns
+//! the merged rows along with the diagnostics collected while merging:
+//!
+//! ```
+//! use solidify::{Join, Solidifier};
+//!
+//! let table = |rows: &[&[&str]]| rows.iter().map(
+//!     |row| row.iter().map(|value| value.to_string()).collect()
+//! ).collect();
+//! let merged = Solidifier::new()
+//!     .shared([1])
+//!     .filler("N/A")
+//!     .join(Join::Outer)
+//!     .merge(vec![
+//!         table(&[&["1", "a"], &["2", "b"]]),
+//!         table(&[&["2", "B"], &["3", "C"]]),
+//!     ])?;
+//! assert_eq!(merged.rows, vec![
+//!     vec!["1", "a", "N/A"],
+//!     vec!["2", "b", "B"],
+//!     vec!["3", "N/A", "C"],
+//! ]);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+mod cli;
+mod files;
+mod params;
+mod solidifier;
+mod strings;
+mod warnings;
+
+use anyhow::Result;
+
+pub use params::{
+    Aggregation,
+    Aggregator,
+    CoalescePolicy,
+    Column,
+    Encoding,
+    Join,
+    MultiMerge,
+    Normalizer,
+    ProvenanceColumn,
+    RaggedPolicy,
+};
+pub use solidifier::builder::{Merged, Solidifier, Table};
+pub use solidifier::diagnostics::{Diagnostic, DiagnosticKind, DiagnosticSink};
+pub use solidifier::{KeyValue, RecordId};
+
+/// Runs the `solidify` command-line tool with the arguments of the current process.
+pub fn run_cli() -> Result<()> {
+    solidifier::solidify(&cli::get_params()?)
+}
@@ -1,19 +1,206 @@
 use std::path::PathBuf;
 
+/// Path standing for stdin when used as an input, and for stdout when used as the output.
+pub const STANDARD_STREAM: &str = "-";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Column {
+    Index(i32),
+    Name(String),
+}
+
+impl From<i32> for Column {
+    fn from(index: i32) -> Self {
+        Column::Index(index)
+    }
+}
+
+impl From<&str> for Column {
+    fn from(name: &str) -> Self {
+        Column::Name(String::from(name))
+    }
+}
+
+impl From<String> for Column {
+    fn from(name: String) -> Self {
+        Column::Name(name)
+    }
+}
+
+/// Worksheet of a spreadsheet input: its 1-based index or its name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Worksheet {
+    Index(usize),
+    Name(String),
+}
+
+pub enum Delimiter {
+    Auto,
+    Fixed(String),
+}
+
+/// Character encoding of an input or of the output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1.
+    Latin1,
+    Windows1252,
+}
+
+/// Format of the output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    Delimited,
+    Xlsx,
+    /// A JSON array of objects.
+    Json,
+    /// A JSON object per line.
+    JsonLines,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Normalizer {
+    Trim,
+    CollapseWhitespace,
+    FoldCase,
+    Nfc,
+    Nfkc,
+    StripDiacritics,
+    RemovePunctuation,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CoalescePolicy {
+    FirstNonEmpty,
+    LastNonEmpty,
+    Input(usize),
+    Fail,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Aggregator {
+    Sum,
+    Min,
+    Max,
+    /// Number of non-empty values.
+    Count,
+    /// Non-empty values joined with the given separator.
+    Concat(String),
+    First,
+    Last,
+    /// Distinct non-empty values (in the order of their first occurrence) joined with
+    /// the given separator.
+    DistinctJoin(String),
+}
+
+/// Aggregators applied to the non-shared columns of records with the same key
+/// within each input.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Aggregation {
+    /// Aggregator of the columns not listed in `columns`.
+    pub default: Aggregator,
+    pub columns: Vec<(Column, Aggregator)>,
+}
+
+/// Default limit on the number of output records per key in `MultiMerge::Cartesian` mode.
+pub const DEFAULT_MAX_COMBINATIONS: usize = 10_000;
+
+/// How records with the same key are matched when some inputs have several of them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MultiMerge {
+    /// Records are matched in the order they appear in the inputs.
+    Zip,
+    /// Every combination of the records of different inputs is output.
+    Cartesian,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Join {
+    Inner,
+    Outer,
+    Input(usize),
+    Anti,
+}
+
+/// What to do with records whose number of columns differs from that of the first record
+/// of their input (an error by default).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RaggedPolicy {
+    /// Pad records with fewer columns with the filler.
+    pub pad: bool,
+    /// Drop the extra values of records with more columns.
+    pub truncate: bool,
+    /// Skip (and report) the records that are neither padded nor truncated.
+    pub skip: bool,
+}
+
+#[derive(Clone, Copy)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProvenanceColumn {
+    Sources,
+    Rows,
+    Match,
+}
+
+/// Options as the user sets them (command-line flags or builder methods), which error
+/// messages refer to.
 pub struct ParamNames {
     pub allow_single_column: String,
     pub allow_multi_merge: String,
+    pub max_combinations: String,
+    pub encoding: String,
+    pub has_headers: String,
 }
 
-pub struct Params {
-    pub inputs: Vec<PathBuf>,
-    pub output: PathBuf,
-    pub delimiter: u8,
-    pub shared_columns: Vec<i32>,
+pub struct MergeParams {
+    pub has_headers: bool,
+    pub shared_columns: Vec<Vec<Column>>,
+    pub key_normalizers: Vec<Vec<Normalizer>>,
+    pub coalesced_columns: Vec<Column>,
+    pub coalesce_policy: CoalescePolicy,
+    pub aggregation: Option<Aggregation>,
     pub allow_single_column: bool,
-    pub allow_multi_merge: bool,
+    pub multi_merge: Option<MultiMerge>,
+    pub max_combinations: usize,
+    pub join: Join,
     pub filler: String,
+    pub ragged: RaggedPolicy,
+    pub fuzzy_merge_level: u32,
     pub similarity_warn_level: u32,
     pub warn_unmatched: bool,
+    pub provenance: Vec<ProvenanceColumn>,
     pub names: ParamNames,
 }
+
+pub struct Params {
+    pub inputs: Vec<PathBuf>,
+    pub output: PathBuf,
+    pub delimiter: Delimiter,
+    pub output_delimiter: Option<String>,
+    /// Encoding of each input, used unless the input starts with a byte order mark.
+    pub encodings: Vec<Encoding>,
+    pub output_encoding: Encoding,
+    /// Start the output with a byte order mark.
+    pub output_bom: bool,
+    /// Worksheet to read from each input that is a spreadsheet.
+    pub worksheets: Vec<Worksheet>,
+    /// Format of the output, if not told by the extension of its path.
+    pub output_format: Option<OutputFormat>,
+    /// Highlight the missing values of merged rows in an xlsx output.
+    pub highlight_filler: bool,
+    pub no_clobber: bool,
+    pub sorted: bool,
+    pub external_sort: Option<usize>,
+    pub report: ReportFormat,
+    pub report_file: Option<PathBuf>,
+    pub unmatched_dir: Option<PathBuf>,
+    pub merge: MergeParams,
+}
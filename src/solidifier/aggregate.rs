@@ -0,0 +1,332 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use anyhow::{bail, ensure, Result};
+use num::bigint::{BigInt, Sign};
+
+use crate::params::{Aggregation, Aggregator, MergeParams};
+
+use super::coalesce::describe_column;
+use super::diagnostics::{Diagnostic, DiagnosticKind, DiagnosticSink};
+use super::keys::{Key, RecordId};
+use super::sheet::{Sheet, SheetRow, SheetRowSection};
+
+/// A record to be merged: either a record of an input as it is, or the aggregate
+/// of all the records of an input with the same key.
+pub enum InputRecord<'a, 'b> {
+    Single(&'b SheetRow<'a>),
+    Aggregate {
+        ids: Vec<RecordId>,
+        values: Vec<Cow<'a, str>>,
+    },
+}
+
+impl<'a, 'b> InputRecord<'a, 'b> {
+    /// Returns the records this one consists of.
+    pub fn ids(&self) -> Vec<RecordId> {
+        match self {
+            InputRecord::Single(row) => vec![row.id()],
+            InputRecord::Aggregate { ids, .. } => ids.clone(),
+        }
+    }
+
+    pub fn value(&self, column: usize) -> Cow<'a, str> {
+        match self {
+            InputRecord::Single(row) => Cow::Borrowed(row.data()[column].as_str()),
+            InputRecord::Aggregate { values, .. } => values[column].clone(),
+        }
+    }
+
+    pub fn split_by_key(&self, sheet: &Sheet) -> Vec<SheetRowSection<Cow<'a, str>>> {
+        match self {
+            InputRecord::Single(row) => row.split_by_key().into_iter().map(
+                |section| section.map(Cow::Borrowed)
+            ).collect(),
+            InputRecord::Aggregate { values, .. } => sheet.split_values_by_key(values),
+        }
+    }
+}
+
+/// Magnitude of the exponent beyond which a number is not held exactly.
+const MAX_EXPONENT: i64 = 100;
+
+enum Number {
+    /// A number in decimal notation, held exactly as an integer scaled down by a power of ten
+    /// (e.g. 1.50 is 150 with a scale of 2).
+    Decimal {
+        scaled: BigInt,
+        scale: usize,
+    },
+    Real(f64),
+}
+
+impl Number {
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        Self::parse_decimal(value).or_else(
+            || value.parse().ok().filter(|real: &f64| real.is_finite()).map(Number::Real)
+        )
+    }
+
+    /// Parses a number such as `-12`, `1.50`, `.5` or `2.5e-3`.
+    fn parse_decimal(value: &str) -> Option<Self> {
+        let (mantissa, exponent) = match value.find(['e', 'E']) {
+            Some(index) => (&value[..index], value[index + 1..].parse::<i64>().ok()?),
+            None => (value, 0),
+        };
+        let unsigned = mantissa.strip_prefix(['+', '-']).unwrap_or(mantissa);
+        let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+        let is_valid = !(integer.is_empty() && fraction.is_empty())
+            && integer.bytes().chain(fraction.bytes()).all(|byte| byte.is_ascii_digit())
+            && exponent.abs() <= MAX_EXPONENT;
+        if !is_valid {
+            return None;
+        }
+        let mut scaled: BigInt = format!("{integer}{fraction}").parse().ok()?;
+        if mantissa.starts_with('-') {
+            scaled = -scaled;
+        }
+        let scale = fraction.len() as i64 - exponent;
+        if scale < 0 {
+            scaled *= BigInt::from(10).pow(scale.unsigned_abs() as u32);
+        }
+        Some(Number::Decimal {
+            scaled,
+            scale: scale.max(0) as usize,
+        })
+    }
+
+    /// Returns the number scaled up by the given power of ten (not less than its own scale).
+    fn rescaled(scaled: &BigInt, scale: usize, new_scale: usize) -> BigInt {
+        scaled * BigInt::from(10).pow((new_scale - scale) as u32)
+    }
+
+    fn as_f64(&self) -> f64 {
+        match self {
+            Number::Decimal { scaled, scale } => {
+                format_decimal(scaled, *scale).parse().unwrap_or(f64::NAN)
+            },
+            Number::Real(real) => *real,
+        }
+    }
+
+    /// Compares the numbers exactly if both are decimal.
+    fn compare(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (
+                Number::Decimal { scaled, scale },
+                Number::Decimal { scaled: other_scaled, scale: other_scale },
+            ) => {
+                let common_scale = *scale.max(other_scale);
+                Self::rescaled(scaled, *scale, common_scale)
+                    .cmp(&Self::rescaled(other_scaled, *other_scale, common_scale))
+            },
+            _ => self.as_f64().total_cmp(&other.as_f64()),
+        }
+    }
+}
+
+/// Formats a decimal number with exactly `scale` digits after the decimal point.
+fn format_decimal(scaled: &BigInt, scale: usize) -> String {
+    let digits = scaled.magnitude().to_string();
+    let digits = format!("{digits:0>width$}", width = scale + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - scale);
+    let sign = if scaled.sign() == Sign::Minus { "-" } else { "" };
+    if fraction.is_empty() {
+        format!("{sign}{integer}")
+    } else {
+        format!("{sign}{integer}.{fraction}")
+    }
+}
+
+fn aggregator_name(aggregator: &Aggregator) -> &'static str {
+    match aggregator {
+        Aggregator::Sum => "sum",
+        Aggregator::Min => "min",
+        Aggregator::Max => "max",
+        Aggregator::Count => "count",
+        Aggregator::Concat(_) => "concat",
+        Aggregator::First => "first",
+        Aggregator::Last => "last",
+        Aggregator::DistinctJoin(_) => "distinct-join",
+    }
+}
+
+fn parse_numbers(
+    values: &[(RecordId, &str)],
+    aggregator: &Aggregator,
+    column: usize,
+    key: &Key,
+    sink: &mut dyn DiagnosticSink,
+) -> Result<Vec<Number>> {
+    let mut numbers = vec![];
+    for (id, value) in values {
+        if let Some(number) = Number::parse(value) {
+            numbers.push(number);
+        } else {
+            let message = format!(
+                "Non-numeric value '{value}' encountered in column {n} of {id}, which is \
+                 aggregated with {name}",
+                n = column + 1,
+                name = aggregator_name(aggregator),
+            );
+            sink.emit(Diagnostic {
+                kind: DiagnosticKind::NonNumeric,
+                message: format!("{message}:"),
+                records: vec![*id],
+                keys: vec![key.values()],
+                distance: None,
+            })?;
+            bail!("{message}. The record is:\n{key}");
+        }
+    }
+    Ok(numbers)
+}
+
+/// Sums the numbers exactly if all of them are decimal, keeping as many decimal places
+/// as the most precise of them has (e.g. 0.1 + 0.20 is 0.30).
+fn sum(numbers: &[Number]) -> String {
+    let decimals: Option<Vec<_>> = numbers.iter().map(|number| match number {
+        Number::Decimal { scaled, scale } => Some((scaled, *scale)),
+        Number::Real(_) => None,
+    }).collect();
+    if let Some(decimals) = decimals {
+        let max_scale = decimals.iter().map(|(_, scale)| *scale).max().unwrap_or(0);
+        let total = decimals.into_iter().map(
+            |(scaled, scale)| Number::rescaled(scaled, scale, max_scale)
+        ).sum();
+        format_decimal(&total, max_scale)
+    } else {
+        numbers.iter().map(Number::as_f64).sum::<f64>().to_string()
+    }
+}
+
+fn join_values(values: &[&str], separator: &str, distinct: bool) -> String {
+    let mut seen = HashSet::new();
+    values.iter().filter(|value| !distinct || seen.insert(**value)).copied()
+        .collect::<Vec<_>>().join(separator)
+}
+
+fn aggregate_values<'a>(
+    aggregator: &Aggregator,
+    values: &[(RecordId, &'a str)],
+    column: usize,
+    key: &Key,
+    sink: &mut dyn DiagnosticSink,
+) -> Result<Cow<'a, str>> {
+    let non_empty: Vec<_> = values.iter().filter(|(_, value)| !value.is_empty()).copied()
+        .collect();
+    let non_empty_values: Vec<_> = non_empty.iter().map(|(_, value)| *value).collect();
+    Ok(match aggregator {
+        Aggregator::First => Cow::Borrowed(values[0].1),
+        Aggregator::Last => Cow::Borrowed(values[values.len() - 1].1),
+        Aggregator::Count => Cow::Owned(non_empty.len().to_string()),
+        Aggregator::Concat(separator) => {
+            Cow::Owned(join_values(&non_empty_values, separator, false))
+        },
+        Aggregator::DistinctJoin(separator) => {
+            Cow::Owned(join_values(&non_empty_values, separator, true))
+        },
+        Aggregator::Sum | Aggregator::Min | Aggregator::Max => {
+            let numbers = parse_numbers(&non_empty, aggregator, column, key, sink)?;
+            if numbers.is_empty() {
+                Cow::Borrowed("")
+            } else if let Aggregator::Sum = aggregator {
+                Cow::Owned(sum(&numbers))
+            } else {
+                let mut chosen = 0;
+                for (index, number) in numbers.iter().enumerate() {
+                    let is_better = if let Aggregator::Min = aggregator {
+                        number.compare(&numbers[chosen]) == Ordering::Less
+                    } else {
+                        number.compare(&numbers[chosen]) == Ordering::Greater
+                    };
+                    if is_better {
+                        chosen = index;
+                    }
+                }
+                Cow::Borrowed(non_empty_values[chosen])
+            }
+        },
+    })
+}
+
+/// Collapses the (non-empty set of) records of an input with the same key into
+/// a single record. Shared columns keep the values of the first record.
+pub fn aggregate<'a, 'b>(
+    rows: &[&'b SheetRow<'a>],
+    sheet: &Sheet,
+    aggregation: &Aggregation,
+    key: &Key,
+    sink: &mut dyn DiagnosticSink,
+) -> Result<InputRecord<'a, 'b>> {
+    let mut values = vec![];
+    for column in 0..sheet.column_count() {
+        let column_values: Vec<_> = rows.iter().map(
+            |row| (row.id(), row.data()[column].as_str())
+        ).collect();
+        values.push(if sheet.is_key_column(column) {
+            Cow::Borrowed(column_values[0].1)
+        } else {
+            let aggregator = sheet.aggregated_columns().iter().position(
+                |aggregated_column| *aggregated_column == Some(column)
+            ).map_or(&aggregation.default, |index| &aggregation.columns[index].1);
+            aggregate_values(aggregator, &column_values, column, key, sink)?
+        });
+    }
+    Ok(InputRecord::Aggregate {
+        ids: rows.iter().map(|row| row.id()).collect(),
+        values,
+    })
+}
+
+pub fn ensure_aggregated_columns_exist(sheets: &[Sheet], params: &MergeParams) -> Result<()> {
+    for (index, (column, _)) in params.aggregation.iter().flat_map(
+        |aggregation| aggregation.columns.iter()
+    ).enumerate() {
+        ensure!(
+            sheets.iter().any(|sheet| sheet.aggregated_columns()[index].is_some()),
+            "None of the inputs has aggregated column {}.",
+            describe_column(column),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sum_of(values: &[&str]) -> String {
+        sum(&values.iter().map(|value| Number::parse(value).unwrap()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn decimal_sums_are_exact() {
+        assert_eq!(sum_of(&["0.1", "0.2"]), "0.3");
+        assert_eq!(sum_of(&["0.1", "0.20"]), "0.30");
+        assert_eq!(sum_of(&["1.50", "2", " -0.5 "]), "3.00");
+        assert_eq!(sum_of(&["-0.25", "0.1"]), "-0.15");
+        assert_eq!(sum_of(&[".5", "+1.", "2e2", "2.5e-3"]), "201.5025");
+        assert_eq!(sum_of(&["-1.5", "1.5"]), "0.0");
+        assert_eq!(
+            sum_of(&["170141183460469231731687303715884105727", "1"]),
+            "170141183460469231731687303715884105728",
+        );
+        assert_eq!(sum_of(&["1e100", "-1e100", "1"]), "1");
+        // Numbers with larger exponents are summed as floating-point numbers.
+        assert_eq!(sum_of(&["1e101", "1"]), (1e101 + 1.0).to_string());
+        assert!(Number::parse("inf").is_none());
+    }
+
+    #[test]
+    fn decimals_are_compared_exactly() {
+        let numbers = ["12345678901234567890.1", "12345678901234567890.09"].map(
+            |value| Number::parse(value).unwrap()
+        );
+        assert_eq!(numbers[0].compare(&numbers[1]), Ordering::Greater);
+        let one = Number::parse("1").unwrap();
+        assert_eq!(Number::parse("1.0").unwrap().compare(&one), Ordering::Equal);
+    }
+}
@@ -0,0 +1,458 @@
+use std::borrow::Cow;
+use std::io::{BufReader, Read};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::params::{
+    Aggregation,
+    Aggregator,
+    CoalescePolicy,
+    Column,
+    Encoding,
+    Join,
+    MergeParams,
+    MultiMerge,
+    Normalizer,
+    ParamNames,
+    ProvenanceColumn,
+    RaggedPolicy,
+    DEFAULT_MAX_COMBINATIONS,
+};
+use crate::strings::countable::Countable;
+
+use super::aggregate::ensure_aggregated_columns_exist;
+use super::coalesce::ensure_coalesced_columns_exist;
+use super::delimited::records;
+use super::diagnostics::Diagnostic;
+use super::encodings::Decoder;
+use super::sheet::Sheet;
+use super::{ensure_consistent_key_order, ensure_proper_delimiter, match_and_merge, merge_header};
+
+/// Records of an input, each consisting of the values of its columns.
+pub type Table = Vec<Vec<String>>;
+
+/// Result of merging several tables.
+#[derive(Clone, Debug)]
+pub struct Merged {
+    /// The merged header row, if the tables have headers.
+    pub header: Option<Vec<String>>,
+    pub rows: Vec<Vec<String>>,
+    /// Data records of each input that have no counterpart in some other input.
+    pub unmatched: Vec<Table>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Builder-style configuration of a merge, mirroring the options of the command-line tool.
+/// Inputs are referred to by their 0-based indices.
+#[derive(Clone, Debug)]
+pub struct Solidifier {
+    delimiter: String,
+    encoding: Encoding,
+    has_headers: bool,
+    shared_columns: Vec<Column>,
+    input_shared_columns: Vec<(usize, Vec<Column>)>,
+    normalizers: Vec<Normalizer>,
+    column_normalizers: Vec<(usize, Vec<Normalizer>)>,
+    coalesced_columns: Vec<Column>,
+    coalesce_policy: CoalescePolicy,
+    aggregation: Option<Aggregation>,
+    allow_single_column: bool,
+    multi_merge: Option<MultiMerge>,
+    max_combinations: usize,
+    join: Join,
+    filler: String,
+    ragged: RaggedPolicy,
+    fuzzy_merge_level: u32,
+    similarity_warn_level: u32,
+    warn_unmatched: bool,
+    provenance: Vec<ProvenanceColumn>,
+}
+
+fn names() -> ParamNames {
+    ParamNames {
+        allow_single_column: String::from("allow_single_column(true)"),
+        allow_multi_merge: String::from("allow_multi_merge(true)"),
+        max_combinations: String::from("max_combinations()"),
+        encoding: String::from("encoding()"),
+        has_headers: String::from("headers(true)"),
+    }
+}
+
+impl Default for Solidifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Solidifier {
+    pub fn new() -> Self {
+        Self {
+            delimiter: String::from("\t"),
+            encoding: Encoding::Utf8,
+            has_headers: false,
+            shared_columns: vec![],
+            input_shared_columns: vec![],
+            normalizers: vec![],
+            column_normalizers: vec![],
+            coalesced_columns: vec![],
+            coalesce_policy: CoalescePolicy::FirstNonEmpty,
+            aggregation: None,
+            allow_single_column: false,
+            multi_merge: None,
+            max_combinations: DEFAULT_MAX_COMBINATIONS,
+            join: Join::Outer,
+            filler: String::new(),
+            ragged: RaggedPolicy::default(),
+            fuzzy_merge_level: 0,
+            similarity_warn_level: 0,
+            warn_unmatched: false,
+            provenance: vec![],
+        }
+    }
+
+    /// Delimiter of the inputs passed to `merge_readers` (a tab by default).
+    pub fn delimiter(mut self, delimiter: impl Into<String>) -> Self {
+        self.delimiter = delimiter.into();
+        self
+    }
+
+    /// Encoding of the inputs passed to `merge_readers` (UTF-8 by default), used unless
+    /// an input starts with a byte order mark.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Treat the first record of each input as a header row.
+    pub fn headers(mut self, has_headers: bool) -> Self {
+        self.has_headers = has_headers;
+        self
+    }
+
+    /// Columns that the records are identified by: 1-based indices (negative ones
+    /// counting from the right, 0 standing for a column unique for each record) or,
+    /// when the inputs have headers, column names.
+    pub fn shared<C: Into<Column>>(mut self, columns: impl IntoIterator<Item = C>) -> Self {
+        self.shared_columns = columns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Shared columns of a particular input, overriding `shared` for that input.
+    pub fn input_shared<C: Into<Column>>(
+        mut self,
+        input: usize,
+        columns: impl IntoIterator<Item = C>,
+    ) -> Self {
+        self.input_shared_columns.retain(|(index, _)| *index != input);
+        self.input_shared_columns.push((input, columns.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    /// Normalizations applied (in the given order) to values of all the shared columns
+    /// before records are matched.
+    pub fn normalize(mut self, normalizers: impl IntoIterator<Item = Normalizer>) -> Self {
+        self.normalizers = normalizers.into_iter().collect();
+        self
+    }
+
+    /// Normalizations applied to values of the shared column with the given 0-based
+    /// index (among the shared columns), overriding `normalize` for that column.
+    pub fn normalize_shared(
+        mut self,
+        column: usize,
+        normalizers: impl IntoIterator<Item = Normalizer>,
+    ) -> Self {
+        self.column_normalizers.retain(|(index, _)| *index != column);
+        self.column_normalizers.push((column, normalizers.into_iter().collect()));
+        self
+    }
+
+    /// Non-shared columns present in several inputs that should be output as a single
+    /// column (at the position the column has in the first input that has it), referred
+    /// to as in `shared`; inputs lacking a column referred to by name are skipped.
+    pub fn coalesce<C: Into<Column>>(mut self, columns: impl IntoIterator<Item = C>) -> Self {
+        self.coalesced_columns = columns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// How to choose the value of a coalesced column when the merged records have
+    /// different values in it (first non-empty value by default).
+    pub fn coalesce_policy(mut self, policy: CoalescePolicy) -> Self {
+        self.coalesce_policy = policy;
+        self
+    }
+
+    /// Collapse the records with the same key within each input into a single record
+    /// instead of pairing them up across the inputs, aggregating the values of each
+    /// non-shared column with the given aggregator (unless overridden for the column).
+    pub fn aggregate(mut self, default: Aggregator) -> Self {
+        let columns = self.aggregation.take().map_or(vec![], |aggregation| aggregation.columns);
+        self.aggregation = Some(Aggregation { default, columns });
+        self
+    }
+
+    /// Aggregator of a non-shared column, referred to as in `coalesce`. Enables aggregation
+    /// (with `Aggregator::First` for the other columns) unless `aggregate` is called.
+    pub fn aggregate_column(mut self, column: impl Into<Column>, aggregator: Aggregator) -> Self {
+        let column = column.into();
+        let aggregation = self.aggregation.get_or_insert_with(|| Aggregation {
+            default: Aggregator::First,
+            columns: vec![],
+        });
+        aggregation.columns.retain(|(another_column, _)| *another_column != column);
+        aggregation.columns.push((column, aggregator));
+        self
+    }
+
+    pub fn allow_single_column(mut self, allow: bool) -> Self {
+        self.allow_single_column = allow;
+        self
+    }
+
+    /// Allow records with the same key to be matched when some inputs have several
+    /// of them, in the order they appear in the inputs (unless `multi_merge` says otherwise).
+    pub fn allow_multi_merge(mut self, allow: bool) -> Self {
+        self.multi_merge = allow.then(|| self.multi_merge.unwrap_or(MultiMerge::Zip));
+        self
+    }
+
+    /// Allow records with the same key to be matched when some inputs have several
+    /// of them, in the given way.
+    pub fn multi_merge(mut self, mode: MultiMerge) -> Self {
+        self.multi_merge = Some(mode);
+        self
+    }
+
+    /// Maximum number of merged records per key in `MultiMerge::Cartesian` mode;
+    /// exceeding it is an error.
+    pub fn max_combinations(mut self, count: usize) -> Self {
+        self.max_combinations = count;
+        self
+    }
+
+    pub fn join(mut self, join: Join) -> Self {
+        self.join = join;
+        self
+    }
+
+    /// Value of cells missing from the inputs (an empty string by default).
+    pub fn filler(mut self, filler: impl Into<String>) -> Self {
+        self.filler = filler.into();
+        self
+    }
+
+    /// What to do with records whose number of columns differs from that of the first
+    /// record of their input (an error by default).
+    pub fn ragged(mut self, policy: RaggedPolicy) -> Self {
+        self.ragged = policy;
+        self
+    }
+
+    pub fn fuzzy_merge(mut self, level: u32) -> Self {
+        self.fuzzy_merge_level = level;
+        self
+    }
+
+    pub fn warn_similar(mut self, level: u32) -> Self {
+        self.similarity_warn_level = level;
+        self
+    }
+
+    pub fn warn_unmatched(mut self, warn: bool) -> Self {
+        self.warn_unmatched = warn;
+        self
+    }
+
+    /// Columns describing where each merged row comes from, appended to the output.
+    pub fn provenance(mut self, columns: impl IntoIterator<Item = ProvenanceColumn>) -> Self {
+        self.provenance = columns.into_iter().collect();
+        self
+    }
+
+    fn shared_columns(&self, input_count: usize) -> Result<Vec<Vec<Column>>> {
+        let mut result = vec![self.shared_columns.clone(); input_count];
+        for (input, columns) in &self.input_shared_columns {
+            ensure!(
+                *input < input_count,
+                "Shared columns are specified for input #{n}, but there are only {inputs}.",
+                n = input + 1,
+                inputs = input_count.count_with("input"),
+            );
+            result[*input] = columns.clone();
+        }
+        for (index, columns) in result.iter().enumerate() {
+            ensure!(
+                columns.len() == result[0].len(),
+                "Input #1 has {first_columns}, but input #{n} has {nth_columns}.",
+                first_columns = result[0].len().count_with("shared column"),
+                nth_columns = columns.len().count_with("shared column"),
+                n = index + 1,
+            );
+        }
+        Ok(result)
+    }
+
+    fn key_normalizers(&self, shared_count: usize) -> Result<Vec<Vec<Normalizer>>> {
+        ensure!(
+            self.normalizers.is_empty() || shared_count > 0,
+            "Normalization makes no sense when data has no shared columns.",
+        );
+        let mut result = vec![self.normalizers.clone(); shared_count];
+        for (column, normalizers) in &self.column_normalizers {
+            ensure!(
+                *column < shared_count,
+                "Normalizations are specified for shared column #{n}, \
+                 but there are only {columns}.",
+                n = column + 1,
+                columns = shared_count.count_with("shared column"),
+            );
+            result[*column] = normalizers.clone();
+        }
+        Ok(result)
+    }
+
+    pub(super) fn params(&self, input_count: usize) -> Result<MergeParams> {
+        ensure!(input_count > 0, "There are no inputs to merge.");
+        ensure!(self.max_combinations > 0, "The maximum number of combinations must be positive.");
+        let shared_columns = self.shared_columns(input_count)?;
+        let key_normalizers = self.key_normalizers(shared_columns[0].len())?;
+        if self.fuzzy_merge_level > 0 || self.similarity_warn_level > 0 {
+            ensure!(
+                !shared_columns.iter().flatten().any(|column| *column == Column::Index(0)),
+                "Records cannot be compared by edit distance \
+                 when shared columns contain 0 (the unique column).",
+            );
+        }
+        if let CoalescePolicy::Input(index) = self.coalesce_policy {
+            ensure!(
+                index < input_count,
+                "Cannot prefer the values of input #{n}, since there are only {inputs}.",
+                n = index + 1,
+                inputs = input_count.count_with("input"),
+            );
+        }
+        if let Join::Input(index) = self.join {
+            ensure!(
+                index < input_count,
+                "Cannot join by input #{n}, since there are only {inputs}.",
+                n = index + 1,
+                inputs = input_count.count_with("input"),
+            );
+        }
+        Ok(MergeParams {
+            has_headers: self.has_headers,
+            shared_columns,
+            key_normalizers,
+            coalesced_columns: self.coalesced_columns.clone(),
+            coalesce_policy: self.coalesce_policy,
+            aggregation: self.aggregation.clone(),
+            allow_single_column: self.allow_single_column,
+            multi_merge: self.multi_merge,
+            max_combinations: self.max_combinations,
+            join: self.join,
+            filler: self.filler.clone(),
+            ragged: self.ragged,
+            fuzzy_merge_level: self.fuzzy_merge_level,
+            similarity_warn_level: self.similarity_warn_level,
+            warn_unmatched: self.warn_unmatched,
+            provenance: self.provenance.clone(),
+            names: names(),
+        })
+    }
+
+    /// Merges the given tables (including their header rows, if any).
+    pub fn merge(&self, tables: Vec<Table>) -> Result<Merged> {
+        let params = self.params(tables.len())?;
+        let mut diagnostics = vec![];
+        let sheets = tables.into_iter().enumerate().map(|(index, table)| Sheet::new(
+            table,
+            self.delimiter.clone(),
+            &params,
+            index,
+            &mut diagnostics,
+        ).with_context(
+            || format!("Could not process input #{}.", index + 1)
+        )).collect::<Result<Vec<_>>>()?;
+        ensure_proper_delimiter(&sheets, &params)?;
+        ensure_consistent_key_order(&sheets)?;
+        ensure_coalesced_columns_exist(&sheets, &params)?;
+        ensure_aggregated_columns_exist(&sheets, &params)?;
+        let header = params.has_headers.then(
+            || merge_header(&sheets, &params).into_iter().map(Cow::into_owned).collect()
+        );
+        let matched = match_and_merge(&sheets, &params, &mut diagnostics)?;
+        let rows = matched.rows.into_iter().map(|row| row.into_iter().map(
+            |value| value.map_or_else(|| params.filler.clone(), Cow::into_owned)
+        ).collect()).collect();
+        let unmatched = matched.unmatched.into_iter().map(|mut records| {
+            records.sort_by_key(|(index, _)| *index);
+            records.into_iter().map(|(_, record)| record.to_vec()).collect()
+        }).collect();
+        Ok(Merged {
+            header,
+            rows,
+            unmatched,
+            diagnostics,
+        })
+    }
+
+    /// Reads delimited data from the given sources and merges it.
+    pub fn merge_readers<R: Read>(&self, readers: Vec<R>) -> Result<Merged> {
+        ensure!(
+            !self.delimiter.is_empty() && !self.delimiter.contains(['"', '\r', '\n']),
+            "The delimiter must be non-empty and cannot contain quotes or line breaks.",
+        );
+        let mut tables = vec![];
+        for (index, reader) in readers.into_iter().enumerate() {
+            let reader = Decoder::new(BufReader::new(reader), self.encoding, &names().encoding);
+            tables.push(records(BufReader::new(reader), &self.delimiter).enumerate().map(
+                |(record_index, record)| record.with_context(|| format!(
+                    "Could not process record #{} of input #{}.",
+                    record_index + 1,
+                    index + 1,
+                ))
+            ).collect::<Result<Table>>()?);
+        }
+        self.merge(tables)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(rows: &[&[&str]]) -> Table {
+        rows.iter().map(|row| row.iter().map(|value| value.to_string()).collect()).collect()
+    }
+
+    fn error_text(result: Result<Merged>) -> String {
+        format!("{:#}", result.unwrap_err())
+    }
+
+    #[test]
+    fn errors_refer_to_builder_methods() {
+        let single = error_text(Solidifier::new().shared([1]).merge(vec![
+            table(&[&["1"], &["2"]]),
+            table(&[&["1"]]),
+        ]));
+        assert!(single.contains("consider using allow_single_column(true)."), "{single}");
+        let multi = error_text(Solidifier::new().shared([1]).merge(vec![
+            table(&[&["1", "a"], &["1", "b"]]),
+            table(&[&["1", "A"]]),
+        ]));
+        assert!(multi.contains("consider using allow_multi_merge(true)."), "{multi}");
+        let combinations = error_text(
+            Solidifier::new().shared([1]).multi_merge(MultiMerge::Cartesian).max_combinations(1)
+                .merge(vec![table(&[&["1", "a"], &["1", "b"]]), table(&[&["1", "A"]])])
+        );
+        assert!(combinations.contains("(see max_combinations())"), "{combinations}");
+        let encoding = error_text(Solidifier::new().shared([1]).merge_readers(vec![
+            &b"1\ta\n"[..],
+            &b"1\t\xff\n"[..],
+        ]));
+        assert!(encoding.contains("(see encoding())"), "{encoding}");
+        for text in [single, multi, combinations, encoding] {
+            assert!(!text.contains("--"), "{text}");
+        }
+    }
+}
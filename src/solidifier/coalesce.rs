@@ -0,0 +1,128 @@
+use std::borrow::Cow;
+
+use anyhow::{bail, ensure, Result};
+
+use crate::params::{CoalescePolicy, Column, MergeParams};
+
+use super::aggregate::InputRecord;
+use super::diagnostics::{Diagnostic, DiagnosticKind, DiagnosticSink};
+use super::keys::Key;
+use super::merge_sections;
+use super::sheet::Sheet;
+
+/// Role of a column of the merged output, as it would be without coalescing.
+#[derive(Clone, Copy)]
+pub enum OutputColumn {
+    Plain,
+    /// The column holds the coalesced value of the coalesced column with the given index.
+    Coalesced(usize),
+    /// The column is a copy of a coalesced column that is output elsewhere.
+    Dropped,
+}
+
+/// Determines the role of each column of the merged output. A coalesced column is
+/// output in place of its counterpart in the first input that has it.
+pub fn layout(sheets: &[Sheet]) -> Vec<OutputColumn> {
+    let columns = merge_sections(&sheets.iter().enumerate().map(|(sheet_index, sheet)| (
+        sheet.split_column_indices_by_key().into_iter().map(
+            |section| section.map(|column| (sheet_index, column))
+        ).collect(),
+        true,
+    )).collect::<Vec<_>>());
+    let count = sheets.first().map_or(0, |sheet| sheet.coalesced_columns().len());
+    let owners: Vec<_> = (0..count).map(
+        |index| sheets.iter().position(|sheet| sheet.coalesced_columns()[index].is_some())
+    ).collect();
+    columns.into_iter().map(|(sheet_index, column)| {
+        let coalesced = sheets[sheet_index].coalesced_columns().iter().position(
+            |coalesced_column| *coalesced_column == Some(column)
+        );
+        match coalesced {
+            Some(index) if owners[index] == Some(sheet_index) => OutputColumn::Coalesced(index),
+            Some(_) => OutputColumn::Dropped,
+            None => OutputColumn::Plain,
+        }
+    }).collect()
+}
+
+/// Replaces the columns of a merged row according to the layout.
+pub fn apply<T>(
+    row: Vec<T>,
+    layout: &[OutputColumn],
+    mut coalesced: impl FnMut(usize, T) -> Result<T>,
+) -> Result<Vec<T>> {
+    let mut result = vec![];
+    for (value, column) in row.into_iter().zip(layout) {
+        match column {
+            OutputColumn::Plain => result.push(value),
+            OutputColumn::Coalesced(index) => result.push(coalesced(*index, value)?),
+            OutputColumn::Dropped => {},
+        }
+    }
+    Ok(result)
+}
+
+pub fn describe_column(column: &Column) -> String {
+    match column {
+        Column::Index(index) => index.to_string(),
+        Column::Name(name) => format!("'{name}'"),
+    }
+}
+
+pub fn ensure_coalesced_columns_exist(sheets: &[Sheet], params: &MergeParams) -> Result<()> {
+    for (index, column) in params.coalesced_columns.iter().enumerate() {
+        ensure!(
+            sheets.iter().any(|sheet| sheet.coalesced_columns()[index].is_some()),
+            "None of the inputs has coalesced column {}.",
+            describe_column(column),
+        );
+    }
+    Ok(())
+}
+
+/// Chooses the value of a coalesced column among the values the merged rows have in it,
+/// reporting conflicting (non-empty) values; the value is missing if none of the rows has
+/// the column.
+pub fn coalesce<'a>(
+    index: usize,
+    data: &[(Option<&InputRecord<'a, '_>>, &'a Sheet)],
+    key: &Key,
+    params: &'a MergeParams,
+    sink: &mut dyn DiagnosticSink,
+) -> Result<Option<Cow<'a, str>>> {
+    let values: Vec<_> = data.iter().filter_map(|(record, sheet)| {
+        let record = record.as_ref()?;
+        Some((record.ids()[0], record.value(sheet.coalesced_columns()[index]?)))
+    }).collect();
+    let non_empty: Vec<_> = values.iter().filter(|(_, value)| !value.is_empty()).collect();
+    if non_empty.iter().any(|(_, value)| *value != non_empty[0].1) {
+        let column = describe_column(&params.coalesced_columns[index]);
+        let description = non_empty.iter().map(
+            |(id, value)| format!("'{value}' in input #{input}", input = id.input_index + 1)
+        ).collect::<Vec<_>>().join(", ");
+        sink.emit(Diagnostic {
+            kind: DiagnosticKind::Conflict,
+            message: format!(
+                "Conflicting values of coalesced column {column} encountered ({description}):",
+            ),
+            records: non_empty.iter().map(|(id, _)| *id).collect(),
+            keys: vec![key.values()],
+            distance: None,
+        })?;
+        if let CoalescePolicy::Fail = params.coalesce_policy {
+            bail!(
+                "Coalesced column {column} has conflicting values ({description}) \
+                 for the record:\n{key}",
+            );
+        }
+    }
+    let chosen = match params.coalesce_policy {
+        CoalescePolicy::FirstNonEmpty | CoalescePolicy::Fail => non_empty.first(),
+        CoalescePolicy::LastNonEmpty => non_empty.last(),
+        CoalescePolicy::Input(input) => non_empty.iter().find(
+            |(id, _)| id.input_index == input
+        ).or(non_empty.first()),
+    };
+    Ok(chosen.map(|(_, value)| value.clone())
+        .or_else(|| values.first().map(|(_, value)| value.clone())))
+}
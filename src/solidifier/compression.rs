@@ -0,0 +1,329 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::rc::Rc;
+use std::thread::{self, JoinHandle};
+
+use anyhow::{bail, Context, Result};
+
+use super::gzip::{GzipDecoder, GzipEncoder};
+
+/// Compression formats: gzip is handled in-process, the others by the corresponding
+/// command-line tools.
+#[derive(Clone, Copy)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    fn by_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            "bz2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    fn by_magic_bytes(header: &[u8]) -> Option<Self> {
+        match header {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            [b'B', b'Z', b'h', b'1'..=b'9', ..] => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    fn program(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Bzip2 => "bzip2",
+        }
+    }
+
+    fn command(&self, decompress: bool) -> Command {
+        let mut command = Command::new(self.program());
+        if let Compression::Zstd = self {
+            command.arg("-q");
+        }
+        if decompress {
+            command.arg("-d");
+        }
+        command.arg("-c");
+        command
+    }
+}
+
+/// Returns the path without its compression extension (if any), so that `data.csv.gz`
+/// is recognized as a CSV file.
+pub fn uncompressed_path(path: &Path) -> PathBuf {
+    match Compression::by_extension(path) {
+        Some(_) => path.with_extension(""),
+        None => path.to_path_buf(),
+    }
+}
+
+/// A compression tool, whose stderr is read by a separate thread (so that the tool never
+/// waits for it to be read while it is being fed or drained itself).
+struct Tool {
+    compression: Compression,
+    child: Child,
+    stderr: Option<JoinHandle<io::Result<String>>>,
+}
+
+fn spawn(command: &mut Command, compression: Compression, name: &str) -> Result<Tool> {
+    let program = compression.program();
+    let mut child = match command.stderr(Stdio::piped()).spawn() {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => bail!(
+            "{program} is needed to process {name}, but it could not be found; make sure it \
+             is installed and on the PATH.",
+        ),
+        result => result.with_context(
+            || format!("Could not run {program}, which is needed to process {name}.")
+        )?,
+    };
+    let mut stderr = child.stderr.take().expect("stderr should be piped");
+    let stderr = thread::spawn(move || {
+        let mut message = String::new();
+        stderr.read_to_string(&mut message).map(|_| message)
+    });
+    Ok(Tool {
+        compression,
+        child,
+        stderr: Some(stderr),
+    })
+}
+
+/// Waits for a compression tool to exit, turning a failure into an error that includes
+/// what the tool has reported.
+fn wait(tool: &mut Tool) -> io::Result<()> {
+    let message = match tool.stderr.take() {
+        Some(stderr) => {
+            stderr.join().map_err(|_| io::Error::other("Reading thread panicked."))??
+        },
+        None => String::new(),
+    };
+    let compression = tool.compression;
+    let status = tool.child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{program} failed ({status}): {message}",
+            program = compression.program(),
+            message = message.trim(),
+        )))
+    }
+}
+
+/// Output of a decompression tool that is fed the compressed data by a separate thread.
+struct Decompressed {
+    tool: Tool,
+    stdout: ChildStdout,
+    feeder: Option<JoinHandle<io::Result<u64>>>,
+}
+
+impl Read for Decompressed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.stdout.read(buf)?;
+        if count == 0 {
+            if let Some(feeder) = self.feeder.take() {
+                let fed = feeder.join().map_err(|_| io::Error::other("Reading thread panicked."))?;
+                wait(&mut self.tool)?;
+                fed?;
+            }
+        }
+        Ok(count)
+    }
+}
+
+impl Drop for Decompressed {
+    fn drop(&mut self) {
+        if self.feeder.is_some() {
+            let _ = self.tool.child.kill();
+            let _ = self.tool.child.wait();
+        }
+    }
+}
+
+/// Decompresses the source on the fly if it is compressed, judging by the extension of
+/// its path or, failing that, by its first bytes; `name` describes the source.
+pub fn decompressed(
+    source: Box<dyn Read + Send>,
+    path: &Path,
+    name: &str,
+) -> Result<Box<dyn BufRead>> {
+    let mut source = BufReader::new(source);
+    let header = source.fill_buf().with_context(|| format!("Could not read {name}."))?;
+    let Some(compression) = Compression::by_extension(path)
+        .or_else(|| Compression::by_magic_bytes(header)) else {
+        return Ok(Box::new(source));
+    };
+    if let Compression::Gzip = compression {
+        let decoder = GzipDecoder::new(source).with_context(|| format!("Could not read {name}."))?;
+        return Ok(Box::new(BufReader::new(decoder)));
+    }
+    let mut tool = spawn(
+        compression.command(true).stdin(Stdio::piped()).stdout(Stdio::piped()),
+        compression,
+        name,
+    )?;
+    let mut stdin = tool.child.stdin.take().expect("stdin should be piped");
+    let stdout = tool.child.stdout.take().expect("stdout should be piped");
+    let feeder = thread::spawn(move || match io::copy(&mut source, &mut stdin) {
+        // The tool stops reading early if the data is corrupt, which it reports itself.
+        Err(error) if error.kind() == io::ErrorKind::BrokenPipe => Ok(0),
+        result => result,
+    });
+    Ok(Box::new(BufReader::new(Decompressed {
+        tool,
+        stdout,
+        feeder: Some(feeder),
+    })))
+}
+
+/// A sink that the data written to it are only complete in once it is finished, which
+/// flushing does not do.
+trait Finish: Write {
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+impl Finish for File {
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: Write> Finish for GzipEncoder<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        GzipEncoder::finish(self)
+    }
+}
+
+/// Input of a compression tool writing to a file.
+struct Compressed {
+    tool: Tool,
+    stdin: Option<ChildStdin>,
+}
+
+impl Compressed {
+    fn stdin(&mut self) -> io::Result<&mut ChildStdin> {
+        self.stdin.as_mut().ok_or_else(|| io::Error::other("The compressed stream is finished."))
+    }
+}
+
+impl Write for Compressed {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin()?.flush()
+    }
+}
+
+impl Finish for Compressed {
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(mut stdin) = self.stdin.take() {
+            stdin.flush()?;
+            drop(stdin);
+            wait(&mut self.tool)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Compressed {
+    fn drop(&mut self) {
+        if self.stdin.take().is_some() {
+            let _ = self.tool.child.wait();
+        }
+    }
+}
+
+/// Writes to the sink of a `CompressedFile`, which it shares with the file.
+struct SharedSink(Rc<RefCell<Box<dyn Finish>>>);
+
+impl Write for SharedSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/// A file that the data written to its sink are compressed into, if its extension calls
+/// for it. The compressed stream only ends once the file is finished, which is done after
+/// the writers wrapping the sink have been flushed.
+pub struct CompressedFile {
+    sink: Rc<RefCell<Box<dyn Finish>>>,
+}
+
+impl CompressedFile {
+    pub fn sink(&self) -> Box<dyn Write> {
+        Box::new(SharedSink(self.sink.clone()))
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.sink.borrow_mut().finish()
+    }
+}
+
+/// Compresses the data written to the file if the extension of `path` (which the file
+/// will eventually have) calls for it.
+pub fn compressed(file: File, path: &Path) -> Result<CompressedFile> {
+    let sink: Box<dyn Finish> = match Compression::by_extension(path) {
+        None => Box::new(file),
+        Some(Compression::Gzip) => Box::new(GzipEncoder::new(file)
+            .with_context(|| format!("Could not write data to {}.", path.display()))?),
+        Some(compression) => {
+            let mut tool = spawn(
+                compression.command(false).stdin(Stdio::piped()).stdout(file),
+                compression,
+                &path.display().to_string(),
+            )?;
+            let stdin = tool.child.stdin.take();
+            Box::new(Compressed {
+                tool,
+                stdin,
+            })
+        },
+    };
+    Ok(CompressedFile {
+        sink: Rc::new(RefCell::new(sink)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read;
+
+    use super::super::external::TempDir;
+    use super::*;
+
+    #[test]
+    fn flushing_does_not_end_the_compressed_stream() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("output.tsv.gz");
+        let compressed = compressed(File::create(&path).unwrap(), &path).unwrap();
+        let mut sink = compressed.sink();
+        sink.write_all(b"first\n").unwrap();
+        sink.flush().unwrap();
+        sink.write_all(b"second\n").unwrap();
+        sink.flush().unwrap();
+        drop(sink);
+        compressed.finish().unwrap();
+        let data = read(&path).unwrap();
+        let mut text = String::new();
+        decompressed(Box::new(io::Cursor::new(data)), &path, "output").unwrap()
+            .read_to_string(&mut text).unwrap();
+        assert_eq!(text, "first\nsecond\n");
+    }
+}
@@ -0,0 +1,232 @@
+use std::io::{BufRead, Write};
+use std::mem::take;
+
+use anyhow::Result;
+
+const QUOTE: char = '"';
+
+pub type Records<'a> = Box<dyn Iterator<Item = Result<Vec<String>>> + 'a>;
+
+pub trait RecordWrite {
+    fn write_record(&mut self, record: &[&str]) -> Result<()>;
+
+    /// Writes a merged row, in which the values it has no data for are missing and written
+    /// as the filler unless the format has a better way of telling them apart.
+    fn write_row(&mut self, row: &[Option<&str>], filler: &str) -> Result<()> {
+        self.write_record(&row.iter().map(|value| value.unwrap_or(filler)).collect::<Vec<_>>())
+    }
+
+    fn flush(&mut self) -> Result<()>;
+}
+
+pub struct Reader<R: BufRead> {
+    source: R,
+    delimiter: String,
+}
+
+pub struct Writer<W: Write> {
+    sink: W,
+    delimiter: String,
+}
+
+fn strip_line_terminator(line: &str) -> Option<&str> {
+    line.strip_suffix("\r\n").or_else(|| line.strip_suffix('\n'))
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(source: R, delimiter: &str) -> Self {
+        Self {
+            source,
+            delimiter: String::from(delimiter),
+        }
+    }
+
+    fn read_record(&mut self) -> Result<Option<Vec<String>>> {
+        let mut fields = vec![];
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut at_field_start = true;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.source.read_line(&mut line)? == 0 {
+                return Ok(if fields.is_empty() && at_field_start {
+                    None
+                } else {
+                    fields.push(field);
+                    Some(fields)
+                });
+            }
+            if fields.is_empty() && at_field_start && strip_line_terminator(&line) == Some("") {
+                continue;
+            }
+            let mut rest = line.as_str();
+            while let Some(char) = rest.chars().next() {
+                if in_quotes {
+                    if char == QUOTE {
+                        if rest[1..].starts_with(QUOTE) {
+                            field.push(QUOTE);
+                            rest = &rest[2..];
+                        } else {
+                            in_quotes = false;
+                            rest = &rest[1..];
+                        }
+                    } else {
+                        field.push(char);
+                        rest = &rest[char.len_utf8()..];
+                    }
+                } else if at_field_start && char == QUOTE {
+                    in_quotes = true;
+                    at_field_start = false;
+                    rest = &rest[1..];
+                } else if rest.starts_with(&self.delimiter) {
+                    fields.push(take(&mut field));
+                    at_field_start = true;
+                    rest = &rest[self.delimiter.len()..];
+                } else if strip_line_terminator(rest) == Some("") {
+                    fields.push(field);
+                    return Ok(Some(fields));
+                } else {
+                    field.push(char);
+                    at_field_start = false;
+                    rest = &rest[char.len_utf8()..];
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = Result<Vec<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(sink: W, delimiter: &str) -> Self {
+        Self {
+            sink,
+            delimiter: String::from(delimiter),
+        }
+    }
+
+    /// Checks whether a field has to be quoted to be read back as written, which is also
+    /// the case when a delimiter could be found across its boundary with a neighbour: when
+    /// it ends with the start of the delimiter or starts with its end.
+    fn needs_quotes(&self, field: &str) -> bool {
+        field.contains(&self.delimiter)
+            || field.contains([QUOTE, '\r', '\n'])
+            || self.delimiter.char_indices().skip(1).any(|(index, _)| {
+                field.ends_with(&self.delimiter[..index])
+                    || field.starts_with(&self.delimiter[index..])
+            })
+    }
+}
+
+impl<W: Write> RecordWrite for Writer<W> {
+    fn write_record(&mut self, record: &[&str]) -> Result<()> {
+        for (index, field) in record.iter().enumerate() {
+            if index > 0 {
+                self.sink.write_all(self.delimiter.as_bytes())?;
+            }
+            if self.needs_quotes(field) || (record.len() == 1 && field.is_empty()) {
+                let escaped = field.replace(QUOTE, "\"\"");
+                write!(self.sink, "{QUOTE}{escaped}{QUOTE}")?;
+            } else {
+                self.sink.write_all(field.as_bytes())?;
+            }
+        }
+        self.sink.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sink.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWrite for csv::Writer<W> {
+    fn write_record(&mut self, record: &[&str]) -> Result<()> {
+        csv::Writer::write_record(self, record)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        csv::Writer::flush(self)?;
+        Ok(())
+    }
+}
+
+pub fn as_strs<S: AsRef<str>>(record: &[S]) -> Vec<&str> {
+    record.iter().map(AsRef::as_ref).collect()
+}
+
+pub fn as_optional_strs<S: AsRef<str>>(row: &[Option<S>]) -> Vec<Option<&str>> {
+    row.iter().map(|value| value.as_ref().map(AsRef::as_ref)).collect()
+}
+
+pub fn records<'a, R: BufRead + 'a>(source: R, delimiter: &str) -> Records<'a> {
+    if let &[byte] = delimiter.as_bytes() {
+        Box::new(csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(byte)
+            .from_reader(source)
+            .into_records()
+            .map(|result| Ok(result?.iter().map(String::from).collect())))
+    } else {
+        Box::new(Reader::new(source, delimiter))
+    }
+}
+
+pub fn record_writer<'a, W: Write + 'a>(sink: W, delimiter: &str) -> Box<dyn RecordWrite + 'a> {
+    if let &[byte] = delimiter.as_bytes() {
+        Box::new(csv::WriterBuilder::new().delimiter(byte).from_writer(sink))
+    } else {
+        Box::new(Writer::new(sink, delimiter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(records: &[Vec<&str>], delimiter: &str) -> String {
+        let mut data = vec![];
+        let mut writer = record_writer(&mut data, delimiter);
+        for record in records {
+            writer.write_record(record).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        String::from_utf8(data).unwrap()
+    }
+
+    #[test]
+    fn fields_next_to_parts_of_the_delimiter_are_quoted() {
+        assert_eq!(write(&[vec!["1", "a|", "z"]], "||"), "1||\"a|\"||z\n");
+        assert_eq!(write(&[vec!["|b", "a|b", "|"]], "||"), "\"|b\"||a|b||\"|\"\n");
+        assert_eq!(write(&[vec!["x<", ">", "<>"]], "<->"), "\"x<\"<->\">\"<-><>\n");
+    }
+
+    #[test]
+    fn written_records_are_read_back() {
+        let values = [
+            "", "a", "a|", "|b", "|", "||", "a||b", "\"q\"", "two\nlines", "<", "->", "→", "x→",
+        ];
+        for delimiter in ["||", "<->", "→", "|→|", "\t"] {
+            let mut expected = vec![];
+            for first in values {
+                for second in values {
+                    expected.push(vec![first, second, first]);
+                }
+            }
+            let written = write(&expected, delimiter);
+            let read: Vec<_> = records(written.as_bytes(), delimiter).map(Result::unwrap).collect();
+            assert_eq!(read, expected, "{delimiter:?}");
+        }
+    }
+}
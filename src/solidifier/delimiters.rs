@@ -0,0 +1,110 @@
+use std::io::BufRead;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+const CANDIDATES: [u8; 4] = [b',', b'\t', b';', b'|'];
+const SAMPLE_SIZE: usize = 100;
+/// Length in bytes beyond which no more records are added to the sample.
+const MAX_SAMPLE_LENGTH: usize = 1 << 20;
+
+fn by_extension(path: &Path) -> Option<u8> {
+    match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "csv" => Some(b','),
+        "tsv" | "tab" => Some(b'\t'),
+        "psv" => Some(b'|'),
+        _ => None,
+    }
+}
+
+fn sample_field_counts(sample: &[u8], delimiter: u8, name: &str) -> Result<Vec<usize>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(sample);
+    let mut counts = vec![];
+    for (index, result) in reader.byte_records().take(SAMPLE_SIZE).enumerate() {
+        let record = result.with_context(
+            || format!("Could not process record #{} of {name}.", index + 1)
+        )?;
+        counts.push(record.len());
+    }
+    Ok(counts)
+}
+
+/// Reads the first records of the source, which the delimiter is detected by. Line breaks
+/// within quoted fields do not end a record, so that no record of the sample is cut short.
+pub fn read_sample(source: &mut impl BufRead) -> std::io::Result<Vec<u8>> {
+    let mut sample = vec![];
+    let mut records = 0;
+    let mut is_quoted = false;
+    while records < SAMPLE_SIZE && sample.len() < MAX_SAMPLE_LENGTH {
+        let start = sample.len();
+        if source.read_until(b'\n', &mut sample)? == 0 {
+            break;
+        }
+        let quotes = sample[start..].iter().filter(|byte| **byte == b'"').count();
+        is_quoted ^= quotes % 2 == 1;
+        if !is_quoted {
+            records += 1;
+        }
+    }
+    Ok(sample)
+}
+
+fn consistent_field_count(counts: &[usize]) -> Option<usize> {
+    let first = *counts.first()?;
+    if first > 1 && counts.iter().all(|count| *count == first) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+/// Detects the delimiter of an input by its path and by a sample of its contents;
+/// `name` describes the input.
+pub fn detect_delimiter(path: &Path, sample: &[u8], name: &str) -> Result<String> {
+    let preferred = by_extension(path);
+    let mut best: Option<(u8, usize)> = None;
+    let mut is_empty = false;
+    for delimiter in preferred.into_iter().chain(
+        CANDIDATES.into_iter().filter(|candidate| Some(*candidate) != preferred)
+    ) {
+        let counts = sample_field_counts(sample, delimiter, name)?;
+        is_empty = counts.is_empty();
+        if let Some(count) = consistent_field_count(&counts) {
+            if Some(delimiter) == preferred {
+                return Ok(String::from(delimiter as char));
+            }
+            if best.is_none_or(|(_, best_count)| count > best_count) {
+                best = Some((delimiter, count));
+            }
+        }
+    }
+    best.map(|(delimiter, _)| delimiter)
+        .or(preferred)
+        .or(if is_empty { Some(CANDIDATES[0]) } else { None })
+        .map(|delimiter| String::from(delimiter as char))
+        .with_context(|| format!("Could not detect the delimiter of {name}; \
+                                  consider specifying it explicitly."))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn sample_keeps_quoted_line_breaks_within_records() {
+        let mut data = Vec::from(&b"id,note,other\n"[..]);
+        for index in 0..SAMPLE_SIZE {
+            data.extend_from_slice(format!("{index},\"a\nb\",c\n").as_bytes());
+        }
+        let sample = read_sample(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(sample.len(), data.len() - "99,\"a\nb\",c\n".len());
+        let delimiter = detect_delimiter(Path::new("data"), &sample, "the input").unwrap();
+        assert_eq!(delimiter, ",");
+    }
+}
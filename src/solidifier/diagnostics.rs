@@ -0,0 +1,95 @@
+use anyhow::Result;
+
+use crate::strings::json::Json;
+
+use super::keys::{describe_key, KeyValue, RecordId};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DiagnosticKind {
+    /// Records with different keys were merged as a result of fuzzy merging.
+    FuzzyMerged,
+    /// Records with some key are not present in equal numbers in all the inputs.
+    Unmatched,
+    /// Records with similar (but different) keys were not merged.
+    Similar,
+    /// Records could be merged in multiple ways, which was not allowed.
+    Ambiguous,
+    /// A record has a different number of columns than the first record of its input.
+    Ragged,
+    /// Merged records have different (non-empty) values in a coalesced column.
+    Conflict,
+    /// A value that is aggregated numerically is not a number.
+    NonNumeric,
+}
+
+/// Something noteworthy encountered while merging the inputs.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    /// The records concerned.
+    pub records: Vec<RecordId>,
+    /// The distinct keys of the records concerned (empty for ragged records).
+    pub keys: Vec<Vec<KeyValue>>,
+    /// The edit distance between the keys, for fuzzy merges and similar records.
+    pub distance: Option<u32>,
+}
+
+/// Receives diagnostics as they are encountered.
+pub trait DiagnosticSink {
+    fn emit(&mut self, diagnostic: Diagnostic) -> Result<()>;
+}
+
+impl DiagnosticSink for Vec<Diagnostic> {
+    fn emit(&mut self, diagnostic: Diagnostic) -> Result<()> {
+        self.push(diagnostic);
+        Ok(())
+    }
+}
+
+impl DiagnosticKind {
+    fn name(&self) -> &'static str {
+        match self {
+            DiagnosticKind::FuzzyMerged => "fuzzy-merged",
+            DiagnosticKind::Unmatched => "unmatched",
+            DiagnosticKind::Similar => "similar",
+            DiagnosticKind::Ambiguous => "ambiguous",
+            DiagnosticKind::Ragged => "ragged",
+            DiagnosticKind::Conflict => "conflict",
+            DiagnosticKind::NonNumeric => "non-numeric",
+        }
+    }
+}
+
+fn json_array(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(", "))
+}
+
+impl Diagnostic {
+    /// Returns the message followed by the keys concerned (or, if there are none,
+    /// by the records concerned), one per line.
+    pub fn lines(&self) -> Vec<String> {
+        let subjects: Vec<_> = if self.keys.is_empty() {
+            self.records.iter().map(RecordId::to_string).collect()
+        } else {
+            self.keys.iter().map(|key| describe_key(key)).collect()
+        };
+        [self.message.clone()].into_iter().chain(subjects).collect()
+    }
+}
+
+impl Json for Diagnostic {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"kind\": {kind}, \"message\": {message}, \"records\": {records}, \
+             \"keys\": {keys}, \"distance\": {distance}}}",
+            kind = self.kind.name().to_json(),
+            message = self.message.trim_end_matches(':').to_json(),
+            records = json_array(self.records.iter().map(RecordId::to_json)),
+            keys = json_array(self.keys.iter().map(
+                |key| json_array(key.iter().map(KeyValue::to_json))
+            )),
+            distance = self.distance.map_or(String::from("null"), |distance| distance.to_string()),
+        )
+    }
+}
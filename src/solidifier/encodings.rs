@@ -0,0 +1,400 @@
+use std::io::{self, BufRead, Read, Write};
+use std::str;
+
+use crate::params::Encoding;
+
+const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+const UTF16LE_BOM: &[u8] = &[0xff, 0xfe];
+const UTF16BE_BOM: &[u8] = &[0xfe, 0xff];
+
+/// Characters of bytes 0x80 to 0x9F in Windows-1252; the five bytes undefined in it are
+/// mapped to the C1 control characters of the same code.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+fn name(encoding: Encoding) -> &'static str {
+    match encoding {
+        Encoding::Utf8 => "UTF-8",
+        Encoding::Utf16Le => "UTF-16LE",
+        Encoding::Utf16Be => "UTF-16BE",
+        Encoding::Latin1 => "Latin-1",
+        Encoding::Windows1252 => "Windows-1252",
+    }
+}
+
+fn bom(encoding: Encoding) -> &'static [u8] {
+    match encoding {
+        Encoding::Utf8 => UTF8_BOM,
+        Encoding::Utf16Le => UTF16LE_BOM,
+        Encoding::Utf16Be => UTF16BE_BOM,
+        Encoding::Latin1 | Encoding::Windows1252 => &[],
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Converts data in some encoding to UTF-8, skipping the byte order mark, which takes
+/// precedence over the given encoding.
+pub struct Decoder<R: BufRead> {
+    source: R,
+    encoding: Encoding,
+    /// Name of the option specifying the encoding, suggested when the data is invalid.
+    option: String,
+    /// Bytes read from the source but not decoded yet.
+    pending: Vec<u8>,
+    /// Offset of the first pending byte in the source.
+    offset: usize,
+    decoded: Vec<u8>,
+    position: usize,
+    is_exhausted: bool,
+    is_started: bool,
+    has_bom: bool,
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub fn new(source: R, encoding: Encoding, option: &str) -> Self {
+        Self {
+            source,
+            encoding,
+            option: String::from(option),
+            pending: vec![],
+            offset: 0,
+            decoded: vec![],
+            position: 0,
+            is_exhausted: false,
+            is_started: false,
+            has_bom: false,
+        }
+    }
+
+    fn fill_pending(&mut self) -> io::Result<()> {
+        let buffer = self.source.fill_buf()?;
+        if buffer.is_empty() {
+            self.is_exhausted = true;
+        } else {
+            self.pending.extend_from_slice(buffer);
+            let length = buffer.len();
+            self.source.consume(length);
+        }
+        Ok(())
+    }
+
+    fn skip_bom(&mut self) -> io::Result<()> {
+        while self.pending.len() < UTF8_BOM.len() && !self.is_exhausted {
+            self.fill_pending()?;
+        }
+        let detected = [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be].into_iter().find(
+            |encoding| self.pending.starts_with(bom(*encoding))
+        );
+        if let Some(encoding) = detected {
+            self.encoding = encoding;
+            self.pending.drain(..bom(encoding).len());
+            self.offset = bom(encoding).len();
+            self.has_bom = true;
+        }
+        Ok(())
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.is_started {
+            self.is_started = true;
+            self.skip_bom()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the encoding of the data (as told by the byte order mark, if there is one)
+    /// and whether the data starts with a byte order mark.
+    pub fn detect_encoding(&mut self) -> io::Result<(Encoding, bool)> {
+        self.start()?;
+        Ok((self.encoding, self.has_bom))
+    }
+
+    fn invalid(&self, index: usize) -> io::Error {
+        invalid_data(format!(
+            "The data is not valid {encoding} (byte #{byte}); consider specifying the encoding \
+             (see {option}).",
+            encoding = name(self.encoding),
+            byte = self.offset + index + 1,
+            option = self.option,
+        ))
+    }
+
+    /// Decodes as many of the pending bytes as possible, returning their number.
+    fn decode(&mut self) -> io::Result<usize> {
+        let pending = &self.pending;
+        match self.encoding {
+            Encoding::Utf8 => match str::from_utf8(pending) {
+                Ok(text) => {
+                    self.decoded.extend_from_slice(text.as_bytes());
+                    Ok(pending.len())
+                },
+                Err(error) => {
+                    let valid = error.valid_up_to();
+                    if error.error_len().is_some() || self.is_exhausted {
+                        return Err(self.invalid(valid));
+                    }
+                    self.decoded.extend_from_slice(&pending[..valid]);
+                    Ok(valid)
+                },
+            },
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut units = pending.chunks_exact(2).map(|pair| match self.encoding {
+                    Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                });
+                let mut count = 0;
+                while let Some(unit) = units.next() {
+                    let is_high_surrogate = (0xd800..0xdc00).contains(&unit);
+                    let text = if is_high_surrogate {
+                        let Some(next) = units.next() else {
+                            break;
+                        };
+                        char::decode_utf16([unit, next]).next()
+                    } else {
+                        char::decode_utf16([unit]).next()
+                    };
+                    let char = text.and_then(Result::ok).ok_or_else(|| self.invalid(count))?;
+                    self.decoded.extend_from_slice(char.encode_utf8(&mut [0; 4]).as_bytes());
+                    count += char.len_utf16() * 2;
+                }
+                if count < pending.len() && self.is_exhausted {
+                    return Err(self.invalid(count));
+                }
+                Ok(count)
+            },
+            Encoding::Latin1 | Encoding::Windows1252 => {
+                for byte in pending {
+                    let char = match (self.encoding, byte) {
+                        (Encoding::Windows1252, 0x80..=0x9f) => WINDOWS_1252[*byte as usize - 0x80],
+                        _ => char::from(*byte),
+                    };
+                    self.decoded.extend_from_slice(char.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                Ok(pending.len())
+            },
+        }
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.start()?;
+        while self.position == self.decoded.len() {
+            self.decoded.clear();
+            self.position = 0;
+            if self.pending.is_empty() && self.is_exhausted {
+                return Ok(0);
+            }
+            if !self.is_exhausted {
+                self.fill_pending()?;
+            }
+            let count = self.decode()?;
+            self.pending.drain(..count);
+            self.offset += count;
+        }
+        let count = buf.len().min(self.decoded.len() - self.position);
+        buf[..count].copy_from_slice(&self.decoded[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// Converts UTF-8 data written to it to some other encoding.
+pub struct Encoder<W: Write> {
+    sink: W,
+    encoding: Encoding,
+    /// Bytes of an incomplete character at the end of the data written so far.
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    fn encode(&mut self, text: &str) -> io::Result<()> {
+        let mut encoded = vec![];
+        for char in text.chars() {
+            match self.encoding {
+                Encoding::Utf8 => {
+                    encoded.extend_from_slice(char.encode_utf8(&mut [0; 4]).as_bytes());
+                },
+                Encoding::Utf16Le | Encoding::Utf16Be => {
+                    for unit in char.encode_utf16(&mut [0; 2]) {
+                        encoded.extend_from_slice(&match self.encoding {
+                            Encoding::Utf16Le => unit.to_le_bytes(),
+                            _ => unit.to_be_bytes(),
+                        });
+                    }
+                },
+                Encoding::Latin1 | Encoding::Windows1252 => {
+                    let byte = match (self.encoding, u32::from(char)) {
+                        (_, code @ (0..=0x7f | 0xa0..=0xff)) => Some(code as u8),
+                        (Encoding::Latin1, code @ 0x80..=0x9f) => Some(code as u8),
+                        (Encoding::Windows1252, _) => WINDOWS_1252.iter().position(
+                            |candidate| *candidate == char
+                        ).map(|index| 0x80 + index as u8),
+                        _ => None,
+                    };
+                    encoded.push(byte.ok_or_else(|| invalid_data(format!(
+                        "Character '{char}' cannot be represented in {encoding}.",
+                        encoding = name(self.encoding),
+                    )))?);
+                },
+            }
+        }
+        self.sink.write_all(&encoded)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid = match str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            Err(_) => return Err(invalid_data(String::from("The data is not valid UTF-8."))),
+        };
+        let pending = self.pending.split_off(valid);
+        let text = String::from_utf8(std::mem::replace(&mut self.pending, pending))
+            .expect("the data should have been validated");
+        self.encode(&text)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+/// Returns a writer converting UTF-8 data to the given encoding, starting with a byte
+/// order mark if requested.
+pub fn encoded<'a, W: Write + 'a>(
+    mut sink: W,
+    encoding: Encoding,
+    with_bom: bool,
+) -> io::Result<Box<dyn Write + 'a>> {
+    if with_bom {
+        sink.write_all(bom(encoding))?;
+    }
+    Ok(if encoding == Encoding::Utf8 {
+        Box::new(sink)
+    } else {
+        Box::new(Encoder {
+            sink,
+            encoding,
+            pending: vec![],
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use super::*;
+
+    const ENCODINGS: [Encoding; 5] = [
+        Encoding::Utf8,
+        Encoding::Utf16Le,
+        Encoding::Utf16Be,
+        Encoding::Latin1,
+        Encoding::Windows1252,
+    ];
+
+    /// Decodes the data read from a source returning at most `chunk_size` bytes at a time.
+    fn decode(data: &[u8], encoding: Encoding, chunk_size: usize) -> io::Result<String> {
+        let source = BufReader::with_capacity(chunk_size, data);
+        let mut text = String::new();
+        Decoder::new(source, encoding, "--encoding").read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    /// Encodes the text written to the encoder a byte at a time.
+    fn encode(text: &str, encoding: Encoding, with_bom: bool) -> io::Result<Vec<u8>> {
+        let mut data = vec![];
+        let mut encoder = encoded(&mut data, encoding, with_bom)?;
+        for byte in text.as_bytes() {
+            encoder.write_all(&[*byte])?;
+        }
+        encoder.flush()?;
+        drop(encoder);
+        Ok(data)
+    }
+
+    #[test]
+    fn utf16_is_decoded_across_reads() {
+        // The crab is a surrogate pair, which a single-byte read splits in any case.
+        let text = "a é 🦀 語";
+        for encoding in [Encoding::Utf16Le, Encoding::Utf16Be] {
+            let data = encode(text, encoding, false).unwrap();
+            for chunk_size in [1, 2, 3, 5, 100] {
+                assert_eq!(decode(&data, encoding, chunk_size).unwrap(), text);
+                let with_bom = [bom(encoding), &data].concat();
+                assert_eq!(decode(&with_bom, Encoding::Utf8, chunk_size).unwrap(), text);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_utf16_is_rejected() {
+        for data in [&[0x3d, 0xd8, b'a', 0][..], &[0x3e, 0xdd], &[0x3d, 0xd8], &[b'a', 0, b'b']] {
+            let error = decode(data, Encoding::Utf16Le, 1).unwrap_err();
+            assert!(error.to_string().starts_with("The data is not valid UTF-16LE"), "{error}");
+        }
+    }
+
+    #[test]
+    fn single_byte_encodings_differ_in_c1_range() {
+        let data = [b'a', 0x80, 0x81, 0x9f, 0xe9];
+        assert_eq!(decode(&data, Encoding::Windows1252, 2).unwrap(), "a€\u{81}Ÿé");
+        assert_eq!(decode(&data, Encoding::Latin1, 2).unwrap(), "a\u{80}\u{81}\u{9f}é");
+        assert_eq!(encode("a€\u{81}Ÿé", Encoding::Windows1252, false).unwrap(), data);
+        assert_eq!(encode("a\u{80}\u{81}\u{9f}é", Encoding::Latin1, false).unwrap(), data);
+        let error = encode("€", Encoding::Latin1, false).unwrap_err();
+        assert_eq!(error.to_string(), "Character '€' cannot be represented in Latin-1.");
+        assert!(encode("語", Encoding::Windows1252, false).is_err());
+    }
+
+    #[test]
+    fn text_round_trips_through_each_encoding() {
+        let text = "id;name\n1;Zoë ÿ\r\n";
+        for encoding in ENCODINGS {
+            for with_bom in [false, true] {
+                let data = encode(text, encoding, with_bom).unwrap();
+                assert_eq!(data.starts_with(bom(encoding)), with_bom || bom(encoding).is_empty());
+                for chunk_size in [1, 4, 100] {
+                    assert_eq!(decode(&data, encoding, chunk_size).unwrap(), text, "{encoding:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn byte_order_mark_takes_precedence_and_is_detected() {
+        let data = [UTF8_BOM, "é".as_bytes()].concat();
+        let mut decoder = Decoder::new(data.as_slice(), Encoding::Latin1, "--encoding");
+        assert_eq!(decoder.detect_encoding().unwrap(), (Encoding::Utf8, true));
+        let mut text = String::new();
+        decoder.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "é");
+        let mut decoder = Decoder::new(&b"ab"[..], Encoding::Latin1, "--encoding");
+        assert_eq!(decoder.detect_encoding().unwrap(), (Encoding::Latin1, false));
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported_with_its_position() {
+        let error = decode(b"abc\xffdef", Encoding::Utf8, 2).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The data is not valid UTF-8 (byte #4); consider specifying the encoding \
+             (see --encoding).",
+        );
+        // A multi-byte character cut off at the end of the data.
+        assert!(decode(&"é".as_bytes()[..1], Encoding::Utf8, 1).is_err());
+        assert_eq!(decode("日本".as_bytes(), Encoding::Utf8, 1).unwrap(), "日本");
+    }
+}
@@ -0,0 +1,338 @@
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::collections::BinaryHeap;
+use std::env::temp_dir;
+use std::fs::{remove_dir_all, remove_file, DirBuilder, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{ErrorKind, Seek, SeekFrom};
+use std::iter::once;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+
+use anyhow::{Context, Result};
+
+use super::sheet::Sheet;
+use super::sorted::{sort_key, IndexedRecords};
+
+/// Maximum number of runs merged at once, which keeps the number of open files bounded.
+const MAX_FAN_IN: usize = 64;
+
+/// A directory accessible only to the current user, holding the temporary files of a single
+/// run of the program; it is removed along with its contents when dropped.
+pub struct TempDir {
+    path: PathBuf,
+    file_count: Cell<usize>,
+}
+
+struct TempFile {
+    path: PathBuf,
+    _directory: Rc<TempDir>,
+}
+
+/// A sorted run of records spilled to disk.
+struct Run {
+    records: csv::StringRecordsIntoIter<File>,
+    _file: TempFile,
+}
+
+struct RunMerger {
+    layout: Sheet,
+    runs: Vec<Run>,
+    pending: Vec<Option<Vec<String>>>,
+    heap: BinaryHeap<Reverse<(Vec<String>, usize, usize)>>,
+}
+
+/// Returns a number that differs between calls, to make the names of temporary files
+/// unpredictable.
+pub fn random_id() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(process::id());
+    hasher.finish()
+}
+
+impl TempDir {
+    pub fn new() -> Result<Rc<Self>> {
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        loop {
+            let path = temp_dir().join(format!("solidify-{:016x}", random_id()));
+            match builder.create(&path) {
+                Ok(()) => {
+                    return Ok(Rc::new(Self {
+                        path,
+                        file_count: Cell::new(0),
+                    }));
+                },
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error).with_context(|| format!(
+                    "Could not create a temporary directory in {}.",
+                    temp_dir().display(),
+                )),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.path);
+    }
+}
+
+impl TempFile {
+    /// Creates a new file in the directory, failing rather than opening an existing one.
+    fn create(directory: &Rc<TempDir>) -> Result<(Self, File)> {
+        let count = directory.file_count.get();
+        directory.file_count.set(count + 1);
+        let path = directory.path.join(format!("run-{count}.tmp"));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("Could not create {}.", path.display()))?;
+        Ok((
+            Self {
+                path,
+                _directory: directory.clone(),
+            },
+            file,
+        ))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.path);
+    }
+}
+
+impl Run {
+    fn write(
+        rows: impl IntoIterator<Item = Result<(usize, Vec<String>)>>,
+        directory: &Rc<TempDir>,
+    ) -> Result<Self> {
+        let (file, mut data) = TempFile::create(directory)?;
+        let error_context = || format!("Could not write to {}.", file.path.display());
+        let mut writer = csv::WriterBuilder::new()
+            .flexible(true)
+            .from_writer(&data);
+        for row in rows {
+            let (index, row) = row?;
+            writer.write_record(once(index.to_string()).chain(row))
+                .with_context(error_context)?;
+        }
+        writer.flush().with_context(error_context)?;
+        drop(writer);
+        data.seek(SeekFrom::Start(0)).with_context(error_context)?;
+        let records = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(data)
+            .into_records();
+        Ok(Self {
+            records,
+            _file: file,
+        })
+    }
+
+    fn read(&mut self) -> Result<Option<(usize, Vec<String>)>> {
+        if let Some(record) = self.records.next() {
+            let record = record?;
+            let mut fields = record.into_iter().map(String::from);
+            let index = fields.next().context("Temporary data is corrupted.")?.parse()?;
+            Ok(Some((index, fields.collect())))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl RunMerger {
+    fn new(layout: Sheet, runs: Vec<Run>) -> Result<Self> {
+        let mut merger = Self {
+            layout,
+            pending: vec![None; runs.len()],
+            runs,
+            heap: BinaryHeap::new(),
+        };
+        for run_index in 0..merger.runs.len() {
+            merger.refill(run_index)?;
+        }
+        Ok(merger)
+    }
+
+    fn refill(&mut self, run_index: usize) -> Result<()> {
+        if let Some((index, row)) = self.runs[run_index].read()? {
+            self.heap.push(Reverse((sort_key(&self.layout, &row), index, run_index)));
+            self.pending[run_index] = Some(row);
+        }
+        Ok(())
+    }
+
+    fn next_record(&mut self) -> Result<Option<(usize, Vec<String>)>> {
+        if let Some(Reverse((_, index, run_index))) = self.heap.pop() {
+            let row = self.pending[run_index].take().unwrap();
+            self.refill(run_index)?;
+            Ok(Some((index, row)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl Iterator for RunMerger {
+    type Item = Result<(usize, Vec<String>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Sorts the records by their keys (and, for equal keys, by their indices), spilling
+/// them to temporary files in `directory` in sorted runs of `run_size` records, unless
+/// all of them fit into a single run. Runs are merged at most `MAX_FAN_IN` at a time,
+/// in as many passes as needed.
+pub fn sort_externally(
+    mut records: IndexedRecords,
+    template: &Sheet,
+    run_size: usize,
+    directory: &Rc<TempDir>,
+) -> Result<IndexedRecords<'static>> {
+    let mut runs = vec![];
+    loop {
+        let mut chunk = records.by_ref().take(run_size).collect::<Result<Vec<_>>>()?;
+        let is_last = chunk.len() < run_size;
+        chunk.sort_by_cached_key(|(index, row)| (sort_key(template, row), *index));
+        if is_last && runs.is_empty() {
+            return Ok(Box::new(chunk.into_iter().map(Ok)));
+        }
+        if !chunk.is_empty() {
+            runs.push(Run::write(chunk.into_iter().map(Ok), directory)?);
+        }
+        if is_last {
+            break;
+        }
+    }
+    let layout = || Sheet::with_structure_of(template, vec![]);
+    while runs.len() > MAX_FAN_IN {
+        let mut merged = vec![];
+        let mut remaining = runs.into_iter();
+        loop {
+            let batch: Vec<_> = remaining.by_ref().take(MAX_FAN_IN).collect();
+            if batch.is_empty() {
+                break;
+            }
+            merged.push(Run::write(RunMerger::new(layout(), batch)?, directory)?);
+        }
+        runs = merged;
+    }
+    Ok(Box::new(RunMerger::new(layout(), runs)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, write};
+
+    use crate::params::{Delimiter, Encoding, Params, ReportFormat};
+
+    use super::super::builder::Solidifier;
+    use super::super::diagnostics::Diagnostic;
+    use super::super::sorted::solidify_sorted;
+    use super::super::solidify_in_memory;
+    use super::*;
+
+    const WORDS: [&str; 6] = ["apple", "apples", "kiwi", "kiwis", "lemon", "melon"];
+
+    fn input(seed: usize, count: usize) -> String {
+        (0..count).map(|index| {
+            let word = WORDS[(index * 7 + seed * 3) % WORDS.len()];
+            format!("{word}{number}\t{seed}-{index}\n", number = (index * 5 + seed) % 4)
+        }).collect()
+    }
+
+    /// Merges the inputs, returning the sorted output lines and the sorted diagnostics.
+    fn merge(directory: &TempDir, external_sort: Option<usize>) -> (Vec<String>, Vec<String>) {
+        let output = directory.path.join(format!("output-{external_sort:?}.tsv"));
+        let params = Params {
+            inputs: vec![directory.path.join("a.tsv"), directory.path.join("b.tsv")],
+            output: output.clone(),
+            delimiter: Delimiter::Fixed(String::from("\t")),
+            output_delimiter: None,
+            encodings: vec![Encoding::Utf8; 2],
+            output_encoding: Encoding::Utf8,
+            output_bom: false,
+            worksheets: vec![],
+            output_format: None,
+            highlight_filler: false,
+            no_clobber: false,
+            sorted: false,
+            external_sort,
+            report: ReportFormat::Text,
+            report_file: None,
+            unmatched_dir: None,
+            merge: Solidifier::new()
+                .shared([1])
+                .allow_multi_merge(true)
+                .warn_similar(1)
+                .warn_unmatched(true)
+                .params(2)
+                .unwrap(),
+        };
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        if external_sort.is_some() {
+            solidify_sorted(&params, &mut diagnostics).unwrap();
+        } else {
+            solidify_in_memory(&params, &mut diagnostics).unwrap();
+        }
+        let mut lines: Vec<_> = read_to_string(output).unwrap().lines().map(String::from).collect();
+        lines.sort();
+        let mut diagnostics: Vec<_> = diagnostics.iter().map(|diagnostic| {
+            let mut keys = diagnostic.keys.clone();
+            keys.sort_by_key(|key| format!("{key:?}"));
+            let Diagnostic { kind, records, distance, .. } = diagnostic;
+            format!("{kind:?} {records:?} {keys:?} {distance:?}")
+        }).collect();
+        diagnostics.sort();
+        (lines, diagnostics)
+    }
+
+    #[test]
+    fn external_sort_merges_like_in_memory() {
+        let directory = TempDir::new().unwrap();
+        write(directory.path.join("a.tsv"), input(1, 300)).unwrap();
+        write(directory.path.join("b.tsv"), input(2, 200)).unwrap();
+        let expected = merge(&directory, None);
+        assert!(expected.1.iter().any(|diagnostic| diagnostic.starts_with("Similar")));
+        // With single-record runs, there are more runs than can be merged at once.
+        for run_size in [1, 7, 1000] {
+            assert_eq!(merge(&directory, Some(run_size)), expected, "run size: {run_size}");
+        }
+    }
+
+    #[test]
+    fn temporary_files_are_private_and_removed() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path.clone();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        let (file, _) = TempFile::create(&directory).unwrap();
+        assert!(file.path.starts_with(&path));
+        drop(directory);
+        assert!(path.exists());
+        drop(file);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,222 @@
+use std::io::{self, BufRead, Read, Write};
+
+use super::spreadsheet::{update_crc32, Deflater, Inflater};
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const DEFLATE: u8 = 8;
+const HEADER_CRC: u8 = 0x02;
+const EXTRA: u8 = 0x04;
+const NAME: u8 = 0x08;
+const COMMENT: u8 = 0x10;
+const RESERVED: u8 = 0xe0;
+/// Operating system field of the written header: unknown.
+const UNKNOWN_SYSTEM: u8 = 0xff;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid gzip data: {message}."))
+}
+
+fn read_bytes<const N: usize>(source: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    source.read_exact(&mut bytes).map_err(|error| match error.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_data("unexpected end of data"),
+        _ => error,
+    })?;
+    Ok(bytes)
+}
+
+/// Reads the header of a gzip member (RFC 1952), skipping its optional fields.
+fn read_header(source: &mut impl BufRead) -> io::Result<()> {
+    let header = read_bytes::<10>(source)?;
+    if header[..2] != MAGIC {
+        return Err(invalid_data("wrong signature"));
+    }
+    if header[2] != DEFLATE {
+        return Err(invalid_data("unsupported compression method"));
+    }
+    let flags = header[3];
+    if flags & RESERVED != 0 {
+        return Err(invalid_data("reserved flags are set"));
+    }
+    if flags & EXTRA != 0 {
+        let length = u16::from_le_bytes(read_bytes(source)?);
+        io::copy(&mut source.take(u64::from(length)), &mut io::sink())?;
+    }
+    for flag in [NAME, COMMENT] {
+        if flags & flag != 0 {
+            source.read_until(0, &mut vec![])?;
+        }
+    }
+    if flags & HEADER_CRC != 0 {
+        read_bytes::<2>(source)?;
+    }
+    Ok(())
+}
+
+/// Decompresses gzip data, which may consist of several members one after another.
+pub struct GzipDecoder<R: BufRead> {
+    member: Inflater<R>,
+    crc: u32,
+    size: u32,
+    is_finished: bool,
+}
+
+impl<R: BufRead> GzipDecoder<R> {
+    pub fn new(mut source: R) -> io::Result<Self> {
+        read_header(&mut source)?;
+        Ok(Self {
+            member: Inflater::new(source),
+            crc: 0,
+            size: 0,
+            is_finished: false,
+        })
+    }
+
+    /// Checks the trailer of the member just decompressed and starts the next one, if any.
+    fn end_member(&mut self) -> io::Result<()> {
+        let source = self.member.get_mut();
+        let trailer = read_bytes::<8>(source)?;
+        if u32::from_le_bytes(trailer[..4].try_into().unwrap()) != self.crc {
+            return Err(invalid_data("checksum mismatch"));
+        }
+        if u32::from_le_bytes(trailer[4..].try_into().unwrap()) != self.size {
+            return Err(invalid_data("size mismatch"));
+        }
+        if source.fill_buf()?.is_empty() {
+            self.is_finished = true;
+        } else {
+            read_header(source)?;
+            self.member.reset();
+            self.crc = 0;
+            self.size = 0;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for GzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.is_finished && !buf.is_empty() {
+            let count = self.member.read(buf)?;
+            if count > 0 {
+                self.crc = update_crc32(self.crc, &buf[..count]);
+                self.size = self.size.wrapping_add(count as u32);
+                return Ok(count);
+            }
+            self.end_member()?;
+        }
+        Ok(0)
+    }
+}
+
+/// Compresses data written to it into gzip data, which is complete once the encoder is
+/// finished.
+pub struct GzipEncoder<W: Write> {
+    deflater: Deflater<W>,
+    crc: u32,
+    size: u32,
+    is_finished: bool,
+}
+
+impl<W: Write> GzipEncoder<W> {
+    pub fn new(mut sink: W) -> io::Result<Self> {
+        sink.write_all(&MAGIC)?;
+        // No flags, no modification time and no extra flags.
+        sink.write_all(&[DEFLATE, 0, 0, 0, 0, 0, 0, UNKNOWN_SYSTEM])?;
+        Ok(Self {
+            deflater: Deflater::new(sink),
+            crc: 0,
+            size: 0,
+            is_finished: false,
+        })
+    }
+
+    /// Compresses the rest of the data and ends the compressed stream; further data cannot
+    /// be written then.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.is_finished {
+            self.is_finished = true;
+            self.deflater.finish()?;
+            let sink = self.deflater.get_mut();
+            sink.write_all(&self.crc.to_le_bytes())?;
+            sink.write_all(&self.size.to_le_bytes())?;
+        }
+        self.deflater.get_mut().flush()
+    }
+}
+
+impl<W: Write> Write for GzipEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.deflater.write(buf)?;
+        self.crc = update_crc32(self.crc, &buf[..count]);
+        self.size = self.size.wrapping_add(count as u32);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflater.get_mut().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of `gzip -n` for "hello\n" and "world\n".
+    const HELLO: [u8; 26] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0xe7, 0x02, 0x00, 0x20, 0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00,
+    ];
+    const WORLD: [u8; 26] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x2b, 0xcf, 0x2f, 0xca, 0x49,
+        0xe1, 0x02, 0x00, 0xa8, 0x61, 0x38, 0xdd, 0x06, 0x00, 0x00, 0x00,
+    ];
+
+    fn decompress(data: &[u8]) -> io::Result<String> {
+        let mut text = String::new();
+        GzipDecoder::new(data)?.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    #[test]
+    fn members_are_decompressed() {
+        assert_eq!(decompress(&HELLO).unwrap(), "hello\n");
+        assert_eq!(decompress(&[HELLO, WORLD].concat()).unwrap(), "hello\nworld\n");
+    }
+
+    #[test]
+    fn optional_header_fields_are_skipped() {
+        let mut data = HELLO[..10].to_vec();
+        data[3] = EXTRA | NAME | COMMENT | HEADER_CRC;
+        data.extend_from_slice(&[2, 0, b'x', b'y']);
+        data.extend_from_slice(b"hello.txt\0a comment\0");
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(&HELLO[10..]);
+        assert_eq!(decompress(&data).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn corrupt_data_is_rejected() {
+        let mut data = HELLO;
+        data[18] ^= 1;
+        let error = decompress(&data).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid gzip data: checksum mismatch.");
+        assert!(decompress(&HELLO[..20]).is_err());
+        assert!(decompress(b"hello").is_err());
+        assert!(decompress(&[HELLO.as_slice(), b"x"].concat()).is_err());
+    }
+
+    #[test]
+    fn compressed_data_is_decompressed_back() {
+        let text = "Solidify merges tables.\n".repeat(5000);
+        let mut encoder = GzipEncoder::new(vec![]).unwrap();
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        assert!(encoder.write_all(b"late").is_err());
+        let data = encoder.deflater.get_mut().clone();
+        assert!(data.len() < text.len() / 10);
+        assert_eq!(decompress(&data).unwrap(), text.repeat(2));
+    }
+}
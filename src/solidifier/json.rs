@@ -0,0 +1,717 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::strings::json::Json;
+
+use super::delimited::RecordWrite;
+
+const EXTENSIONS: [&str; 3] = ["json", "ndjson", "jsonl"];
+const LINE_EXTENSIONS: [&str; 2] = ["ndjson", "jsonl"];
+/// Depth of nested arrays and objects beyond which a document is refused.
+const MAX_DEPTH: usize = 512;
+/// Separator of the keys of nested objects in the names of flattened columns.
+const KEY_SEPARATOR: char = '.';
+
+enum Value {
+    Null,
+    Boolean(bool),
+    /// A number as written in the document.
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+/// Kind of the value a column of a record read from JSON data comes from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ValueKind {
+    /// The object has no such key.
+    Missing,
+    Null,
+    /// A number, a boolean or an array, which the value is the JSON text of.
+    Literal,
+    String,
+}
+
+/// Structure of JSON data read into records, which they can be written back in.
+#[derive(Debug)]
+pub struct JsonLayout {
+    /// Whether the objects are elements of an array rather than a sequence.
+    pub is_array: bool,
+    /// Path of keys leading to the values of each column.
+    paths: Vec<Vec<String>>,
+    /// Kinds of the values of each data record.
+    kinds: Vec<Vec<ValueKind>>,
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()?.to_str().map(str::to_ascii_lowercase)
+}
+
+/// Checks whether an input is JSON, by the extension of its path or, if it has none,
+/// by its first characters.
+pub fn is_json(path: &Path, header: &[u8]) -> bool {
+    match extension(path) {
+        Some(_) => has_json_extension(path),
+        None => matches!(
+            header.iter().find(|byte| !byte.is_ascii_whitespace()),
+            Some(b'[' | b'{'),
+        ),
+    }
+}
+
+pub fn has_json_extension(path: &Path) -> bool {
+    extension(path).is_some_and(|extension| EXTENSIONS.contains(&extension.as_str()))
+}
+
+/// Checks whether a path has the extension of JSON with an object per line.
+pub fn has_json_lines_extension(path: &Path) -> bool {
+    extension(path).is_some_and(|extension| LINE_EXTENSIONS.contains(&extension.as_str()))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: &str) -> Result<T> {
+        let before = &self.text[..self.position];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+        bail!("Invalid JSON at line {line}, column {column}: {message}.")
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    fn is_at_end(&mut self) -> bool {
+        self.skip_whitespace();
+        self.position == self.text.len()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() != Some(byte) {
+            return self.error(&format!("expected '{}'", byte as char));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    /// Consumes the given byte if it is next.
+    fn accept(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        let is_next = self.peek() == Some(byte);
+        if is_next {
+            self.position += 1;
+        }
+        is_next
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            return self.error("the data is nested too deeply");
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => {
+                self.position += 1;
+                let mut members = vec![];
+                if !self.accept(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        if self.peek() != Some(b'"') {
+                            return self.error("expected a key");
+                        }
+                        let key = self.string()?;
+                        self.expect(b':')?;
+                        members.push((key, self.value(depth + 1)?));
+                        if self.accept(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::Object(members))
+            },
+            Some(b'[') => {
+                self.position += 1;
+                let mut elements = vec![];
+                if !self.accept(b']') {
+                    loop {
+                        elements.push(self.value(depth + 1)?);
+                        if self.accept(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                Ok(Value::Array(elements))
+            },
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => {
+                for (literal, value) in [
+                    ("null", Value::Null),
+                    ("true", Value::Boolean(true)),
+                    ("false", Value::Boolean(false)),
+                ] {
+                    if self.text[self.position..].starts_with(literal) {
+                        self.position += literal.len();
+                        return Ok(value);
+                    }
+                }
+                self.error("expected a value")
+            },
+        }
+    }
+
+    fn number(&mut self) -> Result<Value> {
+        let start = self.position;
+        let bytes = self.text.as_bytes();
+        let digits = |position: &mut usize| {
+            let start = *position;
+            while bytes.get(*position).is_some_and(u8::is_ascii_digit) {
+                *position += 1;
+            }
+            *position > start
+        };
+        let mut position = self.position;
+        if bytes[position] == b'-' {
+            position += 1;
+        }
+        let mut is_valid = digits(&mut position);
+        if bytes.get(position) == Some(&b'.') {
+            position += 1;
+            is_valid &= digits(&mut position);
+        }
+        if matches!(bytes.get(position), Some(b'e' | b'E')) {
+            position += 1;
+            if matches!(bytes.get(position), Some(b'+' | b'-')) {
+                position += 1;
+            }
+            is_valid &= digits(&mut position);
+        }
+        if !is_valid {
+            return self.error("invalid number");
+        }
+        self.position = position;
+        Ok(Value::Number(String::from(&self.text[start..position])))
+    }
+
+    fn hex_unit(&mut self) -> Result<u16> {
+        let unit = self.text.get(self.position..self.position + 4)
+            .filter(|digits| digits.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .and_then(|digits| u16::from_str_radix(digits, 16).ok());
+        match unit {
+            Some(unit) => {
+                self.position += 4;
+                Ok(unit)
+            },
+            None => self.error("invalid escape sequence"),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.position += 1;
+        let mut string = String::new();
+        loop {
+            let rest = &self.text[self.position..];
+            let Some(end) = rest.find(['"', '\\']) else {
+                return self.error("unterminated string");
+            };
+            if rest[..end].contains(|char: char| char < ' ') {
+                return self.error("unescaped control character in a string");
+            }
+            string.push_str(&rest[..end]);
+            self.position += end + 1;
+            if rest.as_bytes()[end] == b'"' {
+                return Ok(string);
+            }
+            let escaped = match self.peek() {
+                Some(b'"') => '"',
+                Some(b'\\') => '\\',
+                Some(b'/') => '/',
+                Some(b'b') => '\u{8}',
+                Some(b'f') => '\u{c}',
+                Some(b'n') => '\n',
+                Some(b'r') => '\r',
+                Some(b't') => '\t',
+                Some(b'u') => {
+                    self.position += 1;
+                    let unit = self.hex_unit()?;
+                    let code = if (0xd800..0xdc00).contains(&unit)
+                        && self.text[self.position..].starts_with("\\u") {
+                        self.position += 2;
+                        let low = self.hex_unit()?;
+                        if !(0xdc00..0xe000).contains(&low) {
+                            return self.error("invalid surrogate pair");
+                        }
+                        0x10000 + ((u32::from(unit) - 0xd800) << 10) + (u32::from(low) - 0xdc00)
+                    } else {
+                        u32::from(unit)
+                    };
+                    string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    continue;
+                },
+                _ => return self.error("invalid escape sequence"),
+            };
+            string.push(escaped);
+            self.position += 1;
+        }
+    }
+}
+
+/// Writes a value as compact JSON.
+fn write_value(json: &mut String, value: &Value) {
+    match value {
+        Value::Null => json.push_str("null"),
+        Value::Boolean(value) => json.push_str(if *value { "true" } else { "false" }),
+        Value::Number(number) => json.push_str(number),
+        Value::String(string) => json.push_str(&string.to_json()),
+        Value::Array(elements) => {
+            json.push('[');
+            for (index, element) in elements.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                write_value(json, element);
+            }
+            json.push(']');
+        },
+        Value::Object(members) => {
+            json.push('{');
+            for (index, (key, member)) in members.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                json.push_str(&key.to_json());
+                json.push(':');
+                write_value(json, member);
+            }
+            json.push('}');
+        },
+    }
+}
+
+/// Flattens the members of an object into (path of keys, value) pairs: nested objects
+/// become the paths of keys leading to their members, arrays are kept as JSON and null
+/// values become empty.
+fn flatten(
+    members: Vec<(String, Value)>,
+    prefix: &[String],
+    fields: &mut Vec<(Vec<String>, String, ValueKind)>,
+) {
+    for (key, value) in members {
+        let path = [prefix, &[key]].concat();
+        let (value, kind) = match value {
+            Value::Object(members) => {
+                flatten(members, &path, fields);
+                continue;
+            },
+            Value::Null => (String::new(), ValueKind::Null),
+            Value::Boolean(value) => (value.to_string(), ValueKind::Literal),
+            Value::Number(number) => (number, ValueKind::Literal),
+            Value::String(string) => (string, ValueKind::String),
+            array @ Value::Array(_) => {
+                let mut json = String::new();
+                write_value(&mut json, &array);
+                (json, ValueKind::Literal)
+            },
+        };
+        fields.push((path, value, kind));
+    }
+}
+
+/// Names the column of a path of keys, joining them with `KEY_SEPARATOR`.
+fn column_name(path: &[String]) -> String {
+    path.join(&KEY_SEPARATOR.to_string())
+}
+
+/// Describes a path of keys in messages, e.g. `"a" > "b"` for the member `b` of the
+/// nested object `a`.
+fn describe_path(path: &[String]) -> String {
+    path.iter().map(|key| key.to_json()).collect::<Vec<_>>().join(" > ")
+}
+
+/// Reads JSON data, either an array of objects or a sequence of objects (such as one object
+/// per line), into records starting with a header row of the flattened keys of all the
+/// objects in the order they first appear in, along with the layout of the data.
+pub fn read_json(text: &str) -> Result<(Vec<Vec<String>>, JsonLayout)> {
+    let mut parser = Parser {
+        text,
+        position: 0,
+    };
+    let mut objects = vec![];
+    let mut is_array = false;
+    if parser.accept(b'[') {
+        is_array = true;
+        if !parser.accept(b']') {
+            loop {
+                objects.push(parser.value(1)?);
+                if parser.accept(b']') {
+                    break;
+                }
+                parser.expect(b',')?;
+            }
+        }
+        if !parser.is_at_end() {
+            return parser.error("unexpected data after the array");
+        }
+    } else {
+        while !parser.is_at_end() {
+            objects.push(parser.value(0)?);
+        }
+    }
+    let mut columns: HashMap<String, (usize, Vec<String>)> = HashMap::new();
+    let mut header = vec![];
+    let mut paths = vec![];
+    let mut records = vec![];
+    let mut kinds = vec![];
+    for (index, object) in objects.into_iter().enumerate() {
+        let Value::Object(members) = object else {
+            bail!(
+                "{element} #{number} is not an object.",
+                element = if is_array { "Array element" } else { "Value" },
+                number = index + 1,
+            );
+        };
+        let mut fields = vec![];
+        flatten(members, &[], &mut fields);
+        let mut record = vec![];
+        let mut record_kinds = vec![];
+        for (path, value, kind) in fields {
+            let (column, column_path) = columns.entry(column_name(&path)).or_insert_with_key(
+                |name| {
+                    header.push(name.clone());
+                    paths.push(path.clone());
+                    (header.len() - 1, path.clone())
+                },
+            );
+            ensure!(
+                *column_path == path,
+                "The keys {first} and {second} would both become column '{name}'.",
+                first = describe_path(column_path),
+                second = describe_path(&path),
+                name = header[*column],
+            );
+            let column = *column;
+            if record.len() <= column {
+                record.resize(column + 1, String::new());
+                record_kinds.resize(column + 1, ValueKind::Missing);
+            }
+            record[column] = value;
+            record_kinds[column] = kind;
+        }
+        records.push(record);
+        kinds.push(record_kinds);
+    }
+    for (record, record_kinds) in records.iter_mut().zip(&mut kinds) {
+        record.resize(header.len(), String::new());
+        record_kinds.resize(header.len(), ValueKind::Missing);
+    }
+    ensure!(!header.is_empty() || records.is_empty(), "The objects have no keys.");
+    records.insert(0, header);
+    Ok((records, JsonLayout {
+        is_array,
+        paths,
+        kinds,
+    }))
+}
+
+/// Writes the members of an object given by the paths of keys leading to them (within
+/// the object) and their values as JSON, nesting the members with common leading keys.
+fn write_members(json: &mut String, members: &[(&[String], &str)]) {
+    json.push('{');
+    for (index, (path, value)) in members.iter().enumerate() {
+        let key = &path[0];
+        if members[..index].iter().any(|(another_path, _)| another_path[0] == *key) {
+            continue;
+        }
+        if json.len() > 1 && !json.ends_with('{') {
+            json.push(',');
+        }
+        json.push_str(&key.to_json());
+        json.push(':');
+        if path.len() == 1 {
+            json.push_str(value);
+        } else {
+            let nested: Vec<_> = members[index..].iter().filter(
+                |(another_path, _)| another_path.len() > 1 && another_path[0] == *key
+            ).map(|(another_path, value)| (&another_path[1..], *value)).collect();
+            write_members(json, &nested);
+        }
+    }
+    json.push('}');
+}
+
+/// Writes records read from JSON data back as JSON in their original form: nested objects
+/// are restored, values keep their types, and keys that objects lack are left out.
+pub struct SourceWriter<W: Write> {
+    sink: W,
+    layout: JsonLayout,
+    row_count: usize,
+}
+
+impl<W: Write> SourceWriter<W> {
+    pub fn new(sink: W, layout: JsonLayout) -> Self {
+        Self {
+            sink,
+            layout,
+            row_count: 0,
+        }
+    }
+
+    /// Writes a data record given along with its (0-based) index among the data records.
+    pub fn write(&mut self, index: usize, record: &[String]) -> Result<()> {
+        let kinds = self.layout.kinds.get(index).context("The record is not part of the data.")?;
+        let values: Vec<_> = record.iter().zip(kinds).map(|(value, kind)| match kind {
+            ValueKind::Missing => None,
+            ValueKind::Null => Some(String::from("null")),
+            ValueKind::Literal => Some(value.clone()),
+            ValueKind::String => Some(value.to_json()),
+        }).collect();
+        let members: Vec<_> = self.layout.paths.iter().zip(&values).filter_map(
+            |(path, value)| value.as_deref().map(|value| (path.as_slice(), value))
+        ).collect();
+        let mut json = String::new();
+        if self.layout.is_array {
+            json.push_str(if self.row_count == 0 { "[\n  " } else { ",\n  " });
+        }
+        write_members(&mut json, &members);
+        if !self.layout.is_array {
+            json.push('\n');
+        }
+        self.sink.write_all(json.as_bytes())?;
+        self.row_count += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        if self.layout.is_array {
+            self.sink.write_all(if self.row_count == 0 { b"[]\n" } else { b"\n]\n" })?;
+        }
+        self.sink.flush()?;
+        Ok(())
+    }
+}
+
+/// Creates a writer of JSON objects, either in an array or one per line.
+pub fn json_writer<'a, W: Write + 'a>(
+    sink: W,
+    is_lines: bool,
+    has_header: bool,
+) -> Box<dyn RecordWrite + 'a> {
+    Box::new(Writer::new(sink, is_lines, has_header))
+}
+
+/// Writes records as JSON objects (either in an array or one per line), keyed by the header
+/// row (the first record written) or, without one, by the 1-based column numbers. Values
+/// are written as strings and missing values of merged rows as null.
+pub struct Writer<W: Write> {
+    sink: W,
+    is_lines: bool,
+    has_header: bool,
+    keys: Option<Vec<String>>,
+    row_count: usize,
+    is_finished: bool,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(sink: W, is_lines: bool, has_header: bool) -> Self {
+        Self {
+            sink,
+            is_lines,
+            has_header,
+            keys: None,
+            row_count: 0,
+            is_finished: false,
+        }
+    }
+
+    /// Sets the keys of the objects, making repeated (or empty) column names unique.
+    fn set_keys(&mut self, header: &[&str]) {
+        let mut keys: Vec<String> = vec![];
+        for (index, name) in header.iter().enumerate() {
+            let name = if name.is_empty() { (index + 1).to_string() } else { String::from(*name) };
+            let mut key = name.clone();
+            let mut suffix = 1;
+            while keys.contains(&key) {
+                suffix += 1;
+                key = format!("{name}_{suffix}");
+            }
+            keys.push(key);
+        }
+        self.keys = Some(keys);
+    }
+
+    fn write_object(&mut self, values: &[Option<&str>]) -> Result<()> {
+        ensure!(!self.is_finished, "The JSON data is already complete.");
+        let keys = self.keys.get_or_insert_with(Vec::new);
+        while keys.len() < values.len() {
+            keys.push((keys.len() + 1).to_string());
+        }
+        let mut json = String::new();
+        if !self.is_lines {
+            json.push_str(if self.row_count == 0 { "[\n  " } else { ",\n  " });
+        }
+        json.push('{');
+        for (index, (key, value)) in keys.iter().zip(values).enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            json.push_str(&key.to_json());
+            json.push(':');
+            match value {
+                Some(value) => json.push_str(&value.to_json()),
+                None => json.push_str("null"),
+            }
+        }
+        json.push('}');
+        if self.is_lines {
+            json.push('\n');
+        }
+        self.sink.write_all(json.as_bytes())?;
+        self.row_count += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordWrite for Writer<W> {
+    fn write_record(&mut self, record: &[&str]) -> Result<()> {
+        if self.has_header && self.keys.is_none() {
+            self.set_keys(record);
+            return Ok(());
+        }
+        self.write_object(&record.iter().map(|value| Some(*value)).collect::<Vec<_>>())
+    }
+
+    fn write_row(&mut self, row: &[Option<&str>], _filler: &str) -> Result<()> {
+        self.write_object(row)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.is_finished && !self.is_lines {
+            self.sink.write_all(if self.row_count == 0 { b"[]\n" } else { b"\n]\n" })?;
+        }
+        self.is_finished = true;
+        self.sink.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter().map(|row| row.iter().map(|value| value.to_string()).collect()).collect()
+    }
+
+    fn write(records: &[&[Option<&str>]], is_lines: bool) -> String {
+        let mut data = vec![];
+        let mut writer = Writer::new(&mut data, is_lines, true);
+        writer.write_record(&["id", "n", "id"]).unwrap();
+        for record in records {
+            writer.write_row(record, "").unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+        String::from_utf8(data).unwrap()
+    }
+
+    #[test]
+    fn arrays_and_sequences_of_objects_are_read() {
+        let expected = strings(&[&["a", "b"], &["1", ""], &["", "x"]]);
+        assert_eq!(read_json(r#"[{"a": 1}, {"b": "x"}]"#).unwrap().0, expected);
+        assert_eq!(read_json("{\"a\": 1}\n{\"b\": \"x\"}\n").unwrap().0, expected);
+        assert_eq!(read_json(" [ ] ").unwrap().0, vec![Vec::<String>::new()]);
+    }
+
+    #[test]
+    fn values_are_flattened() {
+        let text = r#"{"a": {"b": -1.5e3, "c": {"d": true}}, "e": null, "f": [1, {"g": "h"}]}"#;
+        assert_eq!(read_json(text).unwrap().0, strings(&[
+            &["a.b", "a.c.d", "e", "f"],
+            &["-1.5e3", "true", "", r#"[1,{"g":"h"}]"#],
+        ]));
+    }
+
+    #[test]
+    fn strings_are_unescaped() {
+        let text = r#"{"k": "\"\\\/\b\f\n\r\t\u00e9\ud83e\udd80 é"}"#;
+        assert_eq!(read_json(text).unwrap().0[1][0], "\"\\/\u{8}\u{c}\n\r\té🦀 é");
+    }
+
+    #[test]
+    fn colliding_column_names_are_rejected() {
+        let error = read_json(r#"{"a": {"b": 1}, "a.b": 2}"#).unwrap_err().to_string();
+        assert_eq!(error, r#"The keys "a" > "b" and "a.b" would both become column 'a.b'."#);
+        assert!(read_json("{\"a.b\": 1}\n{\"a\": {\"b\": 2}}").is_err());
+        assert!(read_json(r#"{"a": 1, "a": 2}"#).is_ok());
+    }
+
+    #[test]
+    fn invalid_data_is_reported_with_its_position() {
+        for (text, message) in [
+            ("[{\"a\": 1},\n {\"a\": 1x}]", "line 2, column 9: expected ','"),
+            (r#"{"a": "b}"#, "line 1, column 8: unterminated string"),
+            (r#"{"a": 1e}"#, "line 1, column 7: invalid number"),
+            (r#"{"a": "\x"}"#, "line 1, column 9: invalid escape sequence"),
+            (r#"[1] 2"#, "line 1, column 5: unexpected data after the array"),
+        ] {
+            let error = read_json(text).unwrap_err().to_string();
+            assert!(error.ends_with(&format!("{message}.")), "{text}: {error}");
+        }
+        assert_eq!(read_json("[1]").unwrap_err().to_string(), "Array element #1 is not an object.");
+        let deep = format!("{{\"a\": {}{}}}", "[".repeat(MAX_DEPTH + 1), "]".repeat(MAX_DEPTH + 1));
+        assert!(read_json(&deep).unwrap_err().to_string().contains("nested too deeply"));
+    }
+
+    #[test]
+    fn values_are_written_as_strings() {
+        assert_eq!(
+            write(&[&[Some("1"), Some("x"), None], &[Some("007"), Some("true"), Some("")]], false),
+            concat!(
+                "[\n  {\"id\":\"1\",\"n\":\"x\",\"id_2\":null},",
+                "\n  {\"id\":\"007\",\"n\":\"true\",\"id_2\":\"\"}\n]\n",
+            ),
+        );
+        assert_eq!(write(&[&[Some("1.5")]], true), "{\"id\":\"1.5\"}\n");
+        assert_eq!(write(&[], false), "[]\n");
+    }
+
+    #[test]
+    fn records_are_written_back_in_their_original_form() {
+        let text = concat!(
+            r#"{"id": 1, "name": {"first": "A", "last": null}, "tags": ["x"]}"#, "\n",
+            r#"{"name": {"first": "2"}, "id": "007", "ok": false}"#, "\n",
+        );
+        let (records, layout) = read_json(text).unwrap();
+        let mut sink = vec![];
+        let mut writer = SourceWriter::new(&mut sink, layout);
+        writer.write(1, &records[2]).unwrap();
+        writer.write(0, &records[1]).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(String::from_utf8(sink).unwrap(), concat!(
+            r#"{"id":"007","name":{"first":"2"},"ok":false}"#, "\n",
+            r#"{"id":1,"name":{"first":"A","last":null},"tags":["x"]}"#, "\n",
+        ));
+        let (records, layout) = read_json(r#"[{"a": {"b": 1}}]"#).unwrap();
+        let mut sink = vec![];
+        let mut writer = SourceWriter::new(&mut sink, layout);
+        writer.write(0, &records[1]).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(String::from_utf8(sink).unwrap(), "[\n  {\"a\":{\"b\":1}}\n]\n");
+    }
+}
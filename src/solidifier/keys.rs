@@ -1,8 +1,11 @@
+use std::borrow::Cow;
 use std::fmt::{Display, Formatter};
 
+use crate::strings::json::Json;
 use crate::strings::literally::Literally;
 
-#[derive(Hash, Eq, PartialEq)]
+/// Identifies a data record (not counting the header row) by 0-based indices.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
 pub struct RecordId {
     pub input_index: usize,
     pub row_index: usize,
@@ -18,16 +21,61 @@ impl Display for RecordId {
     }
 }
 
+impl Json for RecordId {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"input\": {input}, \"record\": {record}}}",
+            input = self.input_index + 1,
+            record = self.row_index + 1,
+        )
+    }
+}
+
+/// Owned counterpart of a key item: either the (normalized) value of a shared column
+/// or, for the unique column 0, the record itself.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum KeyValue {
+    Data(String),
+    Unique(RecordId),
+}
+
+impl Display for KeyValue {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            KeyValue::Data(data) => write!(f, "{data}"),
+            KeyValue::Unique(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+impl Json for KeyValue {
+    fn to_json(&self) -> String {
+        match self {
+            KeyValue::Data(data) => data.to_json(),
+            KeyValue::Unique(id) => id.to_json(),
+        }
+    }
+}
+
+/// Describes a key given by its items.
+pub fn describe_key<T: Display>(items: &[T]) -> String {
+    if items.is_empty() {
+        "empty set of columns".literally()
+    } else {
+        items.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(", ")
+    }
+}
+
 #[derive(Hash, Eq, PartialEq)]
 pub enum KeyItem<'a, 'b> {
-    Data(&'a str),
+    Data(Cow<'a, str>),
     Id(&'b RecordId),
 }
 
 impl<'a, 'b> Display for KeyItem<'a, 'b> {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         write!(f, "{}", match self {
-            KeyItem::Data(data) => format!("{}", data),
+            KeyItem::Data(data) => data.to_string(),
             KeyItem::Id(id) => format!("{}", id),
         })
     }
@@ -44,6 +92,13 @@ impl<'a, 'b> Key<'a, 'b> {
             rows,
         }
     }
+
+    pub fn values(&self) -> Vec<KeyValue> {
+        self.rows.iter().map(|item| match item {
+            KeyItem::Data(data) => KeyValue::Data(data.to_string()),
+            KeyItem::Id(id) => KeyValue::Unique(**id),
+        }).collect()
+    }
 }
 
 impl<'a, 'b, 'c> IntoIterator for &'c Key<'a, 'b> {
@@ -51,16 +106,12 @@ impl<'a, 'b, 'c> IntoIterator for &'c Key<'a, 'b> {
     type IntoIter = std::vec::IntoIter<Self::Item>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.rows.iter().map(|row| row).collect::<Vec<_>>().into_iter()
+        self.rows.iter().collect::<Vec<_>>().into_iter()
     }
 }
 
 impl<'a, 'b> Display for Key<'a, 'b> {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", if self.rows.is_empty() {
-            "empty set of columns".literally()
-        } else {
-            self.rows.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(", ")
-        })
+        write!(f, "{}", describe_key(&self.rows))
     }
 }
@@ -1,70 +1,267 @@
+mod aggregate;
+pub mod builder;
+mod coalesce;
+mod compression;
+mod delimited;
+mod delimiters;
+mod encodings;
+pub mod diagnostics;
+mod external;
+mod gzip;
+mod json;
 mod keys;
+mod normalizers;
+mod output;
+mod report;
 mod sheet;
+mod similarity;
+mod sorted;
+mod spreadsheet;
+mod unmatched;
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{stdin, BufRead, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::Path;
 
-use anyhow::{Context, ensure, Result};
-use csv;
-use edit_distance::edit_distance;
+use anyhow::{anyhow, bail, Context, ensure, Error, Result};
 
-use crate::params::Params;
+use crate::params::{
+    Delimiter,
+    Encoding,
+    Join,
+    MergeParams,
+    MultiMerge,
+    OutputFormat,
+    Params,
+    ProvenanceColumn,
+    STANDARD_STREAM,
+};
 use crate::strings::countable::Countable;
-use crate::warnings::warn;
 
-use keys::{Key, KeyItem};
-use sheet::{Sheet, SheetRow, SheetRowSection};
+use aggregate::{aggregate, ensure_aggregated_columns_exist, InputRecord};
+use delimited::{as_optional_strs, as_strs, record_writer, records, Records, RecordWrite};
+use coalesce::{
+    apply as apply_layout,
+    coalesce,
+    ensure_coalesced_columns_exist,
+    layout,
+    OutputColumn,
+};
+use compression::{decompressed, uncompressed_path};
+use delimiters::{detect_delimiter, read_sample};
+use encodings::{encoded, Decoder};
+use json::{
+    has_json_extension,
+    has_json_lines_extension,
+    is_json,
+    json_writer,
+    read_json,
+    JsonLayout,
+};
+pub use keys::{KeyValue, RecordId};
 
-fn read(path: &PathBuf, params: &Params) -> Result<Vec<Vec<String>>> {
-    let mut reader = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .delimiter(params.delimiter)
-        .from_path(path)
-        .with_context(|| format!("Could not open {}.", path.display()))?;
+use diagnostics::{Diagnostic, DiagnosticKind, DiagnosticSink};
+use keys::Key;
+use output::OutputFile;
+use report::Reporter;
+use sheet::{RaggedRecord, Sheet, SheetRow, SheetRowSection};
+use similarity::{find_similar, DeletionIndex};
+use sorted::solidify_sorted;
+use spreadsheet::{has_xlsx_extension, is_spreadsheet, read_spreadsheet, xlsx_writer};
+use unmatched::UnmatchedWriter;
+
+/// Delimiter of inputs that are not delimited text (spreadsheets and JSON) when none
+/// is specified.
+const IMPLIED_DELIMITER: &str = "\t";
+
+fn is_standard_stream(path: &Path) -> bool {
+    path.as_os_str() == STANDARD_STREAM
+}
+
+/// Describes an input in messages.
+fn input_name(path: &Path) -> String {
+    if is_standard_stream(path) {
+        String::from("stdin")
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// Describes the output in messages.
+fn output_name(path: &Path) -> String {
+    if is_standard_stream(path) {
+        String::from("stdout")
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// Returns the delimiter of an input that is not delimited text: a tab unless one is specified.
+fn implied_delimiter(params: &Params) -> String {
+    match &params.delimiter {
+        Delimiter::Auto => String::from(IMPLIED_DELIMITER),
+        Delimiter::Fixed(delimiter) => delimiter.clone(),
+    }
+}
+
+/// Format and encoding of an input, which its unmatched records are written in.
+pub enum InputFormat {
+    Delimited {
+        encoding: Encoding,
+        has_bom: bool,
+    },
+    Spreadsheet,
+    Json {
+        encoding: Encoding,
+        has_bom: bool,
+        layout: JsonLayout,
+    },
+}
+
+/// Opens an input (stdin for `-`), decompressing and decoding it if necessary, and returns
+/// its delimiter and format along with its records; the records of spreadsheets are those of
+/// the chosen worksheet, and those of JSON inputs start with a header row of their keys.
+fn open_input(
+    input: usize,
+    params: &Params,
+) -> Result<(String, InputFormat, Records<'static>)> {
+    let path = &params.inputs[input];
+    let source: Box<dyn Read + Send> = if is_standard_stream(path) {
+        Box::new(stdin())
+    } else {
+        let file = File::open(path)
+            .with_context(|| format!("Could not open {}.", path.display()))?;
+        Box::new(file)
+    };
+    let mut source = decompressed(source, path, &input_name(path))?;
+    let header = source.fill_buf()
+        .with_context(|| format!("Could not read {}.", input_name(path)))?;
+    if is_spreadsheet(&uncompressed_path(path), header) {
+        let mut data = vec![];
+        source.read_to_end(&mut data)
+            .with_context(|| format!("Could not read {}.", input_name(path)))?;
+        let rows = read_spreadsheet(&data, &params.worksheets[input])
+            .with_context(|| format!("Could not read {}.", input_name(path)))?;
+        let records = Box::new(rows.into_iter().map(Ok));
+        return Ok((implied_delimiter(params), InputFormat::Spreadsheet, records));
+    }
+    let mut decoder = Decoder::new(source, params.encodings[input], &params.merge.names.encoding);
+    let (encoding, has_bom) = decoder.detect_encoding()
+        .with_context(|| format!("Could not read {}.", input_name(path)))?;
+    let mut source: Box<dyn BufRead> = Box::new(BufReader::new(decoder));
+    let header = source.fill_buf()
+        .with_context(|| format!("Could not read {}.", input_name(path)))?;
+    if is_json(&uncompressed_path(path), header) {
+        ensure!(
+            params.merge.has_headers,
+            "{name} is JSON, whose keys form a header row, so {option} is required.",
+            name = input_name(path),
+            option = params.merge.names.has_headers,
+        );
+        let mut text = String::new();
+        source.read_to_string(&mut text)
+            .with_context(|| format!("Could not read {}.", input_name(path)))?;
+        let (rows, layout) = read_json(&text)
+            .with_context(|| format!("Could not read {}.", input_name(path)))?;
+        let format = InputFormat::Json {
+            encoding,
+            has_bom,
+            layout,
+        };
+        return Ok((implied_delimiter(params), format, Box::new(rows.into_iter().map(Ok))));
+    }
+    let delimiter = match &params.delimiter {
+        Delimiter::Auto => {
+            let sample = read_sample(&mut source)
+                .with_context(|| format!("Could not read {}.", input_name(path)))?;
+            let delimiter = detect_delimiter(
+                &uncompressed_path(path), &sample, &input_name(path),
+            )?;
+            source = Box::new(BufReader::new(Cursor::new(sample).chain(source)));
+            delimiter
+        },
+        Delimiter::Fixed(delimiter) => delimiter.clone(),
+    };
+    let records = records(source, &delimiter);
+    Ok((delimiter, InputFormat::Delimited { encoding, has_bom }, records))
+}
+
+fn read(records: Records, path: &Path) -> Result<Vec<Vec<String>>> {
     let mut data: Vec<Vec<String>> = vec![];
-    for (index, result) in reader.records().enumerate() {
+    for (index, result) in records.enumerate() {
         let record = result.with_context(
-            || format!("Could not process record #{} of {}.", index + 1, path.display())
+            || format!("Could not process record #{} of {}.", index + 1, input_name(path))
         )?;
-        data.push(record.iter().map(String::from).collect());
+        data.push(record);
     }
     Ok(data)
 }
 
-fn write(path: &PathBuf, rows: &[Vec<&str>], params: &Params) -> Result<()> {
-    let mut writer = csv::WriterBuilder::new()
-        .delimiter(params.delimiter)
-        .from_path(path)
-        .with_context(|| format!("Could not open {} for writing.", path.display()))?;
-    for row in rows {
-        writer.write_record(row).with_context(
-            || format!("Could not write data to {}.", path.display())
-        )?;
+/// Tells the format of written records by the extension of the path they end up in.
+fn format_by_extension(path: &Path) -> OutputFormat {
+    let path = uncompressed_path(path);
+    if has_xlsx_extension(&path) {
+        OutputFormat::Xlsx
+    } else if has_json_lines_extension(&path) {
+        OutputFormat::JsonLines
+    } else if has_json_extension(&path) {
+        OutputFormat::Json
+    } else {
+        OutputFormat::Delimited
     }
-    Ok(())
 }
 
-fn compare_strings(a: &str, b: &str) -> u32 {
-    edit_distance(a, b) as u32
+/// Creates a writer of records to the sink in the given format or, if none is given, in
+/// the format told by the extension of `path` (where the data ends up). Delimited text is
+/// written in the output encoding, JSON always in UTF-8.
+fn sink_writer(
+    sink: Box<dyn Write>,
+    path: &Path,
+    format: Option<OutputFormat>,
+    delimiter: &str,
+    params: &Params,
+) -> Result<Box<dyn RecordWrite>> {
+    let sink = BufWriter::new(sink);
+    Ok(match format.unwrap_or_else(|| format_by_extension(path)) {
+        OutputFormat::Delimited => {
+            let sink = encoded(sink, params.output_encoding, params.output_bom)
+                .with_context(|| format!("Could not write data to {}.", output_name(path)))?;
+            record_writer(sink, delimiter)
+        },
+        OutputFormat::Xlsx => xlsx_writer(sink, params.highlight_filler),
+        OutputFormat::Json => json_writer(sink, false, params.merge.has_headers),
+        OutputFormat::JsonLines => json_writer(sink, true, params.merge.has_headers),
+    })
 }
 
-fn compare_key_items(a: &KeyItem, b: &KeyItem) -> u32 {
-    match (a, b) {
-        (KeyItem::Data(a), KeyItem::Data(b)) => compare_strings(a, b),
-        _ => 0,
-    }
-}
+/// An output row, whose values either come from the inputs or are computed; values are
+/// missing (`None`) where none of the merged records has data for them.
+type Row<'a> = Vec<Option<Cow<'a, str>>>;
 
-fn compare_keys(a: &Key, b: &Key) -> u32 {
-    a.into_iter().zip(b.into_iter()).map(|(a, b)| compare_key_items(a, b)).sum()
+fn write(
+    mut output: OutputFile,
+    header: Option<&[Cow<str>]>,
+    rows: &[Row],
+    delimiter: &str,
+    params: &Params,
+) -> Result<()> {
+    let mut writer = output.writer(delimiter, params)?;
+    let error_context = || format!("Could not write data to {}.", output_name(output.path()));
+    if let Some(header) = header {
+        writer.write_record(&as_strs(header)).with_context(error_context)?;
+    }
+    for row in rows {
+        writer.write_row(&as_optional_strs(row), &params.merge.filler)
+            .with_context(error_context)?;
+    }
+    writer.flush().with_context(error_context)?;
+    drop(writer);
+    output.commit()
 }
 
-fn merge_row<'a>(data: &[(Option<&SheetRow<'a>>, &'a Sheet)], params: &'a Params) -> Vec<&'a str> {
-    let split: Vec<_> = data.iter().map(|(row, sheet)| if let Some(row) = row {
-        (row.split_by_key(), true)
-    } else {
-        (sheet.split_empty_by_key(&params.filler), false)
-    }).collect();
+fn merge_sections<T: Clone>(split: &[(Vec<SheetRowSection<T>>, bool)]) -> Vec<T> {
     if let Some((first, _)) = split.first() {
         let count = first.len();
         assert!(split.iter().all(|(row, _)| row.len() == count));
@@ -75,12 +272,10 @@ fn merge_row<'a>(data: &[(Option<&SheetRow<'a>>, &'a Sheet)], params: &'a Params
                 match &split_data[section_index] {
                     SheetRowSection::Key(value) => if *is_filled && !seen_filled {
                         seen_filled = true;
-                        result.push(*value);
+                        result.push(value.clone());
                     },
                     SheetRowSection::NonKey(values) => {
-                        for value in values {
-                            result.push(value);
-                        }
+                        result.extend(values.iter().cloned());
                     },
                 }
             }
@@ -91,14 +286,314 @@ fn merge_row<'a>(data: &[(Option<&SheetRow<'a>>, &'a Sheet)], params: &'a Params
     }
 }
 
-fn merge<'a>(data: &[(&Vec<&SheetRow<'a>>, &'a Sheet)], params: &'a Params) -> Vec<Vec<&'a str>> {
-    let max_length = data.iter().map(|(set, _)| set.len()).max().unwrap_or(0);
-    (0..max_length).map(|index| merge_row(&data.iter().map(
-        |&(set, sheet)| (set.get(index).map(|&v| v), sheet)
-    ).collect::<Vec<_>>(), params)).collect()
+fn provenance_name(column: ProvenanceColumn) -> &'static str {
+    match column {
+        ProvenanceColumn::Sources => "_sources",
+        ProvenanceColumn::Rows => "_rows",
+        ProvenanceColumn::Match => "_match",
+    }
+}
+
+fn provenance_value(column: ProvenanceColumn, records: &[Option<&InputRecord>]) -> String {
+    let ids = || records.iter().flatten().flat_map(|record| record.ids());
+    match column {
+        ProvenanceColumn::Sources => ids().map(
+            |id| (id.input_index + 1).to_string()
+        ).collect::<Vec<_>>().join(","),
+        ProvenanceColumn::Rows => ids().map(
+            |id| format!("{}:{}", id.input_index + 1, id.row_index + 1)
+        ).collect::<Vec<_>>().join(";"),
+        ProvenanceColumn::Match => String::from(
+            if records.iter().all(Option::is_some) { "full" } else { "partial" }
+        ),
+    }
+}
+
+fn merge_row<'a>(
+    data: &[(Option<&InputRecord<'a, '_>>, &'a Sheet)],
+    key: &Key,
+    layout: &[OutputColumn],
+    params: &'a MergeParams,
+    sink: &mut dyn DiagnosticSink,
+) -> Result<Row<'a>> {
+    let row: Row = merge_sections(&data.iter().map(|(record, sheet)| if let Some(record) = record {
+        (record.split_by_key(sheet).into_iter().map(|section| section.map(Some)).collect(), true)
+    } else {
+        (sheet.split_empty_by_key(None), false)
+    }).collect::<Vec<_>>());
+    let mut row = apply_layout(row, layout, |index, _| coalesce(index, data, key, params, sink))?;
+    let records: Vec<_> = data.iter().map(|(record, _)| *record).collect();
+    row.extend(params.provenance.iter().map(
+        |column| Some(Cow::Owned(provenance_value(*column, &records)))
+    ));
+    Ok(row)
+}
+
+fn merge_header<'a>(sheets: &'a [Sheet], params: &'a MergeParams) -> Vec<Cow<'a, str>> {
+    let header: Vec<_> = merge_sections(&sheets.iter().map(
+        |sheet| if let Some(header) = sheet.split_header_by_key() {
+            (header, true)
+        } else {
+            (sheet.split_empty_by_key(params.filler.as_str()), false)
+        }
+    ).collect::<Vec<_>>()).into_iter().map(Cow::Borrowed).collect();
+    let mut header = apply_layout(header, &layout(sheets), |_, value| Ok(value)).unwrap();
+    header.extend(params.provenance.iter().map(|column| Cow::Borrowed(provenance_name(*column))));
+    header
+}
+
+fn merge<'a>(
+    data: &[(&Vec<&SheetRow<'a>>, &'a Sheet)],
+    key: &Key,
+    layout: &[OutputColumn],
+    params: &'a MergeParams,
+    sink: &mut dyn DiagnosticSink,
+) -> Result<Vec<Row<'a>>> {
+    let mut records = vec![];
+    for &(set, sheet) in data {
+        records.push(match &params.aggregation {
+            Some(aggregation) if !set.is_empty() => {
+                vec![aggregate(set, sheet, aggregation, key, sink)?]
+            },
+            _ => set.iter().map(|row| InputRecord::Single(row)).collect(),
+        });
+    }
+    let positions = if let Some(MultiMerge::Cartesian) = params.multi_merge {
+        combinations(&records, key, params)?
+    } else {
+        let max_length = records.iter().map(Vec::len).max().unwrap_or(0);
+        (0..max_length).map(|index| vec![index; records.len()]).collect()
+    };
+    let mut rows = vec![];
+    for positions in positions {
+        let row_data: Vec<_> = records.iter().zip(data).zip(&positions).map(
+            |((records, &(_, sheet)), &position)| (records.get(position), sheet)
+        ).collect();
+        let is_present: Vec<_> = row_data.iter().map(|(record, _)| record.is_some()).collect();
+        if is_joined(&is_present, &params.join) {
+            rows.push(merge_row(&row_data, key, layout, params, sink)?);
+        }
+    }
+    Ok(rows)
+}
+
+/// Returns the positions (within each input's records) of every combination of the records
+/// of the inputs that have any, the last input varying fastest.
+fn combinations(
+    records: &[Vec<InputRecord>],
+    key: &Key,
+    params: &MergeParams,
+) -> Result<Vec<Vec<usize>>> {
+    let sizes: Vec<_> = records.iter().map(|records| records.len().max(1)).collect();
+    let count = sizes.iter().fold(1u128, |count, size| count.saturating_mul(*size as u128));
+    ensure!(
+        count <= params.max_combinations as u128,
+        "Merging the records with the key below would produce {count} records, which exceeds \
+         the maximum of {max} (see {option}):\n{key}",
+        max = params.max_combinations,
+        option = params.names.max_combinations,
+    );
+    Ok((0..count as usize).map(|mut number| {
+        let mut positions = vec![0; sizes.len()];
+        for (position, size) in positions.iter_mut().zip(&sizes).rev() {
+            *position = number % size;
+            number /= size;
+        }
+        positions
+    }).collect())
+}
+
+/// Checks whether a merged row is output, given which inputs contribute to it.
+fn is_joined(is_present: &[bool], join: &Join) -> bool {
+    match join {
+        Join::Inner => is_present.iter().all(|is_present| *is_present),
+        Join::Outer => true,
+        Join::Input(index) => is_present[*index],
+        Join::Anti => is_present[0] && !is_present[1..].iter().any(|is_present| *is_present),
+    }
+}
+
+fn have_common_inputs(a: &[Vec<&SheetRow>], b: &[Vec<&SheetRow>]) -> bool {
+    a.iter().zip(b).any(|(a, b)| !a.is_empty() && !b.is_empty())
+}
+
+fn record_ids(row_sets: &[&Vec<Vec<&SheetRow>>]) -> Vec<RecordId> {
+    let mut ids: Vec<_> = row_sets.iter().copied().flatten().flatten().map(
+        |row| row.id()
+    ).collect();
+    ids.sort_by_key(|id| (id.input_index, id.row_index));
+    ids
+}
+
+fn similarity_diagnostic(
+    key: &Key,
+    another_key: &Key,
+    records: Vec<RecordId>,
+    distance: u32,
+) -> Diagnostic {
+    Diagnostic {
+        kind: DiagnosticKind::Similar,
+        message: format!("Similar records encountered (edit distance = {distance}):"),
+        records,
+        keys: vec![key.values(), another_key.values()],
+        distance: Some(distance),
+    }
+}
+
+/// Finds the keys a key could be fuzzily merged with (those of records from other inputs),
+/// sorted by their distances and indices.
+fn fuzzy_candidates<'a, 'b, 'c>(
+    key: &Key,
+    unique_keys: &[&'c Key<'a, 'b>],
+    index: &DeletionIndex,
+    by_key: &HashMap<&'c Key<'a, 'b>, Vec<Vec<&'c SheetRow<'a>>>>,
+) -> Vec<(u32, usize, &'c Key<'a, 'b>)> {
+    let found = index.find(key, unique_keys);
+    let mut candidates: Vec<_> = found.into_iter().map(
+        |(index, distance)| (distance, index, unique_keys[index])
+    ).filter(|(_, _, another_key)| *another_key != key && by_key.get(another_key).is_some_and(
+        |row_sets| !have_common_inputs(&by_key[key], row_sets)
+    )).collect();
+    candidates.sort_by_key(|(distance, index, _)| (*distance, *index));
+    candidates
+}
+
+fn ambiguity_error<'a, 'b, 'c>(
+    key: &Key,
+    closest: &[&'c Key<'a, 'b>],
+    distance: u32,
+    by_key: &HashMap<&'c Key<'a, 'b>, Vec<Vec<&'c SheetRow<'a>>>>,
+    sink: &mut dyn DiagnosticSink,
+) -> Error {
+    let involved: Vec<_> = [key].into_iter().chain(closest.iter().map(|key| *key as &Key))
+        .collect();
+    let emitted = sink.emit(Diagnostic {
+        kind: DiagnosticKind::Ambiguous,
+        message: format!(
+            "Records that could be fuzzily merged in multiple ways encountered \
+             (edit distance = {distance}):",
+        ),
+        records: record_ids(&involved.iter().map(|key| &by_key[key]).collect::<Vec<_>>()),
+        keys: involved.iter().map(|key| key.values()).collect(),
+        distance: Some(distance),
+    });
+    if let Err(error) = emitted {
+        return error;
+    }
+    anyhow!(
+        "There are multiple ways to fuzzily merge records (edit distance = {distance}). \
+         The ambiguous record is:\n{key}\nIt could be merged with any of:\n{candidates}",
+        candidates = closest.iter().map(|key| key.to_string()).collect::<Vec<_>>().join("\n"),
+    )
+}
+
+fn find_fuzzy_match<'a, 'b, 'c>(
+    key: &'c Key<'a, 'b>,
+    unique_keys: &[&'c Key<'a, 'b>],
+    index: &DeletionIndex,
+    by_key: &HashMap<&'c Key<'a, 'b>, Vec<Vec<&'c SheetRow<'a>>>>,
+    sink: &mut dyn DiagnosticSink,
+) -> Result<Option<(u32, &'c Key<'a, 'b>)>> {
+    let candidates = fuzzy_candidates(key, unique_keys, index, by_key);
+    let Some(&(distance, _, _)) = candidates.first() else {
+        return Ok(None);
+    };
+    let closest: Vec<_> = candidates.iter().filter(
+        |(another_distance, _, _)| *another_distance == distance
+    ).map(|(_, _, another_key)| *another_key).collect();
+    if !closest.iter().enumerate().all(|(index, a)| closest[index + 1..].iter().all(
+        |b| !have_common_inputs(&by_key[*a], &by_key[*b])
+    )) {
+        return Err(ambiguity_error(key, &closest, distance, by_key, sink));
+    }
+    // The chosen key must not be as close to another one either, or which of them it is
+    // merged with would depend on the order of the records.
+    let chosen = closest[0];
+    let rivals: Vec<_> = fuzzy_candidates(chosen, unique_keys, index, by_key).into_iter().filter(
+        |(another_distance, _, another_key)| *another_distance <= distance && *another_key != key
+    ).map(|(_, _, another_key)| another_key).collect();
+    if !rivals.is_empty() {
+        let others: Vec<_> = [key].into_iter().chain(rivals).collect();
+        return Err(ambiguity_error(chosen, &others, distance, by_key, sink));
+    }
+    Ok(Some((distance, chosen)))
+}
+
+fn fuzzy_merge<'a, 'b, 'c>(
+    unique_keys: &[&'c Key<'a, 'b>],
+    by_key: &mut HashMap<&'c Key<'a, 'b>, Vec<Vec<&'c SheetRow<'a>>>>,
+    params: &MergeParams,
+    sink: &mut dyn DiagnosticSink,
+) -> Result<()> {
+    let index = DeletionIndex::new(unique_keys, params.fuzzy_merge_level);
+    for key in unique_keys {
+        if !by_key.contains_key(key) {
+            continue;
+        }
+        while let Some((distance, closest_key)) =
+            find_fuzzy_match(key, unique_keys, &index, by_key, sink)? {
+            let closest_row_sets = by_key.remove(closest_key).unwrap();
+            let row_sets = by_key.get_mut(*key).unwrap();
+            let records = record_ids(&[row_sets, &closest_row_sets]);
+            for (set, closest_set) in row_sets.iter_mut().zip(closest_row_sets) {
+                set.extend(closest_set);
+            }
+            sink.emit(Diagnostic {
+                kind: DiagnosticKind::FuzzyMerged,
+                message: format!(
+                    "Records with different keys merged (edit distance = {distance}):",
+                ),
+                records,
+                keys: vec![key.values(), closest_key.values()],
+                distance: Some(distance),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+fn find_similar_keys<'a, 'b, 'c>(
+    unique_keys: &[&'c Key<'a, 'b>],
+    params: &MergeParams,
+) -> HashMap<&'c Key<'a, 'b>, Vec<(&'c Key<'a, 'b>, u32)>> {
+    let similar = find_similar(unique_keys, params.similarity_warn_level);
+    unique_keys.iter().zip(similar).map(|(key, similar)| (*key, similar.into_iter().map(
+        |(index, distance)| (unique_keys[index], distance)
+    ).collect())).collect()
+}
+
+/// Merged rows along with the data records (and their indices) of each input
+/// that have no counterpart in some other input.
+struct Matched<'a> {
+    rows: Vec<Row<'a>>,
+    unmatched: Vec<Vec<(usize, &'a [String])>>,
+}
+
+/// Collects the records that are not merged with records of every other input; when
+/// records are aggregated or combined in every way, each of them is merged with all
+/// the records the other inputs have.
+fn collect_unmatched<'a>(
+    row_sets: &[Vec<&SheetRow<'a>>],
+    matches_all: bool,
+    unmatched: &mut [Vec<(usize, &'a [String])>],
+) {
+    for (index, set) in row_sets.iter().enumerate() {
+        for (position, row) in set.iter().enumerate() {
+            let position = if matches_all { 0 } else { position };
+            if row_sets.iter().enumerate().any(|(another_index, another_set)| {
+                another_index != index && another_set.len() <= position
+            }) {
+                unmatched[index].push((row.id().row_index, row.data()));
+            }
+        }
+    }
 }
 
-fn match_and_merge<'a>(sheets: &'a [Sheet], params: &'a Params) -> Result<Vec<Vec<&'a str>>> {
+fn match_and_merge<'a>(
+    sheets: &'a [Sheet],
+    params: &'a MergeParams,
+    sink: &mut dyn DiagnosticSink,
+) -> Result<Matched<'a>> {
     let rows: Vec<_> = sheets.iter().enumerate().flat_map(
         |(sheet_index, sheet)| sheet.into_iter().map(
             move |row| (row, sheet_index)
@@ -108,28 +603,76 @@ fn match_and_merge<'a>(sheets: &'a [Sheet], params: &'a Params) -> Result<Vec<Ve
     let mut by_key: HashMap<&Key, Vec<Vec<&SheetRow>>> = HashMap::new();
     for ((row, sheet_index), key) in rows.iter().zip(keys.iter()) {
         let entry = by_key.entry(key).or_insert_with(|| vec![vec![]; sheets.len()]);
-        entry[*sheet_index].push(&row);
+        entry[*sheet_index].push(row);
+    }
+    let mut seen = HashSet::new();
+    let unique_keys: Vec<_> = keys.iter().filter(|key| seen.insert(*key)).collect();
+    if params.fuzzy_merge_level > 0 {
+        fuzzy_merge(&unique_keys, &mut by_key, params, sink)?;
     }
+    let similar_keys = if params.similarity_warn_level > 0 {
+        let unique_keys: Vec<_> = unique_keys.into_iter().filter(
+            |key| by_key.contains_key(key)
+        ).collect();
+        find_similar_keys(&unique_keys, params)
+    } else {
+        HashMap::new()
+    };
+    let output_layout = layout(sheets);
     let mut merged = vec![];
+    let mut unmatched = vec![vec![]; sheets.len()];
     for key in &keys {
         if let Some(row_sets) = by_key.remove(key) {
-            ensure!(
-                params.allow_multi_merge
-                || row_sets.iter().all(|set| set.len() <= 1)
-                || row_sets.iter().filter(|set| set.len() > 0).count() <= 1,
-                "There are multiple ways to merge records. If this is intended, \
-                 consider passing the {flag} flag. The ambiguous record is:\n{key}",
-                flag = params.names.allow_multi_merge,
-            );
-            merged.append(&mut merge(&row_sets.iter().zip(sheets).collect::<Vec<_>>(), params));
-            if params.warn_unmatched && row_sets.iter().any(|set| set.len() != row_sets[0].len()) {
+            let is_aggregated = params.aggregation.is_some();
+            let matches_all = is_aggregated || params.multi_merge == Some(MultiMerge::Cartesian);
+            collect_unmatched(&row_sets, matches_all, &mut unmatched);
+            let is_present: Vec<_> = row_sets.iter().map(|set| !set.is_empty()).collect();
+            // Records zipped from uneven groups differ in the inputs contributing to them,
+            // so they are joined one by one.
+            let is_zipped = params.multi_merge == Some(MultiMerge::Zip) && !is_aggregated;
+            if is_zipped || is_joined(&is_present, &params.join) {
+                if !(params.multi_merge.is_some()
+                    || is_aggregated
+                    || row_sets.iter().all(|set| set.len() <= 1)
+                    || row_sets.iter().filter(|set| !set.is_empty()).count() <= 1) {
+                    sink.emit(Diagnostic {
+                        kind: DiagnosticKind::Ambiguous,
+                        message: String::from(
+                            "Records that could be merged in multiple ways encountered:",
+                        ),
+                        records: record_ids(&[&row_sets]),
+                        keys: vec![key.values()],
+                        distance: None,
+                    })?;
+                    bail!(
+                        "There are multiple ways to merge records. If this is intended, \
+                         consider using {option}. The ambiguous record is:\n{key}",
+                        option = params.names.allow_multi_merge,
+                    );
+                }
+                merged.append(&mut merge(
+                    &row_sets.iter().zip(sheets).collect::<Vec<_>>(),
+                    key,
+                    &output_layout,
+                    params,
+                    sink,
+                )?);
+            }
+            let count = |set: &Vec<&SheetRow>| if matches_all {
+                set.len().min(1)
+            } else {
+                set.len()
+            };
+            if params.warn_unmatched
+                && row_sets.iter().any(|set| count(set) != count(&row_sets[0])) {
                 let comparison_key = |(_, set): &(usize, &Vec<&SheetRow>)| set.len();
                 let (max_index, max_set) =
                     row_sets.iter().enumerate().max_by_key(comparison_key).unwrap();
                 let (min_index, min_set) =
                     row_sets.iter().enumerate().min_by_key(comparison_key).unwrap();
-                warn(&[
-                    &format!(
+                sink.emit(Diagnostic {
+                    kind: DiagnosticKind::Unmatched,
+                    message: format!(
                         "{unmatched_records} encountered (found {max_records} \
                          in input #{max_input}, but {min_records} in input #{min_input}):",
                         unmatched_records = (max_set.len() - min_set.len())
@@ -139,46 +682,164 @@ fn match_and_merge<'a>(sheets: &'a [Sheet], params: &'a Params) -> Result<Vec<Ve
                         min_records = min_set.len().count_with("such record"),
                         min_input = min_index + 1,
                     ),
-                    &key.to_string(),
-                ]);
+                    records: record_ids(&[&row_sets]),
+                    keys: vec![key.values()],
+                    distance: None,
+                })?;
             }
-            if params.similarity_warn_level > 0 {
-                for another_key in by_key.keys() {
-                    let distance = compare_keys(key, another_key);
-                    if distance <= params.similarity_warn_level {
-                        warn(&[
-                            &format!("Similar records encountered (edit distance = {distance}):"),
-                            &key.to_string(),
-                            &another_key.to_string(),
-                        ]);
-                    }
-                }
+            for (another_key, distance) in similar_keys.get(key).into_iter().flatten() {
+                sink.emit(similarity_diagnostic(
+                    key,
+                    another_key,
+                    record_ids(&[&row_sets, &by_key[another_key]]),
+                    *distance,
+                ))?;
             }
         }
     }
-    Ok(merged)
+    Ok(Matched {
+        rows: merged,
+        unmatched,
+    })
 }
 
-fn ensure_proper_delimiter(sheets: &[Sheet], params: &Params) -> Result<()> {
+fn ensure_proper_delimiter(sheets: &[Sheet], params: &MergeParams) -> Result<()> {
     ensure!(
-        params.allow_single_column || sheets.iter().any(
-            |sheet| sheet.into_iter().any(|row| row.len() > 1)
-        ),
+        params.allow_single_column || sheets.iter().any(|sheet| sheet.column_count() > 1),
         "Your data seems not to contain any records with more than one column. \
-         Did you specify the delimiter correctly? If so, consider passing the {flag} flag.",
-        flag = params.names.allow_single_column,
+         Did you specify the delimiter correctly? If so, consider using {option}.",
+        option = params.names.allow_single_column,
     );
     Ok(())
 }
 
-pub fn solidify(params: &Params) -> Result<()> {
+fn ensure_consistent_key_order(sheets: &[Sheet]) -> Result<()> {
+    if let Some(first) = sheets.first() {
+        for (index, sheet) in sheets.iter().enumerate() {
+            ensure!(
+                sheet.key_order() == first.key_order(),
+                "Shared columns of input #{n} are not in the same relative order \
+                 as those of input #1.",
+                n = index + 1,
+            );
+        }
+    }
+    Ok(())
+}
+
+fn solidify_in_memory(params: &Params, sink: &mut dyn DiagnosticSink) -> Result<()> {
+    let merge_params = &params.merge;
+    let output = OutputFile::new(&params.output, params.no_clobber)?;
     let mut sheets = vec![];
+    let mut formats = vec![];
     for (index, path) in params.inputs.iter().enumerate() {
-        sheets.push(Sheet::new(read(path, &params)?, &params.shared_columns, index).with_context(
-            || format!("Could not process {}.", path.display())
+        let (delimiter, format, records) = open_input(index, params)?;
+        formats.push(format);
+        sheets.push(Sheet::new(
+            read(records, path)?,
+            delimiter,
+            merge_params,
+            index,
+            sink,
+        ).with_context(
+            || format!("Could not process {}.", input_name(path))
         )?);
     }
-    ensure_proper_delimiter(&sheets, &params)?;
-    write(&params.output, &match_and_merge(&sheets, &params)?, params)?;
+    ensure_proper_delimiter(&sheets, merge_params)?;
+    ensure_consistent_key_order(&sheets)?;
+    ensure_coalesced_columns_exist(&sheets, merge_params)?;
+    ensure_aggregated_columns_exist(&sheets, merge_params)?;
+    let header = merge_params.has_headers.then(|| merge_header(&sheets, merge_params));
+    let mut matched = match_and_merge(&sheets, merge_params, sink)?;
+    if let Some(directory) = &params.unmatched_dir {
+        let mut writer = UnmatchedWriter::new(directory, params, &sheets, formats)?;
+        for records in &mut matched.unmatched {
+            records.sort_by_key(|(index, _)| *index);
+        }
+        writer.write(&matched.unmatched)?;
+        writer.finish()?;
+    }
+    let output_delimiter = params.output_delimiter.as_deref().unwrap_or(sheets[0].delimiter());
+    write(output, header.as_deref(), &matched.rows, output_delimiter, params)?;
     Ok(())
 }
+
+/// Merges the input files into the output file as specified by `params`,
+/// reporting any diagnostics as they are encountered.
+pub fn solidify(params: &Params) -> Result<()> {
+    let mut reporter = Reporter::new(params.report, params.report_file.as_deref())?;
+    let result = if params.sorted || params.external_sort.is_some() {
+        solidify_sorted(params, &mut reporter)
+    } else {
+        solidify_in_memory(params, &mut reporter)
+    };
+    let ragged = result.as_ref().err().and_then(|error| error.downcast_ref::<RaggedRecord>());
+    if let Some(ragged) = ragged {
+        reporter.emit(ragged.diagnostic())?;
+    }
+    reporter.finish()?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::params::Join;
+
+    use super::builder::{Solidifier, Table};
+
+    fn table(rows: &[&[&str]]) -> Table {
+        rows.iter().map(|row| row.iter().map(|value| value.to_string()).collect()).collect()
+    }
+
+    fn merge_uneven_groups(join: Join) -> Vec<Vec<String>> {
+        Solidifier::new()
+            .shared([1])
+            .filler("-")
+            .allow_multi_merge(true)
+            .join(join)
+            .merge(vec![
+                table(&[&["1", "a"], &["1", "b"], &["1", "c"], &["2", "d"]]),
+                table(&[&["1", "A"], &["3", "E"]]),
+            ])
+            .unwrap()
+            .rows
+    }
+
+    #[test]
+    fn join_applies_to_each_zipped_row() {
+        assert_eq!(merge_uneven_groups(Join::Inner), table(&[&["1", "a", "A"]]));
+        assert_eq!(merge_uneven_groups(Join::Input(1)), table(&[
+            &["1", "a", "A"],
+            &["3", "-", "E"],
+        ]));
+        assert_eq!(merge_uneven_groups(Join::Anti), table(&[
+            &["1", "b", "-"],
+            &["1", "c", "-"],
+            &["2", "d", "-"],
+        ]));
+        assert_eq!(merge_uneven_groups(Join::Outer).len(), 5);
+    }
+
+    fn fuzzy_merge(tables: Vec<Table>) -> anyhow::Result<Vec<Vec<String>>> {
+        Ok(Solidifier::new().shared([1]).fuzzy_merge(1).merge(tables)?.rows)
+    }
+
+    #[test]
+    fn fuzzy_merge_does_not_depend_on_the_order_of_inputs() {
+        let two = table(&[&["abc", "1"], &["abd", "2"]]);
+        let one = table(&[&["ab", "3"]]);
+        for tables in [vec![two.clone(), one.clone()], vec![one.clone(), two.clone()]] {
+            let error = fuzzy_merge(tables).unwrap_err().to_string();
+            assert!(error.starts_with("There are multiple ways to fuzzily merge"), "{error}");
+        }
+        let two = table(&[&["abc", "1"], &["xyz", "2"]]);
+        assert_eq!(fuzzy_merge(vec![two.clone(), one.clone()]).unwrap(), table(&[
+            &["abc", "1", "3"],
+            &["xyz", "2", ""],
+        ]));
+        assert_eq!(fuzzy_merge(vec![one, two]).unwrap(), table(&[
+            &["ab", "3", "1"],
+            &["xyz", "", "2"],
+        ]));
+    }
+}
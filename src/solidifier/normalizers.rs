@@ -0,0 +1,40 @@
+use std::borrow::Cow;
+
+use crate::params::Normalizer;
+use crate::strings::normalization::Normalize;
+
+fn collapse_whitespace(value: &str) -> String {
+    let mut result = String::new();
+    let mut after_whitespace = false;
+    for char in value.chars() {
+        if char.is_whitespace() {
+            if !after_whitespace {
+                result.push(' ');
+            }
+            after_whitespace = true;
+        } else {
+            result.push(char);
+            after_whitespace = false;
+        }
+    }
+    result
+}
+
+fn apply(value: &str, normalizer: Normalizer) -> String {
+    match normalizer {
+        Normalizer::Trim => String::from(value.trim()),
+        Normalizer::CollapseWhitespace => collapse_whitespace(value),
+        Normalizer::FoldCase => value.case_folded(),
+        Normalizer::Nfc => value.nfc(),
+        Normalizer::Nfkc => value.nfkc(),
+        Normalizer::StripDiacritics => value.without_diacritics(),
+        Normalizer::RemovePunctuation => value.without_punctuation(),
+    }
+}
+
+pub fn normalize<'a>(value: &'a str, normalizers: &[Normalizer]) -> Cow<'a, str> {
+    normalizers.iter().fold(
+        Cow::Borrowed(value),
+        |value, normalizer| Cow::Owned(apply(&value, *normalizer)),
+    )
+}
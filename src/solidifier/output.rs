@@ -0,0 +1,179 @@
+use std::fs::{hard_link, metadata, remove_file, rename, set_permissions, File, OpenOptions};
+use std::io::{self, stdout, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::params::Params;
+
+use super::compression::{compressed, CompressedFile};
+use super::delimited::RecordWrite;
+use super::external::random_id;
+use super::{is_standard_stream, output_name, sink_writer};
+
+/// The output, written to a temporary file next to it and moved into place once complete,
+/// so that a failure never leaves a partially written output behind (unless it is stdout).
+pub struct OutputFile {
+    path: PathBuf,
+    /// The temporary file along with its path, unless the output is stdout.
+    temp: Option<(PathBuf, File)>,
+    /// The temporary file as written to, which is finished before it is moved into place.
+    compressed: Option<CompressedFile>,
+    no_clobber: bool,
+}
+
+/// Creates a file with an unpredictable name next to the output, failing rather than
+/// opening an existing file (or following a symbolic link).
+fn create_temp_file(path: &Path) -> Result<(PathBuf, File)> {
+    let name = path.file_name()
+        .with_context(|| format!("{} is not a file name.", path.display()))?;
+    loop {
+        let temp_path = path.with_file_name(format!(
+            ".{name}.solidify-{id:016x}.tmp",
+            name = name.to_string_lossy(),
+            id = random_id(),
+        ));
+        match OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error)
+                .with_context(|| format!("Could not open {} for writing.", path.display())),
+        }
+    }
+}
+
+/// Copies a file to a path that must not exist yet, for filesystems without hard links.
+fn copy_new(source: &Path, destination: &Path) -> io::Result<()> {
+    let mut output = OpenOptions::new().write(true).create_new(true).open(destination)?;
+    let result = File::open(source).and_then(|mut input| io::copy(&mut input, &mut output))
+        .and_then(|_| output.sync_all());
+    if result.is_err() {
+        let _ = remove_file(destination);
+    }
+    result
+}
+
+/// Moves a file to a path that must not exist yet.
+fn move_new(source: &Path, destination: &Path) -> io::Result<()> {
+    match hard_link(source, destination) {
+        Err(error) if error.kind() != ErrorKind::AlreadyExists => copy_new(source, destination),
+        result => result,
+    }.and_then(|_| remove_file(source))
+}
+
+impl OutputFile {
+    pub fn new(path: &Path, no_clobber: bool) -> Result<Self> {
+        let temp = if is_standard_stream(path) {
+            None
+        } else {
+            ensure!(
+                !(no_clobber && path.exists()),
+                "{} already exists and would be overwritten.",
+                path.display(),
+            );
+            Some(create_temp_file(path)?)
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            temp,
+            compressed: None,
+            no_clobber,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn writer(&mut self, delimiter: &str, params: &Params) -> Result<Box<dyn RecordWrite>> {
+        let sink = match &self.temp {
+            Some((_, file)) => {
+                let file = file.try_clone().with_context(
+                    || format!("Could not open {} for writing.", self.path.display())
+                )?;
+                let compressed = self.compressed.insert(compressed(file, &self.path)?);
+                compressed.sink()
+            },
+            None => Box::new(stdout().lock()),
+        };
+        sink_writer(sink, &self.path, params.output_format, delimiter, params)
+    }
+
+    /// Finishes the written data and moves them into place; the writer must have been
+    /// flushed and dropped.
+    pub fn commit(mut self) -> Result<()> {
+        if let Some((temp_path, file)) = self.temp.take() {
+            let finished = self.compressed.take().map_or(Ok(()), CompressedFile::finish);
+            let result = finished.and_then(|_| file.sync_all()).and_then(|_| if self.no_clobber {
+                move_new(&temp_path, &self.path)
+            } else {
+                // An overwritten output keeps its permissions.
+                match metadata(&self.path) {
+                    Ok(existing) => set_permissions(&temp_path, existing.permissions()),
+                    Err(_) => Ok(()),
+                }.and_then(|_| rename(&temp_path, &self.path))
+            });
+            if let Err(error) = result {
+                let _ = remove_file(&temp_path);
+                if error.kind() == ErrorKind::AlreadyExists {
+                    bail!("{} already exists and would be overwritten.", self.path.display());
+                }
+                return Err(error).with_context(
+                    || format!("Could not write data to {}.", output_name(&self.path))
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        if let Some((temp_path, _)) = &self.temp {
+            let _ = remove_file(temp_path);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs::{read_to_string, write, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::super::external::TempDir;
+    use super::*;
+
+    fn commit(path: &Path, no_clobber: bool, contents: &str) -> Result<()> {
+        let output = OutputFile::new(path, no_clobber)?;
+        let (temp_path, _) = output.temp.as_ref().unwrap();
+        assert!(temp_path.exists());
+        write(temp_path, contents).unwrap();
+        output.commit()
+    }
+
+    #[test]
+    fn overwritten_output_keeps_its_permissions() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("output.tsv");
+        write(&path, "old").unwrap();
+        set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+        commit(&path, false, "new").unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), "new");
+        assert_eq!(metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+
+    #[test]
+    fn output_is_not_clobbered() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("output.tsv");
+        commit(&path, true, "first").unwrap();
+        assert!(commit(&path, true, "second").is_err());
+        assert_eq!(read_to_string(&path).unwrap(), "first");
+        let copy = directory.path().join("copy.tsv");
+        copy_new(&path, &copy).unwrap();
+        assert_eq!(read_to_string(&copy).unwrap(), "first");
+        assert_eq!(copy_new(&path, &copy).unwrap_err().kind(), ErrorKind::AlreadyExists);
+        let entries = std::fs::read_dir(directory.path()).unwrap().count();
+        assert_eq!(entries, 2);
+    }
+}
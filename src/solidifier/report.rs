@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::io::{stderr, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::params::ReportFormat;
+use crate::strings::json::Json;
+use crate::warnings::write_warning;
+
+use super::diagnostics::{Diagnostic, DiagnosticSink};
+
+/// Writes diagnostics to a file or to stderr as they are emitted.
+pub struct Reporter {
+    format: ReportFormat,
+    writer: Box<dyn Write>,
+    destination: String,
+    count: usize,
+}
+
+impl Reporter {
+    pub fn new(format: ReportFormat, path: Option<&Path>) -> Result<Self> {
+        let (writer, destination): (Box<dyn Write>, _) = if let Some(path) = path {
+            let file = File::create(path)
+                .with_context(|| format!("Could not open {} for writing.", path.display()))?;
+            (Box::new(BufWriter::new(file)), path.display().to_string())
+        } else {
+            (Box::new(stderr()), String::from("stderr"))
+        };
+        Ok(Self {
+            format,
+            writer,
+            destination,
+            count: 0,
+        })
+    }
+
+    fn write(&mut self, diagnostic: &Diagnostic) -> std::io::Result<()> {
+        match self.format {
+            ReportFormat::Text => write_warning(&mut self.writer, &diagnostic.lines()),
+            ReportFormat::Json => write!(
+                self.writer,
+                "{separator}\n  {diagnostic}",
+                separator = if self.count == 0 { "[" } else { "," },
+                diagnostic = diagnostic.to_json(),
+            ),
+        }
+    }
+
+    /// Completes the report; must be called after all the diagnostics are emitted.
+    pub fn finish(mut self) -> Result<()> {
+        let ending = match self.format {
+            ReportFormat::Text => "",
+            ReportFormat::Json if self.count == 0 => "[]\n",
+            ReportFormat::Json => "\n]\n",
+        };
+        write!(self.writer, "{ending}")
+            .and_then(|_| self.writer.flush())
+            .with_context(|| format!("Could not write the report to {}.", self.destination))
+    }
+}
+
+impl DiagnosticSink for Reporter {
+    fn emit(&mut self, diagnostic: Diagnostic) -> Result<()> {
+        self.write(&diagnostic)
+            .with_context(|| format!("Could not write the report to {}.", self.destination))?;
+        self.count += 1;
+        Ok(())
+    }
+}
@@ -1,26 +1,42 @@
-use anyhow::{bail, ensure, Result};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::mem::take;
 
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::params::{Column, MergeParams, Normalizer, RaggedPolicy};
 use crate::strings::countable::Countable;
 
+use super::diagnostics::{Diagnostic, DiagnosticKind, DiagnosticSink};
 use super::keys::{Key, KeyItem, RecordId};
+use super::normalizers::normalize;
 
+#[derive(Clone)]
 struct KeyColumns {
     original: Vec<Option<usize>>,
     sorted: Vec<usize>,
+    normalizers: Vec<Vec<Normalizer>>,
 }
 
 #[non_exhaustive]
 pub struct SheetRow<'a> {
     sheet: &'a Sheet,
+    position: usize,
     id: RecordId,
 }
 
 #[non_exhaustive]
 pub struct Sheet {
+    header: Option<Vec<String>>,
     rows: Vec<Vec<String>>,
+    row_indices: Option<Vec<usize>>,
     column_count: usize,
+    delimiter: String,
     input_index: usize,
     key_columns: KeyColumns,
+    coalesced_columns: Vec<Option<usize>>,
+    aggregated_columns: Vec<Option<usize>>,
 }
 
 pub struct SheetIterator<'a> {
@@ -28,9 +44,90 @@ pub struct SheetIterator<'a> {
     next_index: usize,
 }
 
-pub enum SheetRowSection<'a> {
-    Key(&'a str),
-    NonKey(Vec<&'a str>),
+pub enum SheetRowSection<T> {
+    Key(T),
+    NonKey(Vec<T>),
+}
+
+/// Error caused by a record whose number of columns differs from that of the first record.
+#[derive(Debug)]
+pub struct RaggedRecord {
+    pub id: RecordId,
+    /// The 1-based number of the record in the input, including the header row.
+    pub number: usize,
+    pub column_count: usize,
+    pub expected_column_count: usize,
+}
+
+impl Display for RaggedRecord {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "The first record has {first_columns}, but record #{n} has {nth_columns}.",
+            first_columns = self.expected_column_count.count_with("column"),
+            nth_columns = self.column_count.count_with("column"),
+            n = self.number,
+        )
+    }
+}
+
+impl Error for RaggedRecord {}
+
+impl RaggedRecord {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic {
+            kind: DiagnosticKind::Ragged,
+            message: format!(
+                "Record with {nth_columns} encountered (expected {first_columns}):",
+                first_columns = self.expected_column_count.count_with("column"),
+                nth_columns = self.column_count.count_with("column"),
+            ),
+            records: vec![self.id],
+            keys: vec![],
+            distance: None,
+        }
+    }
+
+    /// Brings the record to the expected number of columns as allowed by the policy,
+    /// returning `None` if the record is to be skipped (which is reported).
+    pub fn fit(
+        self,
+        mut record: Vec<String>,
+        policy: RaggedPolicy,
+        filler: &str,
+        sink: &mut dyn DiagnosticSink,
+    ) -> Result<Option<Vec<String>>> {
+        if record.len() < self.expected_column_count && policy.pad {
+            record.resize(self.expected_column_count, String::from(filler));
+            Ok(Some(record))
+        } else if record.len() > self.expected_column_count && policy.truncate {
+            record.truncate(self.expected_column_count);
+            Ok(Some(record))
+        } else if policy.skip {
+            sink.emit(Diagnostic {
+                message: format!(
+                    "Record with {nth_columns} skipped (expected {first_columns}):",
+                    first_columns = self.expected_column_count.count_with("column"),
+                    nth_columns = self.column_count.count_with("column"),
+                ),
+                ..self.diagnostic()
+            })?;
+            Ok(None)
+        } else {
+            bail!(self)
+        }
+    }
+}
+
+impl<T> SheetRowSection<T> {
+    pub fn map<U>(self, f: impl Fn(T) -> U) -> SheetRowSection<U> {
+        match self {
+            SheetRowSection::Key(value) => SheetRowSection::Key(f(value)),
+            SheetRowSection::NonKey(values) => {
+                SheetRowSection::NonKey(values.into_iter().map(f).collect())
+            },
+        }
+    }
 }
 
 impl KeyColumns {
@@ -42,25 +139,38 @@ impl KeyColumns {
         values
     }
 
-    fn new(original: Vec<Option<usize>>) -> Self {
+    fn new(original: Vec<Option<usize>>, normalizers: &[Vec<Normalizer>]) -> Self {
         let sorted = Self::sorted(&original);
         Self {
             original,
             sorted,
+            normalizers: normalizers.to_vec(),
         }
     }
 
-    fn split<'a>(&self, data: &[&'a str]) -> Vec<SheetRowSection<'a>> {
+    fn order(&self) -> Vec<usize> {
+        self.sorted.iter().map(
+            |index| self.original.iter().position(|column| *column == Some(*index)).unwrap()
+        ).collect()
+    }
+
+    fn values<'a>(&self, data: &'a [String]) -> Vec<Option<Cow<'a, str>>> {
+        self.original.iter().zip(&self.normalizers).map(
+            |(column, normalizers)| column.map(|index| normalize(&data[index], normalizers))
+        ).collect()
+    }
+
+    fn split<T: Clone>(&self, data: &[T]) -> Vec<SheetRowSection<T>> {
         let indices: Vec<_> = self.sorted.iter().map(|index| *index as i32).collect();
         let mut sections = vec![];
         for (&key, &next_key) in [-1].iter().chain(indices.iter()).zip(
             indices.iter().chain([data.len() as i32].iter())
         ) {
             if key >= 0 {
-                sections.push(SheetRowSection::Key(data[key as usize]));
+                sections.push(SheetRowSection::Key(data[key as usize].clone()));
             }
             sections.push(SheetRowSection::NonKey(
-                ((key + 1)..next_key).map(|index| data[index as usize]).collect(),
+                ((key + 1)..next_key).map(|index| data[index as usize].clone()).collect(),
             ));
         }
         sections
@@ -87,23 +197,48 @@ impl Sheet {
         }
     }
 
-    fn check_rectangular(data: &[Vec<String>]) -> Result<usize> {
-        if let Some(first_row) = data.first() {
-            for (index, current_row) in data.iter().enumerate() {
-                if current_row.len() != first_row.len() {
-                    let count_columns = |row: &Vec<String>| row.len().count_with("column");
-                    bail!(
-                        "The first record has {first_columns}, but record #{n} has {nth_columns}.",
-                        first_columns = count_columns(first_row),
-                        nth_columns = count_columns(current_row),
-                        n = index + 1,
-                    )
+    /// Brings the records to the number of columns of the first one, returning that
+    /// number along with the indices of the remaining data records if any were skipped.
+    fn fit_rows(
+        rows: &mut Vec<Vec<String>>,
+        params: &MergeParams,
+        input_index: usize,
+        sink: &mut dyn DiagnosticSink,
+    ) -> Result<(usize, Option<Vec<usize>>)> {
+        let column_count = if let Some(first_row) = rows.first() {
+            first_row.len()
+        } else {
+            return Ok((0, None));
+        };
+        let header_offset = usize::from(params.has_headers);
+        let mut indices = vec![];
+        let mut is_skipped = false;
+        for (index, row) in take(rows).into_iter().enumerate() {
+            let row = if row.len() == column_count {
+                row
+            } else {
+                let ragged = RaggedRecord {
+                    id: RecordId {
+                        input_index,
+                        row_index: index - header_offset,
+                    },
+                    number: index + 1,
+                    column_count: row.len(),
+                    expected_column_count: column_count,
+                };
+                if let Some(row) = ragged.fit(row, params.ragged, &params.filler, sink)? {
+                    row
+                } else {
+                    is_skipped = true;
+                    continue;
                 }
+            };
+            rows.push(row);
+            if index >= header_offset {
+                indices.push(index - header_offset);
             }
-            Ok(first_row.len())
-        } else {
-            Ok(0)
         }
+        Ok((column_count, is_skipped.then_some(indices)))
     }
 
     fn check_convert_columns(columns: &[i32], count: usize) -> Result<Vec<Option<usize>>> {
@@ -114,32 +249,194 @@ impl Sheet {
         }
         let largest_positive = columns.iter().filter(|value| **value > 0).max();
         let smallest_negative = columns.iter().filter(|value| **value < 0).min();
-        match (largest_positive, smallest_negative) {
-            (Some(largest), Some(smallest)) => ensure!(
+        if let (Some(largest), Some(smallest)) = (largest_positive, smallest_negative) {
+            ensure!(
                 largest - smallest <= count as i32,
                 "Positively indexed columns must precede negatively indexed columns; \
                  got {smallest} ~ {smallest_normalized} <= {largest} \
                  (total columns: {count}{note}).",
                 smallest_normalized = Self::normalize_column(*smallest, count)?.unwrap() + 1,
                 note = if count == 1 { "; did you specify the delimiter correctly?" } else { "" },
-            ),
-            _ => (),
+            );
+        }
+        Ok(result)
+    }
+
+    fn resolve_column(column: &Column, header: Option<&Vec<String>>) -> Result<i32> {
+        match column {
+            Column::Index(index) => Ok(*index),
+            Column::Name(name) => {
+                let header = header.with_context(
+                    || format!("Column '{name}' cannot be found since there is no header row.")
+                )?;
+                let mut positions = header.iter().enumerate().filter(|(_, value)| *value == name);
+                match (positions.next(), positions.next()) {
+                    (Some((index, _)), None) => Ok(index as i32 + 1),
+                    (Some(_), Some(_)) => bail!("There are multiple columns named '{name}'."),
+                    (None, _) => bail!("There is no column named '{name}'."),
+                }
+            },
+        }
+    }
+
+    fn resolve_columns(columns: &[Column], header: Option<&Vec<String>>) -> Result<Vec<i32>> {
+        columns.iter().map(|column| Self::resolve_column(column, header)).collect()
+    }
+
+    /// Resolves columns that some inputs may lack (those referred to by name), which must
+    /// be distinct non-shared columns; `purpose` describes what the columns are for.
+    fn resolve_optional_columns<'a>(
+        columns: impl IntoIterator<Item = &'a Column>,
+        header: Option<&Vec<String>>,
+        count: usize,
+        key_columns: &KeyColumns,
+        purpose: &str,
+    ) -> Result<Vec<Option<usize>>> {
+        let mut result = vec![];
+        for column in columns {
+            let index = match column {
+                Column::Index(0) => bail!("The unique column 0 cannot be {purpose}."),
+                Column::Name(name) if header.is_some_and(
+                    |header| !header.contains(name)
+                ) => None,
+                _ => Self::normalize_column(Self::resolve_column(column, header)?, count)?,
+            };
+            if let Some(index) = index {
+                ensure!(
+                    !key_columns.sorted.contains(&index),
+                    "Column {n} is both shared and {purpose}.",
+                    n = index + 1,
+                );
+                ensure!(
+                    !result.contains(&Some(index)),
+                    "Column {n} is {purpose} more than once.",
+                    n = index + 1,
+                );
+            }
+            result.push(index);
         }
         Ok(result)
     }
 
-    pub fn new(rows: Vec<Vec<String>>, key_columns: &[i32], input_index: usize) -> Result<Self> {
-        let column_count = Self::check_rectangular(&rows)?;
+    pub fn new(
+        mut rows: Vec<Vec<String>>,
+        delimiter: String,
+        params: &MergeParams,
+        input_index: usize,
+        sink: &mut dyn DiagnosticSink,
+    ) -> Result<Self> {
+        let (column_count, row_indices) = Self::fit_rows(&mut rows, params, input_index, sink)?;
+        let header = if params.has_headers && !rows.is_empty() {
+            Some(rows.remove(0))
+        } else {
+            None
+        };
+        let key_columns = KeyColumns::new(
+            Self::check_convert_columns(
+                &Self::resolve_columns(&params.shared_columns[input_index], header.as_ref())?,
+                column_count,
+            )?,
+            &params.key_normalizers,
+        );
+        let coalesced_columns = Self::resolve_optional_columns(
+            &params.coalesced_columns,
+            header.as_ref(),
+            column_count,
+            &key_columns,
+            "coalesced",
+        )?;
+        let aggregated_columns = Self::resolve_optional_columns(
+            params.aggregation.iter().flat_map(
+                |aggregation| aggregation.columns.iter().map(|(column, _)| column)
+            ),
+            header.as_ref(),
+            column_count,
+            &key_columns,
+            "aggregated",
+        )?;
         Ok(Sheet {
+            header,
             rows,
+            row_indices,
             column_count,
+            delimiter,
             input_index,
-            key_columns: KeyColumns::new(Self::check_convert_columns(key_columns, column_count)?),
+            key_columns,
+            coalesced_columns,
+            aggregated_columns,
         })
     }
 
-    pub fn split_empty_by_key<'a>(&self, filler: &'a str) -> Vec<SheetRowSection<'a>> {
-        self.key_columns.split(&vec![filler; self.column_count])
+    /// Creates a sheet with the same structure as `template`, containing the given
+    /// rows along with their (0-based) indices in the input.
+    pub fn with_structure_of(template: &Sheet, rows: Vec<(usize, Vec<String>)>) -> Self {
+        let (row_indices, rows) = rows.into_iter().unzip();
+        Sheet {
+            header: None,
+            rows,
+            row_indices: Some(row_indices),
+            column_count: template.column_count,
+            delimiter: template.delimiter.clone(),
+            input_index: template.input_index,
+            key_columns: template.key_columns.clone(),
+            coalesced_columns: template.coalesced_columns.clone(),
+            aggregated_columns: template.aggregated_columns.clone(),
+        }
+    }
+
+    pub fn split_header_by_key(&self) -> Option<Vec<SheetRowSection<&str>>> {
+        self.header.as_ref().map(
+            |header| self.key_columns.split(&header.iter().map(String::as_str).collect::<Vec<_>>())
+        )
+    }
+
+    pub fn header(&self) -> Option<&Vec<String>> {
+        self.header.as_ref()
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.column_count
+    }
+
+    /// Returns the values of the (data) shared columns of the given row after normalization.
+    pub fn key_values<'a>(&self, row: &'a [String]) -> Vec<Cow<'a, str>> {
+        self.key_columns.values(row).into_iter().flatten().collect()
+    }
+
+    pub fn delimiter(&self) -> &str {
+        &self.delimiter
+    }
+
+    pub fn key_order(&self) -> Vec<usize> {
+        self.key_columns.order()
+    }
+
+    /// Splits a row of the given (empty) value, standing in for a record the sheet lacks.
+    pub fn split_empty_by_key<T: Clone>(&self, empty: T) -> Vec<SheetRowSection<T>> {
+        self.key_columns.split(&vec![empty; self.column_count])
+    }
+
+    pub fn split_column_indices_by_key(&self) -> Vec<SheetRowSection<usize>> {
+        self.key_columns.split(&(0..self.column_count).collect::<Vec<_>>())
+    }
+
+    /// Returns the index of each coalesced column in this sheet, if the sheet has it.
+    pub fn coalesced_columns(&self) -> &[Option<usize>] {
+        &self.coalesced_columns
+    }
+
+    /// Returns the index of each column with an explicit aggregator in this sheet,
+    /// if the sheet has it.
+    pub fn aggregated_columns(&self) -> &[Option<usize>] {
+        &self.aggregated_columns
+    }
+
+    pub fn is_key_column(&self, column: usize) -> bool {
+        self.key_columns.sorted.contains(&column)
+    }
+
+    pub fn split_values_by_key<T: Clone>(&self, values: &[T]) -> Vec<SheetRowSection<T>> {
+        self.key_columns.split(values)
     }
 }
 
@@ -147,14 +444,18 @@ impl<'a> Iterator for SheetIterator<'a> {
     type Item = SheetRow<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let row_index = self.next_index;
-        if row_index < self.sheet.rows.len() {
+        let position = self.next_index;
+        if position < self.sheet.rows.len() {
             self.next_index += 1;
             Some(SheetRow {
                 sheet: self.sheet,
+                position,
                 id: RecordId {
                     input_index: self.sheet.input_index,
-                    row_index,
+                    row_index: self.sheet.row_indices.as_ref().map_or(
+                        position,
+                        |indices| indices[position],
+                    ),
                 },
             })
         } else {
@@ -176,27 +477,27 @@ impl<'a> IntoIterator for &'a Sheet {
 }
 
 impl<'a> SheetRow<'a> {
-    fn data(&self) -> &'a Vec<String> {
-        &self.sheet.rows[self.id.row_index]
+    pub fn id(&self) -> RecordId {
+        self.id
     }
 
-    pub fn len(&self) -> usize {
-        self.data().len()
+    pub fn data(&self) -> &'a Vec<String> {
+        &self.sheet.rows[self.position]
     }
 
     pub fn key<'b>(&'b self) -> Key<'a, 'b> {
         Key::new(
-            self.sheet.key_columns.original.iter().map(|column| {
-                if let Some(index) = column {
-                    KeyItem::Data(&self.data()[*index])
+            self.sheet.key_columns.values(self.data()).into_iter().map(
+                |value| if let Some(value) = value {
+                    KeyItem::Data(value)
                 } else {
                     KeyItem::Id(&self.id)
-                }
-            }).collect(),
+                },
+            ).collect(),
         )
     }
 
-    pub fn split_by_key(&self) -> Vec<SheetRowSection<'a>> {
+    pub fn split_by_key(&self) -> Vec<SheetRowSection<&'a str>> {
         self.sheet.key_columns.split(&self.data().iter().map(String::as_str).collect::<Vec<_>>())
     }
 }
@@ -0,0 +1,184 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use edit_distance::edit_distance;
+
+use super::keys::{Key, KeyItem};
+
+/// Index of keys by the strings obtained from them by deleting up to a given number of
+/// characters: any two keys within that edit distance of each other share such a string.
+pub struct DeletionIndex {
+    max_distance: u32,
+    entries: Vec<(u64, usize)>,
+}
+
+const SEPARATOR: char = '\u{1F}';
+
+fn compare_strings(a: &str, b: &str) -> u32 {
+    edit_distance(a, b) as u32
+}
+
+fn compare_key_items(a: &KeyItem, b: &KeyItem) -> u32 {
+    match (a, b) {
+        (KeyItem::Data(a), KeyItem::Data(b)) => compare_strings(a, b),
+        _ => 0,
+    }
+}
+
+pub fn compare_keys(a: &Key, b: &Key) -> u32 {
+    a.into_iter().zip(b).map(|(a, b)| compare_key_items(a, b)).sum()
+}
+
+fn hash_chars(chars: &[char]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    chars.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn collect_deletions(
+    chars: &[char],
+    position: usize,
+    deletions_left: u32,
+    kept: &mut Vec<char>,
+    hashes: &mut Vec<u64>,
+) {
+    if position == chars.len() {
+        hashes.push(hash_chars(kept));
+    } else {
+        kept.push(chars[position]);
+        collect_deletions(chars, position + 1, deletions_left, kept, hashes);
+        kept.pop();
+        if deletions_left > 0 {
+            collect_deletions(chars, position + 1, deletions_left - 1, kept, hashes);
+        }
+    }
+}
+
+fn deletion_hashes(key: &Key, max_deletions: u32) -> Vec<u64> {
+    let mut chars = vec![];
+    for item in key {
+        if let KeyItem::Data(data) = item {
+            chars.extend(data.chars());
+        }
+        chars.push(SEPARATOR);
+    }
+    let mut hashes = vec![];
+    collect_deletions(&chars, 0, max_deletions, &mut vec![], &mut hashes);
+    hashes.sort_unstable();
+    hashes.dedup();
+    hashes
+}
+
+impl DeletionIndex {
+    pub fn new(keys: &[&Key], max_distance: u32) -> Self {
+        let mut entries: Vec<_> = keys.iter().enumerate().flat_map(
+            |(index, key)| deletion_hashes(key, max_distance).into_iter().map(
+                move |hash| (hash, index)
+            )
+        ).collect();
+        entries.sort_unstable();
+        Self {
+            max_distance,
+            entries,
+        }
+    }
+
+    /// Returns indices of all the keys within the maximum distance from the given one
+    /// (including the key itself if it is indexed), along with the respective distances.
+    pub fn find(&self, key: &Key, keys: &[&Key]) -> Vec<(usize, u32)> {
+        let mut candidates = vec![];
+        for hash in deletion_hashes(key, self.max_distance) {
+            let start = self.entries.partition_point(|(entry_hash, _)| *entry_hash < hash);
+            candidates.extend(self.entries[start..].iter().take_while(
+                |(entry_hash, _)| *entry_hash == hash
+            ).map(|(_, index)| *index));
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates.into_iter().map(|index| (index, compare_keys(keys[index], key))).filter(
+            |(_, distance)| *distance <= self.max_distance
+        ).collect()
+    }
+}
+
+/// For each of the keys, finds all the keys following it within `max_distance`,
+/// sorted by their indices.
+pub fn find_similar(keys: &[&Key], max_distance: u32) -> Vec<Vec<(usize, u32)>> {
+    let index = DeletionIndex::new(keys, max_distance);
+    keys.iter().enumerate().map(|(key_index, key)| index.find(key, keys).into_iter().filter(
+        |(another_index, _)| *another_index > key_index
+    ).collect()).collect()
+}
+
+/// Does the same as `find_similar` by comparing every pair of keys; this is
+/// much slower and is only kept as a reference implementation.
+#[cfg(test)]
+fn find_similar_brute_force(keys: &[&Key], max_distance: u32) -> Vec<Vec<(usize, u32)>> {
+    keys.iter().enumerate().map(|(index, key)| {
+        keys.iter().enumerate().skip(index + 1).map(
+            |(another_index, another_key)| (another_index, compare_keys(key, another_key))
+        ).filter(|(_, distance)| *distance <= max_distance).collect()
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+
+    /// Characters the random keys are made of, including multi-byte ones.
+    const ALPHABET: [char; 6] = ['a', 'b', 'é', 'ж', '語', '🦀'];
+
+    /// A xorshift generator, which makes the test reproducible.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+
+        fn string(&mut self) -> String {
+            let length = self.below(6);
+            (0..length).map(|_| ALPHABET[self.below(ALPHABET.len())]).collect()
+        }
+    }
+
+    fn key(values: &[String]) -> Key<'_, 'static> {
+        Key::new(values.iter().map(|value| KeyItem::Data(Cow::Borrowed(value))).collect())
+    }
+
+    #[test]
+    fn index_finds_the_same_keys_as_brute_force() {
+        let mut random = Random(0x5eed_1234_abcd_0001);
+        for column_count in 1..=2 {
+            for _ in 0..20 {
+                let key_count = 1 + random.below(40);
+                let values: Vec<Vec<_>> = (0..key_count).map(
+                    |_| (0..column_count).map(|_| random.string()).collect()
+                ).collect();
+                let keys: Vec<_> = values.iter().map(|values| key(values)).collect();
+                let keys: Vec<_> = keys.iter().collect();
+                for distance in 0..=3 {
+                    assert_eq!(
+                        find_similar(&keys, distance),
+                        find_similar_brute_force(&keys, distance),
+                        "keys: {values:?}, distance: {distance}",
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keys_shorter_than_the_distance_are_found() {
+        let values = [vec![String::new()], vec![String::from("🦀")], vec![String::from("é語")]];
+        let keys: Vec<_> = values.iter().map(|values| key(values)).collect();
+        let keys: Vec<_> = keys.iter().collect();
+        assert_eq!(find_similar(&keys, 3), vec![vec![(1, 1), (2, 2)], vec![(2, 2)], vec![]]);
+        assert_eq!(find_similar(&keys, 1), vec![vec![(1, 1)], vec![], vec![]]);
+    }
+}
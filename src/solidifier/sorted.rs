@@ -0,0 +1,236 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::{bail, Context, Result};
+
+use crate::params::Params;
+
+use super::external::{sort_externally, TempDir};
+use super::aggregate::ensure_aggregated_columns_exist;
+use super::coalesce::ensure_coalesced_columns_exist;
+use super::delimited::{as_optional_strs, as_strs};
+use super::diagnostics::{Diagnostic, DiagnosticSink};
+use super::keys::{Key, KeyItem, RecordId};
+use super::output::OutputFile;
+use super::sheet::{RaggedRecord, Sheet};
+use super::similarity::find_similar;
+use super::unmatched::UnmatchedWriter;
+use super::{
+    ensure_consistent_key_order,
+    ensure_proper_delimiter,
+    input_name,
+    match_and_merge,
+    merge_header,
+    open_input,
+    InputFormat,
+    output_name,
+    similarity_diagnostic,
+};
+
+/// Data records of an input along with their (0-based) indices among the data records.
+pub type IndexedRecords<'a> = Box<dyn Iterator<Item = Result<(usize, Vec<String>)>> + 'a>;
+
+/// Returns the values by which records are sorted.
+pub fn sort_key(template: &Sheet, record: &[String]) -> Vec<String> {
+    template.key_values(record).into_iter().map(String::from).collect()
+}
+
+struct SortedInput<'a> {
+    path: &'a PathBuf,
+    records: IndexedRecords<'a>,
+    header_offset: usize,
+    next: Option<(usize, Vec<String>)>,
+}
+
+/// Diagnostics about skipped records, collected as the inputs are read.
+type Skipped = Rc<RefCell<Vec<Diagnostic>>>;
+
+/// Reads the first record of the input to determine its structure, returning a template
+/// sheet (containing no data) and the format of the input along with its data records.
+fn open(
+    input: usize,
+    params: &Params,
+    skipped: &Skipped,
+) -> Result<(Sheet, InputFormat, IndexedRecords<'static>)> {
+    let path = &params.inputs[input];
+    let (delimiter, format, mut records) = open_input(input, params)?;
+    let first = records.next().transpose().with_context(
+        || format!("Could not process record #1 of {}.", input_name(path))
+    )?;
+    let template = Sheet::new(
+        first.iter().cloned().collect(),
+        delimiter,
+        &params.merge,
+        input,
+        &mut *skipped.borrow_mut(),
+    ).with_context(|| format!("Could not process {}.", input_name(path)))?;
+    let column_count = template.column_count();
+    let header_offset = if params.merge.has_headers { 1 } else { 0 };
+    let first = if params.merge.has_headers { None } else { first.map(Ok) };
+    let name = input_name(path);
+    let policy = params.merge.ragged;
+    let filler = params.merge.filler.clone();
+    let skipped = skipped.clone();
+    let records = first.into_iter().chain(records).enumerate().map(move |(index, record)| {
+        let n = index + header_offset + 1;
+        let record = record.with_context(
+            || format!("Could not process record #{n} of {name}.")
+        )?;
+        if record.len() == column_count {
+            return Ok(Some((index, record)));
+        }
+        let ragged = RaggedRecord {
+            id: RecordId {
+                input_index: input,
+                row_index: index,
+            },
+            number: n,
+            column_count: record.len(),
+            expected_column_count: column_count,
+        };
+        let record = ragged.fit(record, policy, &filler, &mut *skipped.borrow_mut())
+            .with_context(|| format!("Could not process {name}."))?;
+        Ok(record.map(|record| (index, record)))
+    }).filter_map(Result::transpose);
+    Ok((template, format, Box::new(records)))
+}
+
+impl<'a> SortedInput<'a> {
+    fn new(path: &'a PathBuf, mut records: IndexedRecords<'a>, params: &Params) -> Result<Self> {
+        let next = records.next().transpose()?;
+        Ok(Self {
+            path,
+            records,
+            header_offset: if params.merge.has_headers { 1 } else { 0 },
+            next,
+        })
+    }
+
+    fn next_key(&self, template: &Sheet) -> Option<Vec<String>> {
+        self.next.as_ref().map(|(_, record)| sort_key(template, record))
+    }
+
+    fn take_group(&mut self, template: &Sheet, key: &[String]) -> Result<Sheet> {
+        let mut rows = vec![];
+        while self.next_key(template).as_deref() == Some(key) {
+            rows.push(self.next.take().unwrap());
+            self.next = self.records.next().transpose()?;
+        }
+        if let (Some((index, _)), Some(next_key)) = (&self.next, self.next_key(template)) {
+            if next_key.as_slice() < key {
+                bail!(
+                    "{path} is not sorted by its shared columns: \
+                     record #{n} should precede the records before it.",
+                    path = input_name(self.path),
+                    n = index + self.header_offset + 1,
+                );
+            }
+        }
+        Ok(Sheet::with_structure_of(template, rows))
+    }
+}
+
+/// Distinct keys of the merged records (in the order they are merged) along with the records
+/// having them, kept so that similar keys can be reported once all the records are merged.
+type KeyRecords = Vec<(Vec<String>, Vec<RecordId>)>;
+
+fn report_similar(keys: &KeyRecords, level: u32, sink: &mut dyn DiagnosticSink) -> Result<()> {
+    let indexed_keys: Vec<_> = keys.iter().map(|(values, _)| Key::new(values.iter().map(
+        |value| KeyItem::Data(Cow::Borrowed(value.as_str()))
+    ).collect())).collect();
+    let indexed_keys: Vec<_> = indexed_keys.iter().collect();
+    for (index, similar) in find_similar(&indexed_keys, level).into_iter().enumerate() {
+        for (another_index, distance) in similar {
+            let mut records = [keys[index].1.as_slice(), &keys[another_index].1].concat();
+            records.sort_by_key(|id| (id.input_index, id.row_index));
+            sink.emit(similarity_diagnostic(
+                indexed_keys[index],
+                indexed_keys[another_index],
+                records,
+                distance,
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+fn report_skipped(skipped: &Skipped, sink: &mut dyn DiagnosticSink) -> Result<()> {
+    for diagnostic in skipped.borrow_mut().drain(..) {
+        sink.emit(diagnostic)?;
+    }
+    Ok(())
+}
+
+pub fn solidify_sorted(params: &Params, sink: &mut dyn DiagnosticSink) -> Result<()> {
+    let mut output = OutputFile::new(&params.output, params.no_clobber)?;
+    let skipped = Skipped::default();
+    let temp_dir = params.external_sort.map(|_| TempDir::new()).transpose()?;
+    let mut inputs = vec![];
+    let mut templates = vec![];
+    let mut formats = vec![];
+    for (index, path) in params.inputs.iter().enumerate() {
+        let (template, format, mut records) = open(index, params, &skipped)?;
+        formats.push(format);
+        if let (Some(run_size), Some(temp_dir)) = (params.external_sort, &temp_dir) {
+            records = sort_externally(records, &template, run_size, temp_dir)
+                .with_context(|| format!("Could not sort {}.", input_name(path)))?;
+        }
+        inputs.push(SortedInput::new(path, records, params)?);
+        templates.push(template);
+    }
+    ensure_proper_delimiter(&templates, &params.merge)?;
+    ensure_consistent_key_order(&templates)?;
+    ensure_coalesced_columns_exist(&templates, &params.merge)?;
+    ensure_aggregated_columns_exist(&templates, &params.merge)?;
+    let output_delimiter = params.output_delimiter.as_deref().unwrap_or(templates[0].delimiter());
+    let mut writer = output.writer(output_delimiter, params)?;
+    let mut unmatched_writer = params.unmatched_dir.as_ref().map(
+        |directory| UnmatchedWriter::new(directory, params, &templates, formats)
+    ).transpose()?;
+    let error_context = || format!("Could not write data to {}.", output_name(&params.output));
+    if params.merge.has_headers {
+        writer.write_record(&as_strs(&merge_header(&templates, &params.merge)))
+            .with_context(error_context)?;
+    }
+    let similarity_warn_level = params.merge.similarity_warn_level;
+    let mut key_records = KeyRecords::new();
+    loop {
+        let keys: Vec<_> = inputs.iter().zip(&templates).map(
+            |(input, template)| input.next_key(template)
+        ).collect();
+        let key = if let Some(key) = keys.into_iter().flatten().min() {
+            key
+        } else {
+            break;
+        };
+        let mut group = vec![];
+        for (input, template) in inputs.iter_mut().zip(&templates) {
+            group.push(input.take_group(template, &key)?);
+        }
+        report_skipped(&skipped, sink)?;
+        if similarity_warn_level > 0 {
+            let records = group.iter().flat_map(|sheet| sheet.into_iter().map(|row| row.id()));
+            key_records.push((key, records.collect()));
+        }
+        let matched = match_and_merge(&group, &params.merge, sink)?;
+        for row in matched.rows {
+            writer.write_row(&as_optional_strs(&row), &params.merge.filler)
+                .with_context(error_context)?;
+        }
+        if let Some(unmatched_writer) = &mut unmatched_writer {
+            unmatched_writer.write(&matched.unmatched)?;
+        }
+    }
+    report_skipped(&skipped, sink)?;
+    if similarity_warn_level > 0 {
+        report_similar(&key_records, similarity_warn_level, sink)?;
+    }
+    writer.flush().with_context(error_context)?;
+    drop(writer);
+    if let Some(unmatched_writer) = unmatched_writer {
+        unmatched_writer.finish()?;
+    }
+    output.commit()
+}
@@ -0,0 +1,292 @@
+use anyhow::{bail, ensure, Context, Result};
+
+const SIGNATURE: [u8; 8] = [0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+const HEADER_SIZE: usize = 512;
+const HEADER_DIFAT_ENTRIES: usize = 109;
+const DIRECTORY_ENTRY_SIZE: usize = 128;
+const END_OF_CHAIN: u32 = 0xffff_fffe;
+const FREE_SECTOR: u32 = 0xffff_ffff;
+const STREAM: u8 = 2;
+
+struct Stream {
+    name: String,
+    start: u32,
+    size: usize,
+}
+
+/// A compound file (the container format of legacy Office documents) held in memory.
+pub struct CompoundFile<'a> {
+    data: &'a [u8],
+    sector_size: usize,
+    fat: Vec<u32>,
+    mini_fat: Vec<u32>,
+    mini_sector_size: usize,
+    mini_stream_cutoff: usize,
+    /// Contents of the stream holding the small streams.
+    mini_stream: Vec<u8>,
+    streams: Vec<Stream>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data.get(offset..offset + 2).context("Unexpected end of the file.")?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data.get(offset..offset + 4).context("Unexpected end of the file.")?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+pub fn is_compound_file(data: &[u8]) -> bool {
+    data.starts_with(&SIGNATURE)
+}
+
+/// Follows a chain of sectors in an allocation table, starting with the given sector.
+fn chain(table: &[u32], start: u32) -> Result<Vec<u32>> {
+    let mut sectors = vec![];
+    let mut sector = start;
+    while sector != END_OF_CHAIN {
+        ensure!(
+            (sector as usize) < table.len() && sectors.len() < table.len(),
+            "The file structure is corrupt.",
+        );
+        sectors.push(sector);
+        sector = table[sector as usize];
+    }
+    Ok(sectors)
+}
+
+impl<'a> CompoundFile<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        ensure!(is_compound_file(data) && data.len() >= HEADER_SIZE, "The file is corrupt.");
+        let sector_shift = u16_at(data, 0x1e)?;
+        let mini_sector_shift = u16_at(data, 0x20)?;
+        ensure!(
+            matches!(sector_shift, 9 | 12) && mini_sector_shift == 6,
+            "The file structure is not supported.",
+        );
+        let mut file = Self {
+            data,
+            sector_size: 1 << sector_shift,
+            fat: vec![],
+            mini_fat: vec![],
+            mini_sector_size: 1 << mini_sector_shift,
+            mini_stream_cutoff: u32_at(data, 0x38)? as usize,
+            mini_stream: vec![],
+            streams: vec![],
+        };
+        let mut fat_sectors = vec![];
+        for index in 0..HEADER_DIFAT_ENTRIES {
+            fat_sectors.push(u32_at(data, 0x4c + index * 4)?);
+        }
+        let mut difat_sector = u32_at(data, 0x44)?;
+        let entries_per_sector = file.sector_size / 4;
+        while difat_sector != END_OF_CHAIN && difat_sector != FREE_SECTOR {
+            ensure!(fat_sectors.len() < data.len() / 4, "The file structure is corrupt.");
+            let sector = file.sector(difat_sector)?;
+            for index in 0..entries_per_sector - 1 {
+                fat_sectors.push(u32_at(sector, index * 4)?);
+            }
+            difat_sector = u32_at(sector, (entries_per_sector - 1) * 4)?;
+        }
+        let fat_sector_count = u32_at(data, 0x2c)? as usize;
+        for &sector in fat_sectors.iter().take(fat_sector_count) {
+            let sector = file.sector(sector)?;
+            for index in 0..entries_per_sector {
+                file.fat.push(u32_at(sector, index * 4)?);
+            }
+        }
+        let mini_fat = file.read_chain(u32_at(data, 0x3c)?, None)?;
+        file.mini_fat = mini_fat.chunks_exact(4).map(
+            |bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        ).collect();
+        let directory = file.read_chain(u32_at(data, 0x30)?, None)?;
+        for (index, entry) in directory.chunks_exact(DIRECTORY_ENTRY_SIZE).enumerate() {
+            let name_length = (u16_at(entry, 0x40)? as usize).min(64);
+            let name: Vec<u16> = entry[..name_length].chunks_exact(2).map(
+                |bytes| u16::from_le_bytes([bytes[0], bytes[1]])
+            ).take_while(|unit| *unit != 0).collect();
+            let start = u32_at(entry, 0x74)?;
+            let size = u32_at(entry, 0x78)? as usize;
+            if index == 0 {
+                // The root entry refers to the mini stream.
+                file.mini_stream = file.read_chain(start, Some(size))?;
+            } else if entry[0x42] == STREAM {
+                file.streams.push(Stream {
+                    name: String::from_utf16_lossy(&name),
+                    start,
+                    size,
+                });
+            }
+        }
+        Ok(file)
+    }
+
+    fn sector(&self, sector: u32) -> Result<&'a [u8]> {
+        let offset = (sector as usize + 1) * self.sector_size;
+        self.data.get(offset..offset + self.sector_size).context("Unexpected end of the file.")
+    }
+
+    fn read_chain(&self, start: u32, size: Option<usize>) -> Result<Vec<u8>> {
+        let mut contents = vec![];
+        if start == END_OF_CHAIN || start == FREE_SECTOR {
+            return Ok(contents);
+        }
+        for sector in chain(&self.fat, start)? {
+            contents.extend_from_slice(self.sector(sector)?);
+        }
+        if let Some(size) = size {
+            ensure!(contents.len() >= size, "The file structure is corrupt.");
+            contents.truncate(size);
+        }
+        Ok(contents)
+    }
+
+    fn read_mini_chain(&self, start: u32, size: usize) -> Result<Vec<u8>> {
+        let mut contents = vec![];
+        for sector in chain(&self.mini_fat, start)? {
+            let offset = sector as usize * self.mini_sector_size;
+            contents.extend_from_slice(
+                self.mini_stream.get(offset..offset + self.mini_sector_size)
+                    .context("The file structure is corrupt.")?
+            );
+        }
+        ensure!(contents.len() >= size, "The file structure is corrupt.");
+        contents.truncate(size);
+        Ok(contents)
+    }
+
+    /// Reads the first stream with one of the given names.
+    pub fn stream(&self, names: &[&str]) -> Result<Vec<u8>> {
+        let Some(stream) = self.streams.iter().find(
+            |stream| names.contains(&stream.name.as_str())
+        ) else {
+            bail!("The file has no {} stream.", names[0]);
+        };
+        if stream.size < self.mini_stream_cutoff {
+            self.read_mini_chain(stream.start, stream.size)
+        } else {
+            self.read_chain(stream.start, Some(stream.size))
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    const SECTOR_SIZE: usize = 512;
+    const MINI_SECTOR_SIZE: usize = 64;
+    const MINI_STREAM_CUTOFF: usize = 4096;
+    const FAT_SECTOR: u32 = 0xffff_fffd;
+    const ROOT: u8 = 5;
+
+    /// Appends data as a chain of consecutive sectors, returning the first sector.
+    fn allocate(table: &mut Vec<u32>, sectors: &mut Vec<u8>, data: &[u8], size: usize) -> u32 {
+        if data.is_empty() {
+            return END_OF_CHAIN;
+        }
+        let start = table.len() as u32;
+        let count = data.len().div_ceil(size);
+        for index in 1..=count {
+            table.push(if index < count { start + index as u32 } else { END_OF_CHAIN });
+        }
+        sectors.extend_from_slice(data);
+        sectors.resize(table.len() * size, 0);
+        start
+    }
+
+    fn directory_entry(name: &str, kind: u8, start: u32, size: usize) -> Vec<u8> {
+        let mut entry = vec![0; DIRECTORY_ENTRY_SIZE];
+        let name: Vec<u8> = name.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect();
+        entry[..name.len()].copy_from_slice(&name);
+        entry[0x40..0x42].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        entry[0x42] = kind;
+        entry[0x74..0x78].copy_from_slice(&start.to_le_bytes());
+        entry[0x78..0x7c].copy_from_slice(&(size as u32).to_le_bytes());
+        entry
+    }
+
+    /// Builds a compound file with 512-byte sectors holding the given streams, the small
+    /// ones in the mini stream.
+    pub fn compound_file(streams: &[(&str, &[u8])]) -> Vec<u8> {
+        let (mut fat, mut sectors) = (vec![], vec![]);
+        let (mut mini_fat, mut mini_stream) = (vec![], vec![]);
+        let mut entries = vec![];
+        for (name, data) in streams {
+            let start = if data.len() < MINI_STREAM_CUTOFF {
+                allocate(&mut mini_fat, &mut mini_stream, data, MINI_SECTOR_SIZE)
+            } else {
+                allocate(&mut fat, &mut sectors, data, SECTOR_SIZE)
+            };
+            entries.push(directory_entry(name, STREAM, start, data.len()));
+        }
+        let mini_stream_start = allocate(&mut fat, &mut sectors, &mini_stream, SECTOR_SIZE);
+        let root = directory_entry("Root Entry", ROOT, mini_stream_start, mini_stream.len());
+        entries.insert(0, root);
+        let directory_start = allocate(&mut fat, &mut sectors, &entries.concat(), SECTOR_SIZE);
+        let mini_fat: Vec<u8> = mini_fat.iter().flat_map(|entry| entry.to_le_bytes()).collect();
+        let mini_fat_start = allocate(&mut fat, &mut sectors, &mini_fat, SECTOR_SIZE);
+        // The allocation table comes last, and covers its own sectors too.
+        let entries_per_sector = SECTOR_SIZE / 4;
+        let fat_start = fat.len();
+        let mut fat_count = 1;
+        while fat.len() + fat_count > fat_count * entries_per_sector {
+            fat_count += 1;
+        }
+        fat.resize(fat_start + fat_count, FAT_SECTOR);
+        fat.resize(fat_count * entries_per_sector, FREE_SECTOR);
+        sectors.extend(fat.iter().flat_map(|entry| entry.to_le_bytes()));
+        let mut header = vec![0; HEADER_SIZE];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            header[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0, &SIGNATURE);
+        put(0x18, &[0x3e, 0, 3, 0, 0xfe, 0xff, 9, 0, 6, 0]);
+        put(0x2c, &(fat_count as u32).to_le_bytes());
+        put(0x30, &directory_start.to_le_bytes());
+        put(0x38, &(MINI_STREAM_CUTOFF as u32).to_le_bytes());
+        put(0x3c, &mini_fat_start.to_le_bytes());
+        put(0x40, &(mini_fat.len().div_ceil(SECTOR_SIZE) as u32).to_le_bytes());
+        put(0x44, &END_OF_CHAIN.to_le_bytes());
+        for index in 0..HEADER_DIFAT_ENTRIES {
+            let sector = if index < fat_count { (fat_start + index) as u32 } else { FREE_SECTOR };
+            put(0x4c + index * 4, &sector.to_le_bytes());
+        }
+        [header, sectors].concat()
+    }
+
+    #[test]
+    fn streams_are_read_back() {
+        let small = b"a stream held in the mini stream".repeat(10);
+        let large: Vec<u8> = (0..20_000).map(|index| (index % 251) as u8).collect();
+        let data = compound_file(&[("Small", &small), ("Empty", b""), ("Large", &large)]);
+        assert!(is_compound_file(&data));
+        let file = CompoundFile::new(&data).unwrap();
+        assert_eq!(file.stream(&["Small"]).unwrap(), small);
+        assert_eq!(file.stream(&["Empty"]).unwrap(), b"");
+        assert_eq!(file.stream(&["Missing", "Large"]).unwrap(), large);
+        assert_eq!(
+            file.stream(&["Workbook", "Book"]).err().unwrap().to_string(),
+            "The file has no Workbook stream.",
+        );
+    }
+
+    #[test]
+    fn corrupt_files_are_rejected() {
+        let large = vec![1; 10_000];
+        let data = compound_file(&[("Large", &large)]);
+        assert!(CompoundFile::new(&data[..HEADER_SIZE - 1]).is_err());
+        assert!(CompoundFile::new(&data[1..]).is_err());
+        assert!(CompoundFile::new(&data[..data.len() - SECTOR_SIZE]).is_err());
+        let mut unsupported = data.clone();
+        unsupported[0x1e] = 10;
+        assert!(CompoundFile::new(&unsupported).is_err());
+        let mut looping = data;
+        // The first sector of the stream is made to follow itself.
+        let fat_offset = HEADER_SIZE + SECTOR_SIZE * u32_at(&looping, 0x4c).unwrap() as usize;
+        looping[fat_offset..fat_offset + 4].copy_from_slice(&0u32.to_le_bytes());
+        let file = CompoundFile::new(&looping).unwrap();
+        assert!(file.stream(&["Large"]).is_err());
+    }
+}
@@ -0,0 +1,292 @@
+use std::io::{self, Write};
+
+use super::inflate::{
+    DISTANCE_BASES,
+    DISTANCE_EXTRA_BITS,
+    LENGTH_BASES,
+    LENGTH_EXTRA_BITS,
+    WINDOW_SIZE,
+};
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Number of bytes of input compressed into a block at once.
+const BLOCK_SIZE: usize = 1 << 16;
+const HASH_BITS: u32 = 15;
+/// Number of earlier occurrences of a prefix searched for the longest match.
+const MAX_CHAIN: usize = 32;
+const END_OF_BLOCK: u16 = 256;
+const NONE: usize = usize::MAX;
+
+/// Compresses data written to it into raw DEFLATE data (RFC 1951), finding repeated
+/// strings with hash chains and encoding them with the fixed Huffman codes.
+pub struct Deflater<W: Write> {
+    sink: W,
+    bit_buffer: u64,
+    bit_count: u32,
+    /// Compressed bytes not written to the sink yet.
+    output: Vec<u8>,
+    /// The last `WINDOW_SIZE` bytes compressed, followed by the bytes not compressed yet.
+    data: Vec<u8>,
+    /// Position in the whole input of the first byte of `data`.
+    start: usize,
+    /// Index in `data` of the first byte not compressed yet.
+    pending: usize,
+    /// Last position of each hash of three bytes.
+    head: Vec<usize>,
+    /// Previous position with the same hash as each position within the window.
+    previous: Vec<usize>,
+    is_finished: bool,
+}
+
+/// Returns the code of a symbol of the fixed literal/length code along with its length.
+fn literal_code(symbol: u16) -> (u16, u32) {
+    match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    }
+}
+
+/// Returns the index of the largest base not exceeding the value.
+fn base_index(bases: &[u16], value: usize) -> usize {
+    bases.partition_point(|base| usize::from(*base) <= value) - 1
+}
+
+impl<W: Write> Deflater<W> {
+    pub fn new(sink: W) -> Self {
+        Self {
+            sink,
+            bit_buffer: 0,
+            bit_count: 0,
+            output: vec![],
+            data: vec![],
+            start: 0,
+            pending: 0,
+            head: vec![NONE; 1 << HASH_BITS],
+            previous: vec![NONE; WINDOW_SIZE],
+            is_finished: false,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.sink
+    }
+
+    pub fn into_inner(self) -> W {
+        self.sink
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bit_buffer |= u64::from(value) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.output.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, whose bits are stored starting with the most significant one.
+    fn write_code(&mut self, code: u16, length: u32) {
+        self.write_bits(u32::from(code.reverse_bits() >> (16 - length)), length);
+    }
+
+    fn write_symbol(&mut self, symbol: u16) {
+        let (code, length) = literal_code(symbol);
+        self.write_code(code, length);
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let index = base_index(&LENGTH_BASES, length);
+        self.write_symbol(257 + index as u16);
+        let extra = (length - usize::from(LENGTH_BASES[index])) as u32;
+        self.write_bits(extra, u32::from(LENGTH_EXTRA_BITS[index]));
+        let index = base_index(&DISTANCE_BASES, distance);
+        self.write_code(index as u16, 5);
+        let extra = (distance - usize::from(DISTANCE_BASES[index])) as u32;
+        self.write_bits(extra, u32::from(DISTANCE_EXTRA_BITS[index]));
+    }
+
+    fn hash(&self, index: usize) -> usize {
+        let bytes = &self.data[index..index + MIN_MATCH];
+        let value = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    /// Records the position of the string starting at the given index of `data`.
+    fn insert(&mut self, index: usize) {
+        if index + MIN_MATCH <= self.data.len() {
+            let hash = self.hash(index);
+            let position = self.start + index;
+            self.previous[position % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = position;
+        }
+    }
+
+    /// Returns the length and distance of the longest earlier match of the string starting
+    /// at the given index of `data`, if there is one of at least `MIN_MATCH` bytes.
+    fn longest_match(&self, index: usize) -> Option<(usize, usize)> {
+        if index + MIN_MATCH > self.data.len() {
+            return None;
+        }
+        let position = self.start + index;
+        let max_length = MAX_MATCH.min(self.data.len() - index);
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[self.hash(index)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == NONE || candidate < self.start || position - candidate > WINDOW_SIZE {
+                break;
+            }
+            let earlier = &self.data[candidate - self.start..];
+            let length = earlier.iter().zip(&self.data[index..index + max_length])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length >= MIN_MATCH && best.is_none_or(|(best_length, _)| length > best_length) {
+                best = Some((length, position - candidate));
+                if length == max_length {
+                    break;
+                }
+            }
+            let next = self.previous[candidate % WINDOW_SIZE];
+            if next == NONE || next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        best
+    }
+
+    /// Compresses the pending data into a block, keeping the last `WINDOW_SIZE` bytes
+    /// for later matches.
+    fn compress_block(&mut self, is_last: bool) -> io::Result<()> {
+        self.write_bits(u32::from(is_last), 1);
+        self.write_bits(1, 2);
+        let mut index = self.pending;
+        while index < self.data.len() {
+            match self.longest_match(index) {
+                Some((length, distance)) => {
+                    self.write_match(length, distance);
+                    for offset in 0..length {
+                        self.insert(index + offset);
+                    }
+                    index += length;
+                },
+                None => {
+                    self.write_symbol(u16::from(self.data[index]));
+                    self.insert(index);
+                    index += 1;
+                },
+            }
+        }
+        self.write_symbol(END_OF_BLOCK);
+        self.pending = self.data.len();
+        if self.data.len() > WINDOW_SIZE {
+            let excess = self.data.len() - WINDOW_SIZE;
+            self.data.drain(..excess);
+            self.start += excess;
+            self.pending -= excess;
+        }
+        if is_last && self.bit_count > 0 {
+            self.write_bits(0, 8 - self.bit_count);
+        }
+        self.sink.write_all(&self.output)?;
+        self.output.clear();
+        Ok(())
+    }
+
+    /// Compresses the rest of the data and ends the compressed stream; further data cannot
+    /// be written then.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.is_finished {
+            self.is_finished = true;
+            self.compress_block(true)?;
+        }
+        self.sink.flush()
+    }
+}
+
+impl<W: Write> Write for Deflater<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_finished {
+            return Err(io::Error::other("The compressed stream is finished."));
+        }
+        self.data.extend_from_slice(buf);
+        if self.data.len() - self.pending >= BLOCK_SIZE {
+            self.compress_block(false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::super::inflate::{inflate, Inflater};
+    use super::*;
+
+    /// A xorshift generator, which makes the test reproducible.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    /// Returns data made of random bytes and of copies of earlier parts of it.
+    fn data(random: &mut Random, length: usize) -> Vec<u8> {
+        let mut data = vec![];
+        while data.len() < length {
+            if data.len() > 10 && random.below(2) == 0 {
+                let start = random.below(data.len());
+                let end = (start + 3 + random.below(300)).min(data.len());
+                data.extend_from_within(start..end);
+            } else {
+                data.push(b'a' + random.below(20) as u8);
+            }
+        }
+        data.truncate(length);
+        data
+    }
+
+    fn deflate(data: &[u8], chunk_size: usize) -> Vec<u8> {
+        let mut deflater = Deflater::new(vec![]);
+        for chunk in data.chunks(chunk_size) {
+            deflater.write_all(chunk).unwrap();
+        }
+        deflater.finish().unwrap();
+        deflater.sink
+    }
+
+    #[test]
+    fn deflated_data_is_inflated_back() {
+        let mut random = Random(0x5eed_1234_abcd_0002);
+        for length in [0, 1, 2, 3, 100, 5000, BLOCK_SIZE + WINDOW_SIZE + 1, 3 * BLOCK_SIZE] {
+            let data = data(&mut random, length);
+            for chunk_size in [1000, BLOCK_SIZE] {
+                let compressed = deflate(&data, chunk_size);
+                let mut inflated = vec![];
+                Inflater::new(compressed.as_slice()).read_to_end(&mut inflated).unwrap();
+                assert!(inflated == data, "length: {length}, chunk size: {chunk_size}");
+            }
+        }
+    }
+
+    #[test]
+    fn repeated_data_is_compressed() {
+        let data = b"solidify ".repeat(10_000);
+        let compressed = deflate(&data, BLOCK_SIZE);
+        assert!(compressed.len() < data.len() / 50, "{}", compressed.len());
+        assert_eq!(inflate(&compressed, data.len()).unwrap(), data);
+    }
+}
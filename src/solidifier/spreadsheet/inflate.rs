@@ -0,0 +1,390 @@
+use std::io::{self, Read};
+
+use anyhow::{bail, ensure, Context, Result};
+
+const MAX_BITS: usize = 15;
+/// Largest distance back that data can be copied from.
+pub const WINDOW_SIZE: usize = 1 << 15;
+
+/// Base lengths of length codes 257 to 285, followed by the numbers of their extra bits.
+pub const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+pub const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+pub const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which the code lengths of the code length alphabet are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn read_exact(source: &mut impl Read, buf: &mut [u8]) -> Result<()> {
+    match source.read_exact(buf) {
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+            bail!("Unexpected end of compressed data.")
+        },
+        result => Ok(result?),
+    }
+}
+
+/// Reads the bits of a source, taking bytes from it only as they are needed, so that once
+/// aligned to a byte, the source is positioned right after the bits read.
+struct BitReader<R: Read> {
+    source: R,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(source: R) -> Self {
+        Self {
+            source,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32> {
+        while self.bit_count < count {
+            let [byte] = self.bytes::<1>()?;
+            self.bit_buffer |= u32::from(byte) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1 << count) - 1);
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut bytes = [0; N];
+        read_exact(&mut self.source, &mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+/// Canonical Huffman code, stored as the number of codes of each length along with
+/// the symbols ordered by their codes.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length > 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Self {
+            counts,
+            symbols,
+        }
+    }
+
+    fn decode(&self, reader: &mut BitReader<impl Read>) -> Result<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = i32::from(self.counts[length]);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("Invalid Huffman code in compressed data.")
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader<impl Read>) -> Result<(Huffman, Huffman)> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    let mut code_length_lengths = [0; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_length_lengths);
+    let mut lengths = vec![];
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_code.decode(reader)?;
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (
+                *lengths.last().context("Invalid code lengths in compressed data.")?,
+                3 + reader.bits(2)?,
+            ),
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend((0..repeat).map(|_| length));
+    }
+    ensure!(
+        lengths.len() == literal_count + distance_count,
+        "Invalid code lengths in compressed data.",
+    );
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+enum Block {
+    /// The header of a block is next.
+    Header,
+    /// A stored block with the given number of bytes left.
+    Stored(usize),
+    Compressed {
+        literals: Huffman,
+        distances: Huffman,
+    },
+    /// The last block has ended.
+    Finished,
+}
+
+/// Decompresses raw DEFLATE data (RFC 1951) read from a source as it is read itself.
+pub struct Inflater<R: Read> {
+    reader: BitReader<R>,
+    block: Block,
+    is_last_block: bool,
+    /// Data decompressed so far, of which at least the last `WINDOW_SIZE` bytes are kept.
+    history: Vec<u8>,
+    /// Number of bytes of the history already read.
+    position: usize,
+}
+
+impl<R: Read> Inflater<R> {
+    pub fn new(source: R) -> Self {
+        Self {
+            reader: BitReader::new(source),
+            block: Block::Header,
+            is_last_block: false,
+            history: vec![],
+            position: 0,
+        }
+    }
+
+    /// Returns the source, positioned right after the compressed data once it has been read.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader.source
+    }
+
+    /// Starts decompressing another stream of DEFLATE data from the source.
+    pub fn reset(&mut self) {
+        self.reader.align_to_byte();
+        self.block = Block::Header;
+        self.is_last_block = false;
+        self.history.clear();
+        self.position = 0;
+    }
+
+    /// Decompresses the next part of the data: a block header, a part of a stored block,
+    /// or a single symbol of a compressed block.
+    fn step(&mut self) -> Result<()> {
+        let reader = &mut self.reader;
+        match &mut self.block {
+            Block::Header => {
+                self.is_last_block = reader.bits(1)? == 1;
+                self.block = match reader.bits(2)? {
+                    0 => {
+                        reader.align_to_byte();
+                        let header = reader.bytes::<4>()?;
+                        let length = u16::from_le_bytes([header[0], header[1]]);
+                        let complement = u16::from_le_bytes([header[2], header[3]]);
+                        ensure!(length == !complement, "Invalid stored block in compressed data.");
+                        Block::Stored(length as usize)
+                    },
+                    1 => {
+                        let (literals, distances) = fixed_codes();
+                        Block::Compressed { literals, distances }
+                    },
+                    2 => {
+                        let (literals, distances) = dynamic_codes(reader)?;
+                        Block::Compressed { literals, distances }
+                    },
+                    _ => bail!("Invalid block type in compressed data."),
+                };
+            },
+            Block::Stored(0) => self.end_block(),
+            Block::Stored(remaining) => {
+                let start = self.history.len();
+                let count = (*remaining).min(WINDOW_SIZE);
+                self.history.resize(start + count, 0);
+                read_exact(&mut reader.source, &mut self.history[start..])?;
+                *remaining -= count;
+            },
+            Block::Compressed { literals, distances } => {
+                let symbol = literals.decode(reader)? as usize;
+                match symbol {
+                    0..=255 => self.history.push(symbol as u8),
+                    256 => self.end_block(),
+                    _ => {
+                        let index = symbol - 257;
+                        ensure!(
+                            index < LENGTH_BASES.len(),
+                            "Invalid length code in compressed data.",
+                        );
+                        let length = usize::from(LENGTH_BASES[index])
+                            + reader.bits(u32::from(LENGTH_EXTRA_BITS[index]))? as usize;
+                        let index = distances.decode(reader)? as usize;
+                        ensure!(
+                            index < DISTANCE_BASES.len(),
+                            "Invalid distance code in compressed data.",
+                        );
+                        let distance = usize::from(DISTANCE_BASES[index])
+                            + reader.bits(u32::from(DISTANCE_EXTRA_BITS[index]))? as usize;
+                        let history = &mut self.history;
+                        ensure!(distance <= history.len(), "Invalid distance in compressed data.");
+                        let start = history.len() - distance;
+                        for offset in 0..length {
+                            history.push(history[start + offset]);
+                        }
+                    },
+                }
+            },
+            Block::Finished => {},
+        }
+        Ok(())
+    }
+
+    fn end_block(&mut self) {
+        self.block = if self.is_last_block { Block::Finished } else { Block::Header };
+    }
+}
+
+impl<R: Read> Read for Inflater<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position > 2 * WINDOW_SIZE {
+            let excess = self.position - WINDOW_SIZE;
+            self.history.drain(..excess);
+            self.position -= excess;
+        }
+        while self.position == self.history.len() && !matches!(self.block, Block::Finished) {
+            self.step().map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        }
+        let count = buf.len().min(self.history.len() - self.position);
+        buf[..count].copy_from_slice(&self.history[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+/// Decompresses raw DEFLATE data (RFC 1951), as found in ZIP archives.
+pub fn inflate(data: &[u8], expected_size: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(expected_size);
+    Inflater::new(data).read_to_end(&mut output)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|index| u8::from_str_radix(&text[index..index + 2], 16))
+            .collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn blocks_of_each_type_are_inflated() {
+        let stored = [&[0x01, 0x05, 0x00, 0xfa, 0xff][..], b"hello"].concat();
+        assert_eq!(inflate(&stored, 0).unwrap(), b"hello");
+        let fixed = hex("cb48cdc9c9d751c840a214caf38b7252b800");
+        assert_eq!(inflate(&fixed, 0).unwrap(), b"hello, hello, hello world\n");
+        let dynamic = hex("2589c11100300c826605dd7f869aeb4b040285d40aba2b558b39c52872815f678cdb07");
+        assert_eq!(
+            inflate(&dynamic, 0).unwrap(),
+            b"acaadaacdbdbaabbcaabadbbbdabcdbaaabdacbabcaaabcaabaabdbcbbaa",
+        );
+    }
+
+    #[test]
+    fn corrupt_data_is_rejected() {
+        let fixed = hex("cb48cdc9c9d751c840a214caf38b7252b800");
+        let error = inflate(&fixed[..fixed.len() - 2], 0).unwrap_err();
+        assert_eq!(error.to_string(), "Unexpected end of compressed data.");
+        assert!(inflate(&[0x01, 0x05, 0x00, 0x00, 0x00], 0).is_err());
+        assert!(inflate(&[0x07], 0).is_err());
+    }
+
+    #[test]
+    fn long_output_is_read_in_small_parts() {
+        // A literal followed by copies of it, each of the maximum length (fixed codes).
+        let mut writer = vec![];
+        let mut bits = 0u64;
+        let mut count = 0;
+        let mut push = |value: u32, length: u32, reverse: bool| {
+            let value = if reverse { value.reverse_bits() >> (32 - length) } else { value };
+            bits |= u64::from(value) << count;
+            count += length;
+            while count >= 8 {
+                writer.push(bits as u8);
+                bits >>= 8;
+                count -= 8;
+            }
+        };
+        push(1, 1, false);
+        push(1, 2, false);
+        push(0x30 + u32::from(b'x'), 8, true);
+        let copies = 3 * WINDOW_SIZE / 258;
+        for _ in 0..copies {
+            // Length 258 is symbol 285, and distance 1 is code 0.
+            push(0xc5, 8, true);
+            push(0, 5, true);
+        }
+        push(0, 7, true);
+        push(0, 7, false);
+        let mut inflater = Inflater::new(writer.as_slice());
+        let mut output = vec![];
+        let mut buf = [0; 100];
+        loop {
+            let count = inflater.read(&mut buf).unwrap();
+            if count == 0 {
+                break;
+            }
+            output.extend_from_slice(&buf[..count]);
+        }
+        assert_eq!(output.len(), 1 + copies * 258);
+        assert!(output.iter().all(|byte| *byte == b'x'));
+        assert!(inflater.history.len() <= 3 * WINDOW_SIZE);
+    }
+}
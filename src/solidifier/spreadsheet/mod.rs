@@ -0,0 +1,327 @@
+mod cfb;
+mod deflate;
+mod inflate;
+mod ods;
+mod xls;
+mod xlsx;
+mod xml;
+mod zip;
+
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use crate::params::Worksheet;
+
+use super::delimited::RecordWrite;
+
+use cfb::{is_compound_file, CompoundFile};
+use zip::{is_archive, Archive};
+
+pub use deflate::Deflater;
+pub use inflate::Inflater;
+pub use zip::update_crc32;
+
+const EXTENSIONS: [&str; 4] = ["xlsx", "xlsm", "xls", "ods"];
+const SECONDS_PER_DAY: f64 = 86_400.0;
+/// Serial number of 9999-12-31, the last date spreadsheets can represent.
+const MAX_DATE_SERIAL: f64 = 2_958_465.0;
+/// Largest magnitude up to which integral numbers are rendered without a fractional part.
+const MAX_INTEGER: f64 = 1e15;
+
+/// Checks whether an input is a spreadsheet, by the extension of its path or, failing that,
+/// by its first bytes.
+pub fn is_spreadsheet(path: &Path, header: &[u8]) -> bool {
+    has_spreadsheet_extension(path) || is_archive(header) || is_compound_file(header)
+}
+
+pub fn has_spreadsheet_extension(path: &Path) -> bool {
+    path.extension().and_then(|extension| extension.to_str()).is_some_and(
+        |extension| EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+    )
+}
+
+pub fn has_xlsx_extension(path: &Path) -> bool {
+    path.extension().and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("xlsx"))
+}
+
+/// Creates a writer of an xlsx workbook, optionally highlighting the missing values
+/// of merged rows.
+pub fn xlsx_writer<'a, W: Write + 'a>(
+    sink: W,
+    highlight_missing: bool,
+) -> Box<dyn RecordWrite + 'a> {
+    Box::new(xlsx::Writer::new(sink, highlight_missing))
+}
+
+/// Reads the records of a worksheet of an xlsx, xls or ods file, telling the format
+/// by the contents of the file.
+pub fn read_spreadsheet(data: &[u8], worksheet: &Worksheet) -> Result<Vec<Vec<String>>> {
+    if is_compound_file(data) {
+        return xls::read(&CompoundFile::new(data)?, worksheet);
+    }
+    if is_archive(data) {
+        let archive = Archive::new(data)?;
+        if archive.contains(ods::CONTENT) {
+            return ods::read(&archive, worksheet);
+        }
+        return xlsx::read(&archive, worksheet);
+    }
+    bail!("The file is neither an xlsx, xls nor ods spreadsheet.")
+}
+
+/// Finds the position of the chosen worksheet among the worksheets with the given names.
+fn find_worksheet<S: AsRef<str>>(names: &[S], worksheet: &Worksheet) -> Result<usize> {
+    match worksheet {
+        Worksheet::Index(index) => index.checked_sub(1).filter(|index| *index < names.len())
+            .with_context(|| format!(
+                "There is no worksheet #{index} (total worksheets: {count}).",
+                count = names.len(),
+            )),
+        Worksheet::Name(name) => names.iter().position(|candidate| candidate.as_ref() == name)
+            .with_context(|| format!(
+                "There is no worksheet named '{name}' (worksheets: {names}).",
+                names = names.iter().map(
+                    |name| format!("'{}'", name.as_ref())
+                ).collect::<Vec<_>>().join(", "),
+            )),
+    }
+}
+
+/// Non-empty cells of a worksheet, turned into records once all of them are known.
+#[derive(Default)]
+struct Grid {
+    cells: Vec<(usize, usize, String)>,
+}
+
+impl Grid {
+    fn set(&mut self, row: usize, column: usize, value: String) {
+        if !value.is_empty() {
+            self.cells.push((row, column, value));
+        }
+    }
+
+    /// Returns the rows that have non-empty cells, all padded to the same width.
+    fn into_records(mut self) -> Vec<Vec<String>> {
+        self.cells.sort_by_key(|(row, column, _)| (*row, *column));
+        let width = self.cells.iter().map(|(_, column, _)| column + 1).max().unwrap_or(0);
+        let mut records: Vec<Vec<String>> = vec![];
+        let mut last_row = None;
+        for (row, column, value) in self.cells {
+            if last_row != Some(row) {
+                records.push(vec![String::new(); width]);
+                last_row = Some(row);
+            }
+            records.last_mut().expect("a record should have been added")[column] = value;
+        }
+        records
+    }
+}
+
+/// Renders a number the same way regardless of its formatting in the spreadsheet:
+/// integers without a fractional part, other numbers with as many digits as needed
+/// to represent them exactly (and never in exponential notation).
+fn render_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < MAX_INTEGER {
+        (value as i64).to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn render_boolean(value: bool) -> String {
+    String::from(if value { "TRUE" } else { "FALSE" })
+}
+
+/// Which parts of a date and time a number format displays.
+#[derive(Clone, Copy, Default, Eq, PartialEq)]
+struct DateFormat {
+    date: bool,
+    time: bool,
+}
+
+impl DateFormat {
+    fn is_date(&self) -> bool {
+        self.date || self.time
+    }
+}
+
+/// Classifies the built-in number formats of Excel.
+fn builtin_date_format(id: u32) -> DateFormat {
+    match id {
+        14..=17 | 27..=31 | 34..=36 | 50..=58 => DateFormat { date: true, time: false },
+        18..=21 | 32 | 33 | 45..=47 => DateFormat { date: false, time: true },
+        22 => DateFormat { date: true, time: true },
+        _ => DateFormat::default(),
+    }
+}
+
+/// Classifies a custom number format code by the date and time placeholders in it.
+fn custom_date_format(code: &str) -> DateFormat {
+    let mut format = DateFormat::default();
+    // The format applying to positive numbers is the first section.
+    let code = code.split(';').next().unwrap_or_default().to_ascii_lowercase();
+    let mut letters = vec![];
+    let mut chars = code.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '"' => {
+                chars.by_ref().find(|char| *char == '"');
+            },
+            '\\' | '_' | '*' => {
+                chars.next();
+            },
+            '[' => {
+                let contents: String = chars.by_ref().take_while(|char| *char != ']').collect();
+                // Elapsed time, as in [h]:mm.
+                if !contents.is_empty() && contents.chars().all(|char| "hms".contains(char)) {
+                    format.time = true;
+                    // Elapsed hours and seconds tell the minutes next to them from months.
+                    letters.extend(contents.chars().filter(|char| *char != 'm'));
+                }
+            },
+            // AM/PM markers are recognized by their letters alone.
+            _ if char.is_ascii_alphabetic() => letters.push(char),
+            _ => {},
+        }
+    }
+    let mut letters: String = letters.into_iter().collect();
+    for marker in ["ampm", "ap"] {
+        if letters.contains(marker) {
+            format.time = true;
+            letters = letters.replace(marker, "");
+        }
+    }
+    for (index, letter) in letters.char_indices() {
+        match letter {
+            'y' | 'd' => format.date = true,
+            'h' | 's' => format.time = true,
+            'm' => {
+                // "m" stands for minutes right after hours or right before seconds.
+                let previous = letters[..index].trim_end_matches('m').chars().last();
+                let next = letters[index..].trim_start_matches('m').chars().next();
+                if previous == Some('h') || next == Some('s') {
+                    format.time = true;
+                } else {
+                    format.date = true;
+                }
+            },
+            _ => {},
+        }
+    }
+    format
+}
+
+/// Converts a number of days since 1970-01-01 to a (year, month, day) date.
+fn civil_date(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+        - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn render_time(seconds: i64) -> String {
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Renders a date serial number (the number of days since the epoch of the spreadsheet,
+/// with the time of day as the fractional part) as YYYY-MM-DD, HH:MM:SS or both, depending
+/// on what the format displays; durations longer than a day are rendered as hours.
+fn render_date(serial: f64, format: DateFormat, is_1904: bool) -> String {
+    if !(0.0..=MAX_DATE_SERIAL).contains(&serial) {
+        return render_number(serial);
+    }
+    let total_seconds = (serial * SECONDS_PER_DAY).round() as i64;
+    let (days, seconds) = (total_seconds / 86_400, total_seconds % 86_400);
+    if !format.date {
+        return render_time(total_seconds);
+    }
+    let date = if is_1904 {
+        // 1904-01-01 is day 0.
+        let (year, month, day) = civil_date(days - 24_107);
+        format!("{year:04}-{month:02}-{day:02}")
+    } else if days == 60 {
+        // Day 60 is February 29, 1900, which spreadsheets consider to exist.
+        String::from("1900-02-29")
+    } else {
+        // 1900-01-01 is day 1, and days after the nonexistent leap day are shifted by it.
+        let (year, month, day) = civil_date(days - if days < 60 { 25_568 } else { 25_569 });
+        format!("{year:04}-{month:02}-{day:02}")
+    };
+    if format.time {
+        format!("{date} {}", render_time(seconds))
+    } else {
+        date
+    }
+}
+
+/// Renders an ISO 8601 date or date and time (as stored in ods and xlsx files) the same
+/// way as date serial numbers, dropping any fractions of seconds and time zones.
+fn render_iso_date(value: &str) -> String {
+    match value.split_once('T') {
+        Some((date, time)) => {
+            let time: String = time.chars().take_while(
+                |char| char.is_ascii_digit() || *char == ':'
+            ).collect();
+            format!("{date} {time}")
+        },
+        None => String::from(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATE: DateFormat = DateFormat { date: true, time: false };
+    const TIME: DateFormat = DateFormat { date: false, time: true };
+    const DATE_TIME: DateFormat = DateFormat { date: true, time: true };
+
+    #[test]
+    fn date_serials_are_rendered() {
+        assert_eq!(render_date(1.0, DATE, false), "1900-01-01");
+        assert_eq!(render_date(59.0, DATE, false), "1900-02-28");
+        assert_eq!(render_date(60.0, DATE, false), "1900-02-29");
+        assert_eq!(render_date(61.0, DATE, false), "1900-03-01");
+        assert_eq!(render_date(45_000.5, DATE_TIME, false), "2023-03-15 12:00:00");
+        assert_eq!(render_date(MAX_DATE_SERIAL, DATE, false), "9999-12-31");
+        assert_eq!(render_date(0.0, DATE, true), "1904-01-01");
+        assert_eq!(render_date(1.5, TIME, false), "36:00:00");
+        assert_eq!(render_date(0.999_999_9, TIME, false), "24:00:00");
+        assert_eq!(render_date(-1.0, DATE, false), "-1");
+        assert_eq!(render_date(MAX_DATE_SERIAL + 1.0, DATE, false), "2958466");
+    }
+
+    #[test]
+    fn numbers_are_rendered() {
+        assert_eq!(render_number(42.0), "42");
+        assert_eq!(render_number(-0.125), "-0.125");
+        assert_eq!(render_number(1e20), "100000000000000000000");
+        assert_eq!(render_iso_date("2024-01-02T03:04:05.678+01:00"), "2024-01-02 03:04:05");
+        assert_eq!(render_iso_date("2024-01-02"), "2024-01-02");
+    }
+
+    #[test]
+    fn date_formats_are_classified() {
+        assert!(builtin_date_format(14) == DATE && builtin_date_format(22) == DATE_TIME);
+        assert!(builtin_date_format(20) == TIME && !builtin_date_format(2).is_date());
+        assert!(custom_date_format("yyyy-mm-dd") == DATE);
+        assert!(custom_date_format("h:mm") == TIME);
+        assert!(custom_date_format("mm:ss") == TIME);
+        assert!(custom_date_format("[h]:mm") == TIME);
+        assert!(custom_date_format("[mm]:ss") == TIME);
+        assert!(custom_date_format("m/d/yyyy h:mm AM/PM") == DATE_TIME);
+        assert!(!custom_date_format("0.00").is_date());
+        assert!(!custom_date_format(r#""days: "0"#).is_date());
+        assert!(!custom_date_format("#,##0;[Red]-#,##0").is_date());
+    }
+}
@@ -0,0 +1,332 @@
+use anyhow::Result;
+
+use crate::params::Worksheet;
+
+use super::xml::{document, Event, Reader};
+use super::zip::Archive;
+use super::{
+    find_worksheet,
+    render_boolean,
+    render_iso_date,
+    render_number,
+    render_time,
+    Grid,
+    SECONDS_PER_DAY,
+};
+
+/// Part of an OpenDocument package holding the contents of the document.
+pub const CONTENT: &str = "content.xml";
+
+/// Parses an ISO 8601 duration such as `PT10H30M00S` into seconds.
+fn parse_duration(value: &str) -> Option<f64> {
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(value) => (-1.0, value),
+        None => (1.0, value),
+    };
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    let mut in_time = false;
+    for char in value.strip_prefix('P')?.chars() {
+        let unit = match char {
+            'T' => {
+                in_time = true;
+                continue;
+            },
+            '0'..='9' | '.' => {
+                number.push(char);
+                continue;
+            },
+            'D' if !in_time => SECONDS_PER_DAY,
+            'H' if in_time => 3600.0,
+            'M' if in_time => 60.0,
+            'S' if in_time => 1.0,
+            _ => return None,
+        };
+        seconds += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+    Some(sign * seconds)
+}
+
+/// A cell of the worksheet being read.
+struct Cell {
+    value_type: String,
+    value: Option<String>,
+    text: String,
+    paragraphs: usize,
+    /// Depth of the paragraphs being read, outside of which text is only formatting.
+    paragraph_depth: usize,
+    repeat: usize,
+    /// Depth of the comments within the cell, whose text is not part of the value.
+    annotation_depth: usize,
+}
+
+impl Cell {
+    fn new(event: &Event) -> Self {
+        let value_type = event.attribute("value-type").unwrap_or("string");
+        let value = match value_type {
+            "date" => event.attribute("date-value"),
+            "time" => event.attribute("time-value"),
+            "boolean" => event.attribute("boolean-value"),
+            _ => event.attribute("value"),
+        };
+        Self {
+            value_type: String::from(value_type),
+            value: value.map(String::from),
+            text: String::new(),
+            paragraphs: 0,
+            paragraph_depth: 0,
+            repeat: repeat(event, "number-columns-repeated"),
+            annotation_depth: 0,
+        }
+    }
+
+    fn handle(&mut self, event: &Event) {
+        if self.annotation_depth > 0 {
+            match event {
+                Event::Start { name: "annotation", .. } => self.annotation_depth += 1,
+                Event::End("annotation") => self.annotation_depth -= 1,
+                _ => {},
+            }
+            return;
+        }
+        match event {
+            Event::Start { name: "annotation", .. } => self.annotation_depth += 1,
+            Event::Start { name: "p" | "h", .. } => {
+                if self.paragraphs > 0 {
+                    self.text.push('\n');
+                }
+                self.paragraphs += 1;
+                self.paragraph_depth += 1;
+            },
+            Event::End("p" | "h") => {
+                self.paragraph_depth = self.paragraph_depth.saturating_sub(1);
+            },
+            Event::Start { name: "s", .. } => {
+                let count = event.attribute("c").and_then(|c| c.parse().ok()).unwrap_or(1);
+                self.text.extend((0..count).map(|_| ' '));
+            },
+            Event::Start { name: "tab", .. } => self.text.push('\t'),
+            Event::Start { name: "line-break", .. } => self.text.push('\n'),
+            Event::Text(text) if self.paragraph_depth > 0 => self.text.push_str(text),
+            _ => {},
+        }
+    }
+
+    fn render(self) -> String {
+        let Some(value) = self.value else {
+            return self.text;
+        };
+        let rendered = match self.value_type.as_str() {
+            "float" | "percentage" | "currency" => value.parse().ok().map(render_number),
+            "date" => Some(render_iso_date(&value)),
+            "time" => parse_duration(&value).map(|seconds| render_time(seconds.round() as i64)),
+            "boolean" => Some(render_boolean(value == "true")),
+            _ => None,
+        };
+        rendered.unwrap_or(self.text)
+    }
+}
+
+fn repeat(event: &Event, attribute: &str) -> usize {
+    event.attribute(attribute).and_then(|count| count.parse().ok()).unwrap_or(1)
+}
+
+/// Reads the cells of the chosen table, given the events following its start.
+fn read_table<'a>(events: &mut impl Iterator<Item = Result<Event<'a>>>) -> Result<Grid> {
+    let mut grid = Grid::default();
+    let mut row = 0;
+    let mut row_repeat = 1;
+    let mut cells: Vec<(String, usize)> = vec![];
+    let mut cell: Option<Cell> = None;
+    let mut depth = 0;
+    for event in events {
+        let event = event?;
+        if let Some(current) = &mut cell {
+            let is_cell_end = current.annotation_depth == 0;
+            match event {
+                Event::End("table-cell" | "covered-table-cell") if is_cell_end => {
+                    let current = cell.take().expect("the cell should be present");
+                    let repeat = current.repeat;
+                    cells.push((current.render(), repeat));
+                },
+                _ => current.handle(&event),
+            }
+            continue;
+        }
+        match event {
+            Event::Start { name: "table", .. } => depth += 1,
+            Event::End("table") if depth == 0 => break,
+            Event::End("table") => depth -= 1,
+            _ if depth > 0 => {},
+            Event::Start { name: "table-row", .. } => {
+                row_repeat = repeat(&event, "number-rows-repeated");
+                cells.clear();
+            },
+            Event::Start { name: "table-cell" | "covered-table-cell", .. } => {
+                cell = Some(Cell::new(&event));
+            },
+            Event::End("table-row") => {
+                if cells.iter().all(|(value, _)| value.is_empty()) {
+                    row += row_repeat;
+                    continue;
+                }
+                for _ in 0..row_repeat {
+                    let mut column = 0;
+                    for (value, repeat) in &cells {
+                        if !value.is_empty() {
+                            for offset in 0..*repeat {
+                                grid.set(row, column + offset, value.clone());
+                            }
+                        }
+                        column += repeat;
+                    }
+                    row += 1;
+                }
+            },
+            _ => {},
+        }
+    }
+    Ok(grid)
+}
+
+/// Skips the events of a table that is not chosen, given the events following its start.
+fn skip_table<'a>(events: &mut impl Iterator<Item = Result<Event<'a>>>) -> Result<()> {
+    let mut depth = 0;
+    for event in events {
+        match event? {
+            Event::Start { name: "table", .. } => depth += 1,
+            Event::End("table") if depth == 0 => break,
+            Event::End("table") => depth -= 1,
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+pub fn read(archive: &Archive, worksheet: &Worksheet) -> Result<Vec<Vec<String>>> {
+    let data = archive.file(CONTENT)?;
+    let mut names = vec![];
+    let mut grid = None;
+    let mut events = Reader::new(document(&data)?);
+    while let Some(event) = events.next() {
+        let event = event?;
+        if let Event::Start { name: "table", .. } = event {
+            let name = String::from(event.attribute("name").unwrap_or_default());
+            let is_chosen = grid.is_none() && match worksheet {
+                Worksheet::Index(index) => *index == names.len() + 1,
+                Worksheet::Name(chosen) => *chosen == name,
+            };
+            names.push(name);
+            if is_chosen {
+                grid = Some(read_table(&mut events)?);
+            } else {
+                skip_table(&mut events)?;
+            }
+        }
+    }
+    find_worksheet(&names, worksheet)?;
+    Ok(grid.map(Grid::into_records).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::super::zip::ArchiveWriter;
+    use super::*;
+
+    fn spreadsheet(tables: &str) -> Vec<u8> {
+        let mut data = vec![];
+        let mut writer = ArchiveWriter::new(&mut data);
+        writer.start_entry("mimetype").unwrap();
+        writer.write_all(b"application/vnd.oasis.opendocument.spreadsheet").unwrap();
+        writer.start_entry(CONTENT).unwrap();
+        write!(
+            writer,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content
+    xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0"
+    xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0"
+    xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0">
+<office:body><office:spreadsheet>{tables}</office:spreadsheet></office:body>
+</office:document-content>"#,
+        ).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        data
+    }
+
+    fn strings(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter().map(|row| row.iter().map(|value| String::from(*value)).collect()).collect()
+    }
+
+    #[test]
+    fn cells_are_read() {
+        let data = spreadsheet(r#"
+<table:table table:name="Data">
+  <table:table-column table:number-columns-repeated="4"/>
+  <table:table-row>
+    <table:table-cell office:value-type="string"><text:p>id</text:p></table:table-cell>
+    <table:table-cell><text:p>text</text:p></table:table-cell>
+    <table:table-cell table:number-columns-repeated="2"><text:p>when</text:p></table:table-cell>
+  </table:table-row>
+  <table:table-row>
+    <table:table-cell office:value-type="float" office:value="1.50">
+      <text:p>1,5</text:p>
+    </table:table-cell>
+    <table:table-cell>
+      <office:annotation><text:p>a comment</text:p></office:annotation>
+      <text:p>two<text:s text:c="2"/>spaces &amp;</text:p><text:p>lines<text:tab/>!</text:p>
+    </table:table-cell>
+    <table:table-cell office:value-type="date" office:date-value="2024-01-02T03:04:05.5">
+      <text:p>02/01/24</text:p>
+    </table:table-cell>
+    <table:table-cell office:value-type="time" office:time-value="PT25H30M00S">
+      <text:p>25:30</text:p>
+    </table:table-cell>
+  </table:table-row>
+  <table:table-row table:number-rows-repeated="1000">
+    <table:table-cell table:number-columns-repeated="4"/>
+  </table:table-row>
+  <table:table-row table:number-rows-repeated="2">
+    <table:table-cell office:value-type="boolean" office:boolean-value="true">
+      <text:p>TRUE</text:p>
+    </table:table-cell>
+    <table:covered-table-cell/>
+    <table:table-cell office:value-type="percentage" office:value="0.25">
+      <text:p>25%</text:p>
+    </table:table-cell>
+  </table:table-row>
+</table:table>
+<table:table table:name="Other">
+  <table:table-row>
+    <table:table-cell office:value-type="date" office:date-value="2024-01-02">
+      <text:p>02/01/24</text:p>
+    </table:table-cell>
+  </table:table-row>
+</table:table>"#);
+        let archive = Archive::new(&data).unwrap();
+        assert_eq!(read(&archive, &Worksheet::Index(1)).unwrap(), strings(&[
+            &["id", "text", "when", "when"],
+            &["1.5", "two  spaces &\nlines\t!", "2024-01-02 03:04:05", "25:30:00"],
+            &["TRUE", "", "0.25", ""],
+            &["TRUE", "", "0.25", ""],
+        ]));
+        let other = read(&archive, &Worksheet::Name(String::from("Other"))).unwrap();
+        assert_eq!(other, strings(&[&["2024-01-02"]]));
+        assert_eq!(
+            read(&archive, &Worksheet::Name(String::from("Missing"))).err().unwrap().to_string(),
+            "There is no worksheet named 'Missing' (worksheets: 'Data', 'Other').",
+        );
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("PT10H30M00S"), Some(37_800.0));
+        assert_eq!(parse_duration("P1DT0.5S"), Some(86_400.5));
+        assert_eq!(parse_duration("-PT1M"), Some(-60.0));
+        assert_eq!(parse_duration("PT1D"), None);
+        assert_eq!(parse_duration("10:30"), None);
+    }
+}
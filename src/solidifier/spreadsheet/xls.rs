@@ -0,0 +1,524 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::params::Worksheet;
+
+use super::cfb::CompoundFile;
+use super::{
+    builtin_date_format,
+    custom_date_format,
+    find_worksheet,
+    render_boolean,
+    render_date,
+    render_number,
+    DateFormat,
+    Grid,
+};
+
+const WORKBOOK_STREAMS: [&str; 2] = ["Workbook", "Book"];
+const BIFF8: u16 = 0x0600;
+const WORKSHEET: u8 = 0;
+
+const BOF: u16 = 0x0809;
+const EOF: u16 = 0x000a;
+const FILEPASS: u16 = 0x002f;
+const DATEMODE: u16 = 0x0022;
+const FORMAT: u16 = 0x041e;
+const XF: u16 = 0x00e0;
+const BOUNDSHEET: u16 = 0x0085;
+const SST: u16 = 0x00fc;
+const CONTINUE: u16 = 0x003c;
+const LABELSST: u16 = 0x00fd;
+const LABEL: u16 = 0x0204;
+const NUMBER: u16 = 0x0203;
+const RK: u16 = 0x027e;
+const MULRK: u16 = 0x00bd;
+const FORMULA: u16 = 0x0006;
+const STRING: u16 = 0x0207;
+const BOOLERR: u16 = 0x0205;
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data.get(offset..offset + 2).context("A record is truncated.")?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data.get(offset..offset + 4).context("A record is truncated.")?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn f64_at(data: &[u8], offset: usize) -> Result<f64> {
+    let bytes = data.get(offset..offset + 8).context("A record is truncated.")?;
+    Ok(f64::from_le_bytes(bytes.try_into().expect("the slice should have 8 bytes")))
+}
+
+/// Decodes characters stored either as UTF-16 or as single bytes (the low bytes of
+/// UTF-16 code units).
+fn decode_chars(bytes: &[u8], is_wide: bool) -> String {
+    if is_wide {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(
+            |pair| u16::from_le_bytes([pair[0], pair[1]])
+        ).collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        bytes.iter().map(|byte| char::from(*byte)).collect()
+    }
+}
+
+/// Reads a string with a 16-bit character count, returning it along with its size in bytes.
+fn unicode_string(data: &[u8], offset: usize) -> Result<(String, usize)> {
+    let count = u16_at(data, offset)? as usize;
+    let flags = *data.get(offset + 2).context("A record is truncated.")?;
+    let is_wide = flags & 1 != 0;
+    let length = if is_wide { count * 2 } else { count };
+    let bytes = data.get(offset + 3..offset + 3 + length).context("A record is truncated.")?;
+    Ok((decode_chars(bytes, is_wide), 3 + length))
+}
+
+/// Decodes an RK value, a compressed representation of a number.
+fn rk_number(rk: u32) -> f64 {
+    let value = if rk & 2 != 0 {
+        f64::from(rk as i32 >> 2)
+    } else {
+        f64::from_bits(u64::from(rk & 0xffff_fffc) << 32)
+    };
+    if rk & 1 != 0 { value / 100.0 } else { value }
+}
+
+fn error_text(code: u8) -> String {
+    String::from(match code {
+        0x00 => "#NULL!",
+        0x07 => "#DIV/0!",
+        0x0f => "#VALUE!",
+        0x17 => "#REF!",
+        0x1d => "#NAME?",
+        0x24 => "#NUM!",
+        0x2a => "#N/A",
+        _ => "#ERROR!",
+    })
+}
+
+/// Iterates over the records of a workbook stream, starting at the given offset.
+struct Records<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<(u16, &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset + 4 > self.data.len() {
+            return None;
+        }
+        let header = &self.data[self.offset..self.offset + 4];
+        let id = u16::from_le_bytes([header[0], header[1]]);
+        let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let start = self.offset + 4;
+        self.offset = start + length;
+        Some(self.data.get(start..start + length).map(|data| (id, data))
+            .context("A record is truncated."))
+    }
+}
+
+/// Reads the strings of the shared string table, which may be split across several records.
+struct SharedStrings<'a> {
+    segments: Vec<&'a [u8]>,
+    segment: usize,
+    offset: usize,
+}
+
+impl SharedStrings<'_> {
+    fn available(&mut self) -> Result<&[u8]> {
+        while self.offset == self.segments[self.segment].len() {
+            ensure!(self.segment + 1 < self.segments.len(), "The shared strings are truncated.");
+            self.segment += 1;
+            self.offset = 0;
+        }
+        Ok(&self.segments[self.segment][self.offset..])
+    }
+
+    fn bytes(&mut self, mut count: usize) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        while count > 0 {
+            let available = self.available()?;
+            let taken = available.len().min(count);
+            bytes.extend_from_slice(&available[..taken]);
+            self.offset += taken;
+            count -= taken;
+        }
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let header = self.bytes(3)?;
+        let mut count = u16::from_le_bytes([header[0], header[1]]) as usize;
+        let flags = header[2];
+        let mut is_wide = flags & 1 != 0;
+        let runs = if flags & 8 != 0 {
+            let bytes = self.bytes(2)?;
+            u16::from_le_bytes([bytes[0], bytes[1]]) as usize * 4
+        } else {
+            0
+        };
+        let extension = if flags & 4 != 0 {
+            let bytes = self.bytes(4)?;
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+        } else {
+            0
+        };
+        let mut text = String::new();
+        while count > 0 {
+            if self.offset == self.segments[self.segment].len() {
+                // Characters continued in the next record are preceded by their own flags.
+                ensure!(
+                    self.segment + 1 < self.segments.len(),
+                    "The shared strings are truncated.",
+                );
+                self.segment += 1;
+                self.offset = 1;
+                is_wide = self.segments[self.segment].first().is_some_and(|flags| flags & 1 != 0);
+                continue;
+            }
+            let available = &self.segments[self.segment][self.offset..];
+            let char_size = if is_wide { 2 } else { 1 };
+            let taken = (available.len() / char_size).min(count);
+            ensure!(taken > 0, "The shared strings are corrupt.");
+            text.push_str(&decode_chars(&available[..taken * char_size], is_wide));
+            self.offset += taken * char_size;
+            count -= taken;
+        }
+        self.bytes(runs + extension)?;
+        Ok(text)
+    }
+}
+
+/// Workbook-wide data needed to read the cells of a worksheet.
+#[derive(Default)]
+struct Globals {
+    is_1904: bool,
+    custom_formats: HashMap<u16, DateFormat>,
+    /// Number format of each cell style.
+    formats: Vec<u16>,
+    /// Names of the worksheets along with the offsets of their records.
+    worksheets: Vec<(String, usize)>,
+    shared_strings: Vec<String>,
+}
+
+impl Globals {
+    fn read(data: &[u8]) -> Result<Self> {
+        let mut globals = Globals::default();
+        let mut records = Records {
+            data,
+            offset: 0,
+        }.peekable();
+        let (id, bof) = records.next().context("The workbook is empty.")??;
+        ensure!(
+            id == BOF && u16_at(bof, 0)? == BIFF8,
+            "Only spreadsheets saved by Excel 97 or later are supported.",
+        );
+        while let Some(record) = records.next() {
+            let (id, record) = record?;
+            match id {
+                EOF => break,
+                FILEPASS => bail!("The spreadsheet is password-protected."),
+                DATEMODE => globals.is_1904 = u16_at(record, 0)? == 1,
+                FORMAT => {
+                    let (code, _) = unicode_string(record, 2)?;
+                    globals.custom_formats.insert(u16_at(record, 0)?, custom_date_format(&code));
+                },
+                XF => globals.formats.push(u16_at(record, 2)?),
+                BOUNDSHEET if record.get(5) == Some(&WORKSHEET) => {
+                    let count = *record.get(6).context("A record is truncated.")? as usize;
+                    let is_wide = record.get(7).is_some_and(|flags| flags & 1 != 0);
+                    let length = if is_wide { count * 2 } else { count };
+                    let name = record.get(8..8 + length).context("A record is truncated.")?;
+                    let offset = u32_at(record, 0)? as usize;
+                    globals.worksheets.push((decode_chars(name, is_wide), offset));
+                },
+                SST => {
+                    let mut segments = vec![record.get(8..).context("A record is truncated.")?];
+                    while let Some(Ok((CONTINUE, continued))) = records.peek() {
+                        segments.push(continued);
+                        records.next();
+                    }
+                    let count = u32_at(record, 4)? as usize;
+                    let mut strings = SharedStrings {
+                        segments,
+                        segment: 0,
+                        offset: 0,
+                    };
+                    for _ in 0..count {
+                        globals.shared_strings.push(strings.string()?);
+                    }
+                },
+                _ => {},
+            }
+        }
+        Ok(globals)
+    }
+
+    fn date_format(&self, style: u16) -> DateFormat {
+        self.formats.get(style as usize).map_or(DateFormat::default(), |&format| {
+            self.custom_formats.get(&format).copied()
+                .unwrap_or(builtin_date_format(u32::from(format)))
+        })
+    }
+
+    fn render_number(&self, value: f64, style: u16) -> String {
+        let format = self.date_format(style);
+        if format.is_date() {
+            render_date(value, format, self.is_1904)
+        } else {
+            render_number(value)
+        }
+    }
+}
+
+fn read_worksheet(data: &[u8], offset: usize, globals: &Globals) -> Result<Vec<Vec<String>>> {
+    let mut grid = Grid::default();
+    let mut records = Records {
+        data,
+        offset,
+    };
+    let (id, _) = records.next().context("The worksheet is missing.")??;
+    ensure!(id == BOF, "The worksheet is corrupt.");
+    // Position of a formula whose string result is stored in the next STRING record.
+    let mut string_formula = None;
+    for record in records {
+        let (id, record) = record?;
+        if id == EOF {
+            break;
+        }
+        let position = || -> Result<(usize, usize)> {
+            Ok((u16_at(record, 0)? as usize, u16_at(record, 2)? as usize))
+        };
+        match id {
+            LABELSST => {
+                let (row, column) = position()?;
+                let index = u32_at(record, 6)? as usize;
+                let value = globals.shared_strings.get(index).cloned().unwrap_or_default();
+                grid.set(row, column, value);
+            },
+            LABEL => {
+                let (row, column) = position()?;
+                grid.set(row, column, unicode_string(record, 6)?.0);
+            },
+            NUMBER => {
+                let (row, column) = position()?;
+                let value = f64_at(record, 6)?;
+                grid.set(row, column, globals.render_number(value, u16_at(record, 4)?));
+            },
+            RK => {
+                let (row, column) = position()?;
+                let value = rk_number(u32_at(record, 6)?);
+                grid.set(row, column, globals.render_number(value, u16_at(record, 4)?));
+            },
+            MULRK => {
+                let (row, first_column) = position()?;
+                let count = record.len().saturating_sub(6) / 6;
+                for index in 0..count {
+                    let offset = 4 + index * 6;
+                    let value = rk_number(u32_at(record, offset + 2)?);
+                    grid.set(
+                        row,
+                        first_column + index,
+                        globals.render_number(value, u16_at(record, offset)?),
+                    );
+                }
+            },
+            FORMULA => {
+                let (row, column) = position()?;
+                let result = record.get(6..14).context("A record is truncated.")?;
+                if result[6..8] == [0xff, 0xff] {
+                    match result[0] {
+                        0 => string_formula = Some((row, column)),
+                        1 => grid.set(row, column, render_boolean(result[2] != 0)),
+                        2 => grid.set(row, column, error_text(result[2])),
+                        _ => {},
+                    }
+                } else {
+                    let value = f64_at(record, 6)?;
+                    grid.set(row, column, globals.render_number(value, u16_at(record, 4)?));
+                }
+            },
+            STRING => if let Some((row, column)) = string_formula.take() {
+                grid.set(row, column, unicode_string(record, 0)?.0);
+            },
+            BOOLERR => {
+                let (row, column) = position()?;
+                let value = *record.get(6).context("A record is truncated.")?;
+                let value = if record.get(7) == Some(&1) {
+                    error_text(value)
+                } else {
+                    render_boolean(value != 0)
+                };
+                grid.set(row, column, value);
+            },
+            _ => {},
+        }
+    }
+    Ok(grid.into_records())
+}
+
+pub fn read(file: &CompoundFile, worksheet: &Worksheet) -> Result<Vec<Vec<String>>> {
+    let data = file.stream(&WORKBOOK_STREAMS)?;
+    let globals = Globals::read(&data)?;
+    let names: Vec<_> = globals.worksheets.iter().map(|(name, _)| name).collect();
+    let index = find_worksheet(&names, worksheet)?;
+    read_worksheet(&data, globals.worksheets[index].1, &globals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cfb::tests::compound_file;
+    use super::*;
+
+    fn record(id: u16, data: &[u8]) -> Vec<u8> {
+        [&id.to_le_bytes()[..], &(data.len() as u16).to_le_bytes(), data].concat()
+    }
+
+    fn cell(id: u16, row: u16, column: u16, style: u16, value: &[u8]) -> Vec<u8> {
+        let position = [row.to_le_bytes(), column.to_le_bytes(), style.to_le_bytes()].concat();
+        record(id, &[&position[..], value].concat())
+    }
+
+    /// Encodes a string with a 16-bit character count, as single bytes.
+    fn string(text: &str) -> Vec<u8> {
+        [&(text.len() as u16).to_le_bytes()[..], &[0], text.as_bytes()].concat()
+    }
+
+    fn bof(kind: u16) -> Vec<u8> {
+        record(BOF, &[&BIFF8.to_le_bytes()[..], &kind.to_le_bytes(), &[0; 12]].concat())
+    }
+
+    fn xf(format: u16) -> Vec<u8> {
+        record(XF, &[&[0, 0][..], &format.to_le_bytes(), &[0; 16]].concat())
+    }
+
+    /// Assembles a workbook stream from the records of its globals (starting with BOF, and
+    /// without the worksheets and EOF) and the records of its worksheets.
+    fn workbook(globals: &[u8], worksheets: &[(&str, &[u8])]) -> Vec<u8> {
+        let boundsheet = |offset: usize, name: &str| {
+            let header = [&(offset as u32).to_le_bytes()[..], &[0, WORKSHEET, name.len() as u8, 0]];
+            record(BOUNDSHEET, &[&header.concat()[..], name.as_bytes()].concat())
+        };
+        let mut offset = globals.len() + record(EOF, &[]).len() + worksheets.iter().map(
+            |(name, _)| boundsheet(0, name).len()
+        ).sum::<usize>();
+        let mut data = globals.to_vec();
+        for (name, records) in worksheets {
+            data.extend(boundsheet(offset, name));
+            offset += records.len();
+        }
+        data.extend(record(EOF, &[]));
+        for (_, records) in worksheets {
+            data.extend_from_slice(records);
+        }
+        data
+    }
+
+    fn read_workbook(stream: &[u8], worksheet: &Worksheet) -> Result<Vec<Vec<String>>> {
+        read(&CompoundFile::new(&compound_file(&[("Workbook", stream)]))?, worksheet)
+    }
+
+    fn strings(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter().map(|row| row.iter().map(|value| String::from(*value)).collect()).collect()
+    }
+
+    #[test]
+    fn cells_are_read() {
+        // The last shared string starts with single-byte characters and is continued in
+        // a record storing its other characters as UTF-16.
+        let zurich = [6, 0, 0, b'Z', 0xfc];
+        let sst = [&[0; 4][..], &3u32.to_le_bytes(), &string("id"), &string("name"), &zurich]
+            .concat();
+        let continued: Vec<u8> = [1].into_iter().chain(
+            "rich".encode_utf16().flat_map(u16::to_le_bytes)
+        ).collect();
+        let globals = [
+            bof(0x0005),
+            record(DATEMODE, &[0, 0]),
+            record(FORMAT, &[&164u16.to_le_bytes()[..], &string("yyyy-mm-dd")].concat()),
+            xf(0),
+            xf(14),
+            xf(164),
+            xf(20),
+            record(SST, &sst),
+            record(CONTINUE, &continued),
+        ].concat();
+        let rk_float = (0.5f64.to_bits() >> 32) as u32;
+        let string_result = [0, 0, 0, 0, 0, 0, 0xff, 0xff];
+        let boolean_result = [1, 0, 1, 0, 0, 0, 0xff, 0xff];
+        let data = [
+            bof(0x0010),
+            cell(LABELSST, 0, 0, 0, &0u32.to_le_bytes()),
+            cell(LABELSST, 0, 1, 0, &1u32.to_le_bytes()),
+            cell(LABELSST, 0, 3, 0, &2u32.to_le_bytes()),
+            cell(NUMBER, 1, 0, 0, &1.5f64.to_le_bytes()),
+            cell(LABEL, 1, 1, 0, &string("plain")),
+            cell(RK, 1, 2, 0, &(42 << 2 | 2u32).to_le_bytes()),
+            cell(RK, 1, 3, 0, &(1234 << 2 | 3u32).to_le_bytes()),
+            record(MULRK, &[
+                &2u16.to_le_bytes()[..],
+                &1u16.to_le_bytes(),
+                &0u16.to_le_bytes(),
+                &(7 << 2 | 2u32).to_le_bytes(),
+                &0u16.to_le_bytes(),
+                &rk_float.to_le_bytes(),
+                &2u16.to_le_bytes(),
+            ].concat()),
+            cell(FORMULA, 3, 0, 0, &[&string_result[..], &[0; 6]].concat()),
+            record(STRING, &string("from a formula")),
+            cell(FORMULA, 3, 1, 0, &[&boolean_result[..], &[0; 6]].concat()),
+            cell(FORMULA, 3, 2, 0, &[&2.25f64.to_le_bytes()[..], &[0; 6]].concat()),
+            cell(BOOLERR, 3, 3, 0, &[0x07, 1]),
+            cell(BOOLERR, 4, 0, 0, &[0, 0]),
+            cell(NUMBER, 4, 1, 1, &45_000.5f64.to_le_bytes()),
+            cell(RK, 4, 2, 2, &(45_000 << 2 | 2u32).to_le_bytes()),
+            cell(NUMBER, 4, 3, 3, &0.75f64.to_le_bytes()),
+            record(EOF, &[]),
+        ].concat();
+        let empty = [bof(0x0010), record(EOF, &[])].concat();
+        let stream = workbook(&globals, &[("Data", &data), ("Empty", &empty)]);
+        assert_eq!(read_workbook(&stream, &Worksheet::Index(1)).unwrap(), strings(&[
+            &["id", "name", "", "Zürich"],
+            &["1.5", "plain", "42", "12.34"],
+            &["", "7", "0.5", ""],
+            &["from a formula", "TRUE", "2.25", "#DIV/0!"],
+            &["FALSE", "2023-03-15", "2023-03-15", "18:00:00"],
+        ]));
+        let empty = read_workbook(&stream, &Worksheet::Name(String::from("Empty"))).unwrap();
+        assert_eq!(empty, Vec::<Vec<String>>::new());
+        assert_eq!(
+            read_workbook(&stream, &Worksheet::Index(3)).err().unwrap().to_string(),
+            "There is no worksheet #3 (total worksheets: 2).",
+        );
+    }
+
+    #[test]
+    fn dates_of_the_1904_system_are_read() {
+        let globals = [bof(0x0005), record(DATEMODE, &[1, 0]), xf(22)].concat();
+        let data = [bof(0x0010), cell(NUMBER, 0, 0, 0, &1.25f64.to_le_bytes()), record(EOF, &[])]
+            .concat();
+        let stream = workbook(&globals, &[("Dates", &data)]);
+        let records = read_workbook(&stream, &Worksheet::Index(1)).unwrap();
+        assert_eq!(records, strings(&[&["1904-01-02 06:00:00"]]));
+    }
+
+    #[test]
+    fn unsupported_workbooks_are_rejected() {
+        let protected = [bof(0x0005), record(FILEPASS, &[0; 6])].concat();
+        let protected = workbook(&protected, &[]);
+        assert_eq!(
+            read_workbook(&protected, &Worksheet::Index(1)).err().unwrap().to_string(),
+            "The spreadsheet is password-protected.",
+        );
+        let mut old = workbook(&bof(0x0005), &[]);
+        old[4..6].copy_from_slice(&0x0500u16.to_le_bytes());
+        assert!(read_workbook(&old, &Worksheet::Index(1)).is_err());
+        let truncated = workbook(&bof(0x0005), &[("Data", &bof(0x0010))]);
+        assert!(read_workbook(&truncated[..truncated.len() - 1], &Worksheet::Index(1)).is_err());
+    }
+}
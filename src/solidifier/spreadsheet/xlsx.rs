@@ -0,0 +1,626 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::params::Worksheet;
+use crate::solidifier::delimited::RecordWrite;
+
+use super::xml::{document, Event, Reader};
+use super::zip::{Archive, ArchiveWriter};
+use super::{
+    builtin_date_format,
+    custom_date_format,
+    find_worksheet,
+    render_boolean,
+    render_date,
+    render_iso_date,
+    render_number,
+    DateFormat,
+    Grid,
+};
+
+const DEFAULT_WORKBOOK: &str = "xl/workbook.xml";
+const MAX_ROWS: usize = 1_048_576;
+const MAX_COLUMNS: usize = 16_384;
+const MAX_CELL_LENGTH: usize = 32_767;
+/// Style of the cells that are highlighted, as defined in `STYLES`.
+const HIGHLIGHTED_STYLE: &str = "1";
+
+const CONTENT_TYPES: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">"#,
+    r#"<Default Extension="rels" "#,
+    r#"ContentType="application/vnd.openxmlformats-package.relationships+xml"/>"#,
+    r#"<Default Extension="xml" ContentType="application/xml"/>"#,
+    r#"<Override PartName="/xl/workbook.xml" "#,
+    r#"ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>"#,
+    r#"<Override PartName="/xl/worksheets/sheet1.xml" "#,
+    r#"ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#,
+    r#"<Override PartName="/xl/styles.xml" "#,
+    r#"ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/>"#,
+    r#"</Types>"#,
+);
+
+const PACKAGE_RELATIONSHIPS: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+    r#"<Relationship Id="rId1" "#,
+    r#"Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" "#,
+    r#"Target="xl/workbook.xml"/>"#,
+    r#"</Relationships>"#,
+);
+
+const WORKBOOK: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" "#,
+    r#"xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">"#,
+    r#"<sheets><sheet name="Sheet1" sheetId="1" r:id="rId1"/></sheets>"#,
+    r#"</workbook>"#,
+);
+
+const WORKBOOK_RELATIONSHIPS: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">"#,
+    r#"<Relationship Id="rId1" "#,
+    r#"Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" "#,
+    r#"Target="worksheets/sheet1.xml"/>"#,
+    r#"<Relationship Id="rId2" "#,
+    r#"Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" "#,
+    r#"Target="styles.xml"/>"#,
+    r#"</Relationships>"#,
+);
+
+/// The default style, and a style with a light yellow background.
+const STYLES: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+    r#"<fonts count="1"><font><sz val="11"/><name val="Calibri"/></font></fonts>"#,
+    r#"<fills count="3">"#,
+    r#"<fill><patternFill patternType="none"/></fill>"#,
+    r#"<fill><patternFill patternType="gray125"/></fill>"#,
+    r#"<fill><patternFill patternType="solid"><fgColor rgb="FFFFEB9C"/></patternFill></fill>"#,
+    r#"</fills>"#,
+    r#"<borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders>"#,
+    r#"<cellStyleXfs count="1">"#,
+    r#"<xf numFmtId="0" fontId="0" fillId="0" borderId="0"/>"#,
+    r#"</cellStyleXfs>"#,
+    r#"<cellXfs count="2">"#,
+    r#"<xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/>"#,
+    r#"<xf numFmtId="0" fontId="0" fillId="2" borderId="0" xfId="0" applyFill="1"/>"#,
+    r#"</cellXfs>"#,
+    r#"<cellStyles count="1"><cellStyle name="Normal" xfId="0" builtinId="0"/></cellStyles>"#,
+    r#"</styleSheet>"#,
+);
+
+const WORKSHEET_START: &str = concat!(
+    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#,
+    r#"<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+    r#"<sheetData>"#,
+);
+
+const WORKSHEET_END: &str = "</sheetData></worksheet>";
+
+/// Resolves the target of a relationship of the given part of the package.
+fn resolve(part: &str, target: &str) -> String {
+    match target.strip_prefix('/') {
+        Some(absolute) => String::from(absolute),
+        None => {
+            let directory = part.rsplit_once('/').map_or("", |(directory, _)| directory);
+            let mut segments: Vec<_> = directory.split('/').filter(|s| !s.is_empty()).collect();
+            for segment in target.split('/') {
+                match segment {
+                    ".." => {
+                        segments.pop();
+                    },
+                    "." => {},
+                    _ => segments.push(segment),
+                }
+            }
+            segments.join("/")
+        },
+    }
+}
+
+/// Returns the path of the part holding the relationships of the given part.
+fn relationships_path(part: &str) -> String {
+    match part.rsplit_once('/') {
+        Some((directory, name)) => format!("{directory}/_rels/{name}.rels"),
+        None => format!("_rels/{part}.rels"),
+    }
+}
+
+/// Reads the relationships of a part as (id, type, resolved target) triples.
+fn relationships(archive: &Archive, part: &str) -> Result<Vec<(String, String, String)>> {
+    let path = relationships_path(part);
+    if !archive.contains(&path) {
+        return Ok(vec![]);
+    }
+    let data = archive.file(&path)?;
+    let mut result = vec![];
+    for event in Reader::new(document(&data)?) {
+        let event = event?;
+        if let Event::Start { name: "Relationship", .. } = event {
+            let target = event.attribute("Target").unwrap_or_default();
+            result.push((
+                String::from(event.attribute("Id").unwrap_or_default()),
+                String::from(event.attribute("Type").unwrap_or_default()),
+                resolve(part, target),
+            ));
+        }
+    }
+    Ok(result)
+}
+
+fn workbook_path(archive: &Archive) -> Result<String> {
+    // The relationships of the package itself are those of an unnamed part.
+    Ok(relationships(archive, "")?.into_iter().find(
+        |(_, kind, _)| kind.ends_with("/officeDocument")
+    ).map_or(String::from(DEFAULT_WORKBOOK), |(_, _, target)| target))
+}
+
+/// Worksheets of a workbook as (name, relationship id) pairs, along with whether
+/// the workbook uses the 1904 date system.
+fn read_workbook(data: &[u8]) -> Result<(Vec<(String, String)>, bool)> {
+    let mut sheets = vec![];
+    let mut is_1904 = false;
+    for event in Reader::new(document(data)?) {
+        let event = event?;
+        match event {
+            Event::Start { name: "sheet", .. } => sheets.push((
+                String::from(event.attribute("name").unwrap_or_default()),
+                String::from(event.attribute("id").unwrap_or_default()),
+            )),
+            Event::Start { name: "workbookPr", .. } => {
+                is_1904 = matches!(event.attribute("date1904"), Some("1" | "true"));
+            },
+            _ => {},
+        }
+    }
+    Ok((sheets, is_1904))
+}
+
+/// Collects the text of a string item (`si` or `is`), skipping phonetic runs.
+struct StringItem {
+    text: String,
+    /// Whether the text of the current element is part of the string.
+    in_text: bool,
+    phonetic_depth: usize,
+}
+
+impl StringItem {
+    fn new() -> Self {
+        Self {
+            text: String::new(),
+            in_text: false,
+            phonetic_depth: 0,
+        }
+    }
+
+    fn handle(&mut self, event: &Event) {
+        match event {
+            Event::Start { name: "rPh", .. } => self.phonetic_depth += 1,
+            Event::End("rPh") => self.phonetic_depth = self.phonetic_depth.saturating_sub(1),
+            Event::Start { name: "t", .. } => self.in_text = self.phonetic_depth == 0,
+            Event::End("t") => self.in_text = false,
+            Event::Text(text) if self.in_text => self.text.push_str(text),
+            _ => {},
+        }
+    }
+
+    /// Returns the text, with the characters escaped as `_xHHHH_` unescaped.
+    fn into_text(self) -> String {
+        if !self.text.contains("_x") {
+            return self.text;
+        }
+        let mut text = String::with_capacity(self.text.len());
+        let mut rest = self.text.as_str();
+        while let Some(start) = rest.find("_x") {
+            text.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let char = is_escape_sequence(rest).then(|| u32::from_str_radix(&rest[2..6], 16).ok())
+                .flatten()
+                .and_then(char::from_u32);
+            match char {
+                Some(char) => {
+                    text.push(char);
+                    rest = &rest[7..];
+                },
+                None => {
+                    text.push('_');
+                    rest = &rest[1..];
+                },
+            }
+        }
+        text.push_str(rest);
+        text
+    }
+}
+
+fn read_shared_strings(data: &[u8]) -> Result<Vec<String>> {
+    let mut strings = vec![];
+    let mut item: Option<StringItem> = None;
+    for event in Reader::new(document(data)?) {
+        let event = event?;
+        match event {
+            Event::Start { name: "si", .. } => item = Some(StringItem::new()),
+            Event::End("si") => {
+                strings.push(item.take().map(StringItem::into_text).unwrap_or_default());
+            },
+            _ => if let Some(item) = &mut item {
+                item.handle(&event);
+            },
+        }
+    }
+    Ok(strings)
+}
+
+/// Reads the date format of each cell style.
+fn read_styles(data: &[u8]) -> Result<Vec<DateFormat>> {
+    let mut custom_formats = HashMap::new();
+    let mut styles = vec![];
+    let mut in_cell_styles = false;
+    for event in Reader::new(document(data)?) {
+        let event = event?;
+        match event {
+            Event::Start { name: "numFmt", .. } => {
+                let id = event.attribute("numFmtId").and_then(|id| id.parse::<u32>().ok());
+                if let Some(id) = id {
+                    let code = event.attribute("formatCode").unwrap_or_default();
+                    custom_formats.insert(id, custom_date_format(code));
+                }
+            },
+            Event::Start { name: "cellXfs", .. } => in_cell_styles = true,
+            Event::End("cellXfs") => in_cell_styles = false,
+            Event::Start { name: "xf", .. } if in_cell_styles => {
+                let id = event.attribute("numFmtId").and_then(|id| id.parse().ok()).unwrap_or(0);
+                styles.push(custom_formats.get(&id).copied().unwrap_or(builtin_date_format(id)));
+            },
+            _ => {},
+        }
+    }
+    Ok(styles)
+}
+
+/// Parses a cell reference such as `B3` into 0-based (row, column) indices.
+fn parse_reference(reference: &str) -> Option<(usize, usize)> {
+    let split = reference.find(|char: char| char.is_ascii_digit())?;
+    let (letters, digits) = reference.split_at(split);
+    let mut column = 0;
+    for letter in letters.chars() {
+        if !letter.is_ascii_uppercase() {
+            return None;
+        }
+        column = column * 26 + (letter as usize - 'A' as usize + 1);
+    }
+    Some((digits.parse::<usize>().ok()?.checked_sub(1)?, column.checked_sub(1)?))
+}
+
+/// A cell of a worksheet being read.
+struct Cell {
+    row: usize,
+    column: usize,
+    kind: String,
+    style: usize,
+    value: String,
+    inline: Option<StringItem>,
+    in_value: bool,
+}
+
+/// Parts of a workbook shared by its worksheets.
+struct Workbook<'a> {
+    shared_strings: &'a [String],
+    styles: &'a [DateFormat],
+    is_1904: bool,
+}
+
+impl Cell {
+    fn render(self, workbook: &Workbook) -> String {
+        let value = self.value;
+        match self.kind.as_str() {
+            "s" => value.trim().parse::<usize>().ok()
+                .and_then(|index| workbook.shared_strings.get(index).cloned())
+                .unwrap_or_default(),
+            "inlineStr" => self.inline.map(StringItem::into_text).unwrap_or_default(),
+            "b" => render_boolean(value.trim() == "1"),
+            "d" => render_iso_date(value.trim()),
+            "str" | "e" => value,
+            _ => match value.trim().parse::<f64>() {
+                Ok(number) => match workbook.styles.get(self.style) {
+                    Some(format) if format.is_date() => {
+                        render_date(number, *format, workbook.is_1904)
+                    },
+                    _ => render_number(number),
+                },
+                Err(_) => value,
+            },
+        }
+    }
+}
+
+fn read_worksheet(data: &[u8], workbook: &Workbook) -> Result<Vec<Vec<String>>> {
+    let mut grid = Grid::default();
+    let mut row = 0;
+    let mut next_column = 0;
+    let mut is_first_row = true;
+    let mut cell: Option<Cell> = None;
+    for event in Reader::new(document(data)?) {
+        let event = event?;
+        match event {
+            Event::Start { name: "row", .. } => {
+                row = match event.attribute("r").and_then(|r| r.parse::<usize>().ok()) {
+                    Some(number) => number.saturating_sub(1),
+                    None if is_first_row => 0,
+                    None => row + 1,
+                };
+                is_first_row = false;
+                next_column = 0;
+            },
+            Event::Start { name: "c", .. } => {
+                let (cell_row, column) = event.attribute("r").and_then(parse_reference)
+                    .unwrap_or((row, next_column));
+                next_column = column + 1;
+                cell = Some(Cell {
+                    row: cell_row,
+                    column,
+                    kind: String::from(event.attribute("t").unwrap_or("n")),
+                    style: event.attribute("s").and_then(|s| s.parse().ok()).unwrap_or(0),
+                    value: String::new(),
+                    inline: None,
+                    in_value: false,
+                });
+            },
+            Event::End("c") => if let Some(cell) = cell.take() {
+                let (row, column) = (cell.row, cell.column);
+                grid.set(row, column, cell.render(workbook));
+            },
+            _ => if let Some(cell) = &mut cell {
+                match &event {
+                    Event::Start { name: "v", .. } => cell.in_value = true,
+                    Event::End("v") => cell.in_value = false,
+                    Event::Start { name: "is", .. } => cell.inline = Some(StringItem::new()),
+                    Event::Text(text) if cell.in_value => cell.value.push_str(text),
+                    _ => if let Some(item) = &mut cell.inline {
+                        item.handle(&event);
+                    },
+                }
+            },
+        }
+    }
+    Ok(grid.into_records())
+}
+
+pub fn read(archive: &Archive, worksheet: &Worksheet) -> Result<Vec<Vec<String>>> {
+    let workbook = workbook_path(archive)?;
+    let (sheets, is_1904) = read_workbook(&archive.file(&workbook)?)?;
+    let names: Vec<_> = sheets.iter().map(|(name, _)| name).collect();
+    let index = find_worksheet(&names, worksheet)?;
+    let relationships = relationships(archive, &workbook)?;
+    let find_part = |suffix: &str| relationships.iter().find(
+        |(_, kind, _)| kind.ends_with(suffix)
+    ).map(|(_, _, target)| target.clone());
+    let shared_strings = match find_part("/sharedStrings") {
+        Some(path) => read_shared_strings(&archive.file(&path)?)?,
+        None => vec![],
+    };
+    let styles = match find_part("/styles") {
+        Some(path) => read_styles(&archive.file(&path)?)?,
+        None => vec![],
+    };
+    let (name, id) = &sheets[index];
+    let path = relationships.iter().find(|(candidate, _, _)| candidate == id)
+        .map(|(_, _, target)| target)
+        .with_context(|| format!("The workbook has no data for worksheet '{name}'."))?;
+    read_worksheet(&archive.file(path)?, &Workbook {
+        shared_strings: &shared_strings,
+        styles: &styles,
+        is_1904,
+    })
+}
+
+/// Returns the letters of a column, given its 0-based index.
+fn column_letters(column: usize) -> String {
+    let mut letters = vec![];
+    let mut number = column + 1;
+    while number > 0 {
+        letters.push(b'A' + ((number - 1) % 26) as u8);
+        number = (number - 1) / 26;
+    }
+    letters.iter().rev().map(|letter| char::from(*letter)).collect()
+}
+
+/// Escapes text for an element of a worksheet. Characters that XML cannot represent
+/// (and underscores that would be mistaken for them) are escaped as `_xHHHH_`.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for (index, char) in text.char_indices() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\t' | '\n' => escaped.push(char),
+            '_' if is_escape_sequence(&text[index..]) => escaped.push_str("_x005F_"),
+            _ if char < ' ' => {
+                escaped.push_str(&format!("_x{:04X}_", char as u32));
+            },
+            _ => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+fn is_escape_sequence(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    bytes.len() >= 7
+        && bytes.starts_with(b"_x")
+        && bytes[2..6].iter().all(u8::is_ascii_hexdigit)
+        && bytes[6] == b'_'
+}
+
+/// Writes records as the only worksheet of an xlsx workbook, which is assembled once
+/// the writer is flushed. Values that are numbers written the way numbers are rendered
+/// when reading spreadsheets are stored as numbers, and other values as text.
+pub struct Writer<W: Write> {
+    archive: ArchiveWriter<W>,
+    row_count: usize,
+    highlight_missing: bool,
+    is_started: bool,
+    is_finished: bool,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(sink: W, highlight_missing: bool) -> Self {
+        Self {
+            archive: ArchiveWriter::new(sink),
+            row_count: 0,
+            highlight_missing,
+            is_started: false,
+            is_finished: false,
+        }
+    }
+
+    /// Writes the parts of the workbook other than the worksheet, then starts the worksheet,
+    /// whose rows are streamed into the archive as they are written.
+    fn start(&mut self) -> Result<()> {
+        if !self.is_started {
+            self.is_started = true;
+            for (name, contents) in [
+                ("[Content_Types].xml", CONTENT_TYPES),
+                ("_rels/.rels", PACKAGE_RELATIONSHIPS),
+                ("xl/workbook.xml", WORKBOOK),
+                ("xl/_rels/workbook.xml.rels", WORKBOOK_RELATIONSHIPS),
+                ("xl/styles.xml", STYLES),
+                ("xl/worksheets/sheet1.xml", WORKSHEET_START),
+            ] {
+                self.archive.start_entry(name)?;
+                self.archive.write_all(contents.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a row of (value, whether to highlight it) cells.
+    fn write_cells<'a>(
+        &mut self,
+        cells: impl ExactSizeIterator<Item = (&'a str, bool)>,
+    ) -> Result<()> {
+        ensure!(!self.is_finished, "The workbook is already complete.");
+        self.start()?;
+        ensure!(
+            self.row_count < MAX_ROWS,
+            "The output has more rows than an xlsx worksheet can hold ({MAX_ROWS}).",
+        );
+        ensure!(
+            cells.len() <= MAX_COLUMNS,
+            "The output has more columns than an xlsx worksheet can hold ({MAX_COLUMNS}).",
+        );
+        self.row_count += 1;
+        let row = self.row_count;
+        let mut xml = format!(r#"<row r="{row}">"#);
+        for (column, (value, is_highlighted)) in cells.enumerate() {
+            if value.is_empty() && !is_highlighted {
+                continue;
+            }
+            ensure!(
+                value.chars().count() <= MAX_CELL_LENGTH,
+                "A value is longer than an xlsx cell can hold ({MAX_CELL_LENGTH} characters).",
+            );
+            let reference = format!("{}{row}", column_letters(column));
+            let style = if is_highlighted {
+                format!(r#" s="{HIGHLIGHTED_STYLE}""#)
+            } else {
+                String::new()
+            };
+            if value.is_empty() {
+                xml.push_str(&format!(r#"<c r="{reference}"{style}/>"#));
+            } else if is_number(value) {
+                xml.push_str(&format!(r#"<c r="{reference}"{style}><v>{value}</v></c>"#));
+            } else {
+                xml.push_str(&format!(
+                    r#"<c r="{reference}"{style} t="inlineStr"><is>"#,
+                ));
+                xml.push_str(&format!(r#"<t xml:space="preserve">{}</t></is></c>"#, escape(value)));
+            }
+        }
+        xml.push_str("</row>");
+        self.archive.write_all(xml.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Checks whether a value is a number written the way numbers are rendered, so that
+/// storing it as a number loses nothing (such as leading zeros).
+fn is_number(value: &str) -> bool {
+    value.parse::<f64>().is_ok_and(|number| number.is_finite() && render_number(number) == value)
+}
+
+impl<W: Write> RecordWrite for Writer<W> {
+    fn write_record(&mut self, record: &[&str]) -> Result<()> {
+        self.write_cells(record.iter().map(|value| (*value, false)))
+    }
+
+    fn write_row(&mut self, row: &[Option<&str>], filler: &str) -> Result<()> {
+        let highlight_missing = self.highlight_missing;
+        self.write_cells(row.iter().map(
+            |value| (value.unwrap_or(filler), highlight_missing && value.is_none())
+        ))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.is_finished {
+            self.start()?;
+            self.is_finished = true;
+            self.archive.write_all(WORKSHEET_END.as_bytes())?;
+            self.archive.finish()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter().map(|row| row.iter().map(|value| String::from(*value)).collect()).collect()
+    }
+
+    #[test]
+    fn written_workbook_is_read_back() {
+        let mut data = vec![];
+        let mut writer = Writer::new(&mut data, true);
+        writer.write_record(&["id", "name", "amount"]).unwrap();
+        writer.write_row(&[Some("1"), Some("<A & B>"), Some("-0.5")], "").unwrap();
+        writer.write_row(&[Some("007"), None, Some(" x\ty ")], "").unwrap();
+        writer.write_record(&["2", "", "1e3"]).unwrap();
+        RecordWrite::flush(&mut writer).unwrap();
+        drop(writer);
+        let archive = Archive::new(&data).unwrap();
+        assert_eq!(read(&archive, &Worksheet::Index(1)).unwrap(), strings(&[
+            &["id", "name", "amount"],
+            &["1", "<A & B>", "-0.5"],
+            &["007", "", " x\ty "],
+            &["2", "", "1e3"],
+        ]));
+        let worksheet = archive.file("xl/worksheets/sheet1.xml").unwrap();
+        let worksheet = String::from_utf8(worksheet).unwrap();
+        assert!(worksheet.contains(r#"<c r="B3" s="1"/>"#), "{worksheet}");
+        assert!(worksheet.contains(r#"<c r="C2"><v>-0.5</v></c>"#), "{worksheet}");
+    }
+
+    #[test]
+    fn empty_workbook_is_complete() {
+        let mut data = vec![];
+        RecordWrite::flush(&mut Writer::new(&mut data, false)).unwrap();
+        let archive = Archive::new(&data).unwrap();
+        assert_eq!(read(&archive, &Worksheet::Index(1)).unwrap(), Vec::<Vec<String>>::new());
+    }
+
+    #[test]
+    fn oversized_values_are_rejected() {
+        let mut writer = Writer::new(vec![], false);
+        let value = "x".repeat(MAX_CELL_LENGTH + 1);
+        assert!(writer.write_record(&[&value]).is_err());
+        assert!(writer.write_record(&vec![""; MAX_COLUMNS + 1]).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use std::borrow::Cow;
+
+use anyhow::{bail, Context, Result};
+
+/// A piece of an XML document; element and attribute names are stripped of their namespace
+/// prefixes.
+pub enum Event<'a> {
+    Start {
+        name: &'a str,
+        attributes: Vec<(&'a str, Cow<'a, str>)>,
+    },
+    End(&'a str),
+    Text(Cow<'a, str>),
+}
+
+impl Event<'_> {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        match self {
+            Event::Start { attributes, .. } => attributes.iter().find(
+                |(attribute, _)| *attribute == name
+            ).map(|(_, value)| value.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// A minimal non-validating XML reader, sufficient for the documents inside spreadsheet files.
+pub struct Reader<'a> {
+    text: &'a str,
+    position: usize,
+    /// End of an empty element, reported right after its start.
+    pending_end: Option<&'a str>,
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, local)| local)
+}
+
+fn unescape(text: &str) -> Result<Cow<'_, str>> {
+    if !text.contains('&') {
+        return Ok(Cow::Borrowed(text));
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        let end = rest[start..].find(';').context("Unterminated XML entity.")? + start;
+        let entity = &rest[start + 1..end];
+        let char = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else {
+                    entity.strip_prefix('#').and_then(|decimal| decimal.parse().ok())
+                };
+                code.and_then(char::from_u32)
+                    .with_context(|| format!("Unknown XML entity &{entity};."))?
+            },
+        };
+        result.push(char);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(Cow::Owned(result))
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            position: 0,
+            pending_end: None,
+        }
+    }
+
+    fn skip_past(&mut self, terminator: &str) -> Result<&'a str> {
+        let rest = &self.text[self.position..];
+        let end = rest.find(terminator).context("Unexpected end of an XML document.")?;
+        self.position += end + terminator.len();
+        Ok(&rest[..end])
+    }
+
+    /// Reads the rest of a tag, whose attribute values may contain `>`.
+    fn read_tag(&mut self) -> Result<Event<'a>> {
+        let rest = &self.text[self.position..];
+        let mut quote = None;
+        let end = rest.find(|char| match quote {
+            Some(open) => {
+                if char == open {
+                    quote = None;
+                }
+                false
+            },
+            None if char == '"' || char == '\'' => {
+                quote = Some(char);
+                false
+            },
+            None => char == '>',
+        }).context("Unexpected end of an XML document.")?;
+        self.position += end + 1;
+        let tag = &rest[..end];
+        if let Some(name) = tag.strip_prefix('/') {
+            return Ok(Event::End(local_name(name.trim())));
+        }
+        let (tag, is_empty) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let name = local_name(&tag[..name_end]);
+        let mut attributes = vec![];
+        let mut rest = tag[name_end..].trim_start();
+        while !rest.is_empty() {
+            let (attribute, value) = rest.split_once('=').context("Malformed XML attribute.")?;
+            let value = value.trim_start();
+            let quote = value.chars().next().filter(|char| *char == '"' || *char == '\'')
+                .context("Malformed XML attribute.")?;
+            let end = value[1..].find(quote).context("Malformed XML attribute.")? + 1;
+            attributes.push((local_name(attribute.trim()), unescape(&value[1..end])?));
+            rest = value[end + 1..].trim_start();
+        }
+        if is_empty {
+            self.pending_end = Some(name);
+        }
+        Ok(Event::Start {
+            name,
+            attributes,
+        })
+    }
+
+    fn read_event(&mut self) -> Result<Option<Event<'a>>> {
+        loop {
+            if let Some(name) = self.pending_end.take() {
+                return Ok(Some(Event::End(name)));
+            }
+            let rest = &self.text[self.position..];
+            if rest.is_empty() {
+                return Ok(None);
+            }
+            if !rest.starts_with('<') {
+                let end = rest.find('<').unwrap_or(rest.len());
+                self.position += end;
+                return Ok(Some(Event::Text(unescape(&rest[..end])?)));
+            }
+            if rest.starts_with("<![CDATA[") {
+                self.position += "<![CDATA[".len();
+                return Ok(Some(Event::Text(Cow::Borrowed(self.skip_past("]]>")?))));
+            }
+            if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                self.position += 1;
+                return self.read_tag().map(Some);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<Event<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_event().transpose()
+    }
+}
+
+/// Returns the text of an XML document from a spreadsheet file.
+pub fn document(data: &[u8]) -> Result<&str> {
+    match std::str::from_utf8(data) {
+        Ok(text) => Ok(text.strip_prefix('\u{feff}').unwrap_or(text)),
+        Err(_) => bail!("An XML document is not valid UTF-8."),
+    }
+}
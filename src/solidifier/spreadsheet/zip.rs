@@ -0,0 +1,324 @@
+use std::io::{self, Write};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use super::deflate::Deflater;
+use super::inflate::inflate;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_SIGNATURE: u32 = 0x06054b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const END_SIZE: usize = 22;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+/// Flag telling that the CRC-32 and the sizes of an entry follow its contents.
+const DATA_DESCRIPTOR_FLAG: u16 = 0x0008;
+/// Version 2.0 of the format, the first one supporting deflated entries and directories.
+const VERSION: u16 = 20;
+/// Modification date of the entries written, 1980-01-01 in MS-DOS format.
+const DATE: u16 = 0x0021;
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut value = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 { 0xedb8_8320 ^ (value >> 1) } else { value >> 1 };
+            bit += 1;
+        }
+        table[index] = value;
+        index += 1;
+    }
+    table
+}
+
+/// Returns the CRC-32 of data following data whose CRC-32 is `crc` (0 for no data).
+pub fn update_crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, byte| {
+        CRC_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+struct Entry {
+    name: String,
+    method: u16,
+    compressed_size: usize,
+    size: usize,
+    offset: usize,
+}
+
+/// A ZIP archive held in memory.
+pub struct Archive<'a> {
+    data: &'a [u8],
+    entries: Vec<Entry>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data.get(offset..offset + 2).context("Unexpected end of the archive.")?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data.get(offset..offset + 4).context("Unexpected end of the archive.")?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+pub fn is_archive(data: &[u8]) -> bool {
+    data.starts_with(&LOCAL_HEADER_SIGNATURE.to_le_bytes())
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        let end = (END_SIZE..=data.len()).map(|offset| data.len() - offset).find(
+            |&offset| u32_at(data, offset).ok() == Some(END_SIGNATURE)
+        ).context("The archive directory is missing.")?;
+        let count = u16_at(data, end + 10)?;
+        let mut offset = u32_at(data, end + 16)? as usize;
+        let mut entries = vec![];
+        for _ in 0..count {
+            ensure!(
+                u32_at(data, offset)? == CENTRAL_HEADER_SIGNATURE,
+                "The archive directory is corrupt.",
+            );
+            let name_length = u16_at(data, offset + 28)? as usize;
+            let extra_length = u16_at(data, offset + 30)? as usize;
+            let comment_length = u16_at(data, offset + 32)? as usize;
+            let name = data.get(offset + 46..offset + 46 + name_length)
+                .context("Unexpected end of the archive.")?;
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: u16_at(data, offset + 10)?,
+                compressed_size: u32_at(data, offset + 20)? as usize,
+                size: u32_at(data, offset + 24)? as usize,
+                offset: u32_at(data, offset + 42)? as usize,
+            });
+            offset += 46 + name_length + extra_length + comment_length;
+        }
+        Ok(Self {
+            data,
+            entries,
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.iter().any(|entry| entry.name == name)
+    }
+
+    /// Extracts the file with the given path within the archive.
+    pub fn file(&self, name: &str) -> Result<Vec<u8>> {
+        let entry = self.entries.iter().find(|entry| entry.name == name)
+            .with_context(|| format!("The archive has no {name}."))?;
+        let data = self.data;
+        ensure!(
+            u32_at(data, entry.offset)? == LOCAL_HEADER_SIGNATURE,
+            "The archive entry {name} is corrupt.",
+        );
+        let start = entry.offset + 30
+            + u16_at(data, entry.offset + 26)? as usize
+            + u16_at(data, entry.offset + 28)? as usize;
+        let compressed = data.get(start..start + entry.compressed_size)
+            .context("Unexpected end of the archive.")?;
+        let contents = match entry.method {
+            STORED => compressed.to_vec(),
+            DEFLATED => inflate(compressed, entry.size)
+                .with_context(|| format!("Could not extract {name} from the archive."))?,
+            method => bail!("The archive entry {name} uses unsupported compression ({method})."),
+        };
+        Ok(contents)
+    }
+}
+
+/// Counts the bytes written through it.
+struct CountingWriter<W: Write> {
+    sink: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.sink.write(buf)?;
+        self.count += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+/// The entry of an archive being written, with the CRC-32 and the size of its contents.
+struct EntryWriter<W: Write> {
+    name: String,
+    offset: u64,
+    deflater: Deflater<CountingWriter<W>>,
+    crc: u32,
+    size: u64,
+}
+
+/// Writes a ZIP archive, compressing its entries with DEFLATE as their contents are written,
+/// so that neither has to be held in memory. The CRC-32 and the sizes of each entry follow
+/// its contents in a data descriptor.
+pub struct ArchiveWriter<W: Write> {
+    /// The sink, unless an entry is being written to it.
+    sink: Option<CountingWriter<W>>,
+    entry: Option<EntryWriter<W>>,
+    directory: Vec<u8>,
+    count: u16,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(sink: W) -> Self {
+        Self {
+            sink: Some(CountingWriter {
+                sink,
+                count: 0,
+            }),
+            entry: None,
+            directory: vec![],
+            count: 0,
+        }
+    }
+
+    /// Starts a new entry, finishing the current one (if any); the contents of the entry
+    /// are written to the archive writer itself.
+    pub fn start_entry(&mut self, name: &str) -> Result<()> {
+        self.finish_entry()?;
+        let mut sink = self.sink.take().context("The archive is already complete.")?;
+        let offset = sink.count;
+        sink.write_all(&LOCAL_HEADER_SIGNATURE.to_le_bytes())?;
+        sink.write_all(&entry_fields(name, 0, 0, 0))?;
+        sink.write_all(name.as_bytes())?;
+        self.entry = Some(EntryWriter {
+            name: String::from(name),
+            offset,
+            deflater: Deflater::new(sink),
+            crc: 0,
+            size: 0,
+        });
+        Ok(())
+    }
+
+    fn finish_entry(&mut self) -> Result<()> {
+        let Some(mut entry) = self.entry.take() else {
+            return Ok(());
+        };
+        entry.deflater.finish()?;
+        let mut sink = entry.deflater.into_inner();
+        let data_offset = entry.offset + 30 + entry.name.len() as u64;
+        let compressed_size = u32::try_from(sink.count - data_offset).ok();
+        let size = u32::try_from(entry.size).ok();
+        let (Some(compressed_size), Some(size)) = (compressed_size, size) else {
+            bail!("{} is too large for an archive.", entry.name);
+        };
+        sink.write_all(&DATA_DESCRIPTOR_SIGNATURE.to_le_bytes())?;
+        for value in [entry.crc, compressed_size, size] {
+            sink.write_all(&value.to_le_bytes())?;
+        }
+        self.sink = Some(sink);
+        let directory = &mut self.directory;
+        directory.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+        directory.extend_from_slice(&VERSION.to_le_bytes());
+        directory.extend_from_slice(&entry_fields(&entry.name, entry.crc, compressed_size, size));
+        // No comment, disk number 0 and no attributes.
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(
+            &u32::try_from(entry.offset).context("The archive is too large.")?.to_le_bytes()
+        );
+        directory.extend_from_slice(entry.name.as_bytes());
+        self.count = self.count.checked_add(1).context("The archive has too many entries.")?;
+        Ok(())
+    }
+
+    /// Finishes the last entry and writes the central directory, completing the archive.
+    pub fn finish(&mut self) -> Result<()> {
+        self.finish_entry()?;
+        let mut sink = self.sink.take().context("The archive is already complete.")?;
+        let offset = u32::try_from(sink.count).context("The archive is too large.")?;
+        sink.write_all(&self.directory)?;
+        sink.write_all(&END_SIGNATURE.to_le_bytes())?;
+        sink.write_all(&[0; 4])?;
+        sink.write_all(&self.count.to_le_bytes())?;
+        sink.write_all(&self.count.to_le_bytes())?;
+        sink.write_all(&(self.directory.len() as u32).to_le_bytes())?;
+        sink.write_all(&offset.to_le_bytes())?;
+        sink.write_all(&0u16.to_le_bytes())?;
+        sink.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ArchiveWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let entry = self.entry.as_mut().ok_or_else(|| io::Error::other("No entry is started."))?;
+        let count = entry.deflater.write(buf)?;
+        entry.crc = update_crc32(entry.crc, &buf[..count]);
+        entry.size += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the fields shared by the local and the central headers of an entry, from
+/// the version needed on (up to the name).
+fn entry_fields(name: &str, crc: u32, compressed_size: u32, size: u32) -> Vec<u8> {
+    let mut fields = vec![];
+    for value in [VERSION, DATA_DESCRIPTOR_FLAG, DEFLATED, 0, DATE] {
+        fields.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [crc, compressed_size, size] {
+        fields.extend_from_slice(&value.to_le_bytes());
+    }
+    fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    fields.extend_from_slice(&0u16.to_le_bytes());
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_archive_is_read_back() {
+        let contents = b"contents compressed with DEFLATE ".repeat(1000);
+        let mut data = vec![];
+        let mut writer = ArchiveWriter::new(&mut data);
+        writer.start_entry("empty.txt").unwrap();
+        writer.start_entry("dir/file.txt").unwrap();
+        for chunk in contents.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        assert!(writer.start_entry("late.txt").is_err());
+        assert!(is_archive(&data));
+        assert!(data.len() < contents.len() / 10);
+        let archive = Archive::new(&data).unwrap();
+        assert!(archive.contains("empty.txt") && !archive.contains("late.txt"));
+        assert_eq!(archive.file("empty.txt").unwrap(), b"");
+        assert_eq!(archive.file("dir/file.txt").unwrap(), contents);
+        assert!(archive.file("missing.txt").is_err());
+    }
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(update_crc32(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(update_crc32(update_crc32(0, b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn truncated_archive_is_rejected() {
+        let mut data = vec![];
+        let mut writer = ArchiveWriter::new(&mut data);
+        writer.start_entry("file.txt").unwrap();
+        writer.write_all(b"contents").unwrap();
+        writer.finish().unwrap();
+        assert!(Archive::new(&data[..data.len() - 1]).is_err());
+        assert!(Archive::new(&data[20..]).is_err());
+    }
+}
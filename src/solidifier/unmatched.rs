@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{canonicalize, create_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::files::is_same_file;
+use crate::params::Params;
+
+use super::{input_name, is_standard_stream, InputFormat};
+use super::compression::{compressed, uncompressed_path, CompressedFile};
+use super::delimited::{as_strs, record_writer, RecordWrite};
+use super::encodings::encoded;
+use super::json::SourceWriter;
+use super::sheet::Sheet;
+use super::spreadsheet::xlsx_writer;
+
+/// Writer of the unmatched records of a single input.
+enum Writer {
+    Records(Box<dyn RecordWrite>),
+    Json(SourceWriter<Box<dyn Write>>),
+}
+
+/// Writes the records of each input that have no counterpart in some other input
+/// to a file of the same name, format and encoding in a given directory. Records of stdin
+/// are written to a file named `stdin` (`stdin.json`, `stdin.ndjson` or `stdin.xlsx` unless
+/// it is delimited text), and those of spreadsheets as xlsx, with the extension changed
+/// accordingly (e.g. `data.ods.gz` becomes `data.xlsx.gz`).
+pub struct UnmatchedWriter {
+    writers: Vec<(PathBuf, Writer, CompressedFile)>,
+}
+
+/// Returns the name of the file the unmatched records of an input are written to.
+fn file_name(input: &Path, format: &InputFormat) -> Result<OsString> {
+    if is_standard_stream(input) {
+        return Ok(OsString::from(match format {
+            InputFormat::Delimited { .. } => "stdin",
+            InputFormat::Spreadsheet => "stdin.xlsx",
+            InputFormat::Json { layout, .. } if layout.is_array => "stdin.json",
+            InputFormat::Json { .. } => "stdin.ndjson",
+        }));
+    }
+    let name = input.file_name()
+        .with_context(|| format!("{} is not a file name.", input.display()))?;
+    if !matches!(format, InputFormat::Spreadsheet) {
+        return Ok(name.to_os_string());
+    }
+    let uncompressed = uncompressed_path(Path::new(name));
+    let mut xlsx_name = uncompressed.with_extension("xlsx").into_os_string();
+    if uncompressed.as_os_str() != name {
+        if let Some(extension) = Path::new(name).extension() {
+            xlsx_name.push(".");
+            xlsx_name.push(extension);
+        }
+    }
+    Ok(xlsx_name)
+}
+
+impl UnmatchedWriter {
+    pub fn new(
+        directory: &Path,
+        params: &Params,
+        sheets: &[Sheet],
+        formats: Vec<InputFormat>,
+    ) -> Result<Self> {
+        create_dir_all(directory)
+            .with_context(|| format!("Could not create {}.", directory.display()))?;
+        let directory = canonicalize(directory)
+            .with_context(|| format!("Could not open {}.", directory.display()))?;
+        let mut names = HashSet::new();
+        let mut paths = vec![];
+        for (input, format) in params.inputs.iter().zip(&formats) {
+            let name = file_name(input, format)?;
+            ensure!(
+                !names.contains(&name),
+                "There are multiple inputs named {}, so their unmatched records cannot be written \
+                 to the same directory.",
+                Path::new(&name).display(),
+            );
+            let path = directory.join(&name);
+            names.insert(name);
+            ensure!(
+                is_standard_stream(input) || !is_same_file(input, &path),
+                "Unmatched records of {} would overwrite the input itself.",
+                input_name(input),
+            );
+            ensure!(
+                is_standard_stream(&params.output) || !is_same_file(&params.output, &path),
+                "Unmatched records of {} would overwrite the output.",
+                input_name(input),
+            );
+            paths.push(path);
+        }
+        let mut writers = vec![];
+        for ((path, sheet), format) in paths.into_iter().zip(sheets).zip(formats) {
+            let error_context = || format!("Could not write data to {}.", path.display());
+            let file = File::create(&path)
+                .with_context(|| format!("Could not open {} for writing.", path.display()))?;
+            let compressed = compressed(file, &path)?;
+            let sink = BufWriter::new(compressed.sink());
+            let writer = match format {
+                InputFormat::Delimited { encoding, has_bom } => {
+                    let sink = encoded(sink, encoding, has_bom).with_context(error_context)?;
+                    let mut writer = record_writer(sink, sheet.delimiter());
+                    if let Some(header) = sheet.header() {
+                        writer.write_record(&as_strs(header)).with_context(error_context)?;
+                    }
+                    Writer::Records(writer)
+                },
+                InputFormat::Spreadsheet => {
+                    let mut writer = xlsx_writer(sink, params.highlight_filler);
+                    if let Some(header) = sheet.header() {
+                        writer.write_record(&as_strs(header)).with_context(error_context)?;
+                    }
+                    Writer::Records(writer)
+                },
+                InputFormat::Json { encoding, has_bom, layout } => {
+                    let sink = encoded(sink, encoding, has_bom).with_context(error_context)?;
+                    Writer::Json(SourceWriter::new(sink, layout))
+                },
+            };
+            writers.push((path, writer, compressed));
+        }
+        Ok(Self {
+            writers,
+        })
+    }
+
+    /// Writes the given records of each input, along with their (0-based) indices among
+    /// the data records of the input.
+    pub fn write(&mut self, unmatched: &[Vec<(usize, &[String])>]) -> Result<()> {
+        for ((path, writer, _), records) in self.writers.iter_mut().zip(unmatched) {
+            for (index, record) in records {
+                match writer {
+                    Writer::Records(writer) => writer.write_record(&as_strs(record)),
+                    Writer::Json(writer) => writer.write(*index, record),
+                }.with_context(|| format!("Could not write data to {}.", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        for (path, writer, compressed) in self.writers {
+            match writer {
+                Writer::Records(mut writer) => writer.flush(),
+                Writer::Json(mut writer) => writer.flush(),
+            }.and_then(|_| Ok(compressed.finish()?))
+                .with_context(|| format!("Could not write data to {}.", path.display()))?;
+        }
+        Ok(())
+    }
+}
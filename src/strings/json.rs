@@ -0,0 +1,22 @@
+pub trait Json {
+    fn to_json(&self) -> String;
+}
+
+impl Json for str {
+    fn to_json(&self) -> String {
+        let mut result = String::from('"');
+        for c in self.chars() {
+            match c {
+                '"' => result.push_str("\\\""),
+                '\\' => result.push_str("\\\\"),
+                '\n' => result.push_str("\\n"),
+                '\r' => result.push_str("\\r"),
+                '\t' => result.push_str("\\t"),
+                c if c < ' ' => result.push_str(&format!("\\u{:04x}", c as u32)),
+                c => result.push(c),
+            }
+        }
+        result.push('"');
+        result
+    }
+}
@@ -1,2 +1,5 @@
 pub mod countable;
+pub mod json;
 pub mod literally;
+pub mod normalization;
+mod unicode_tables;
@@ -0,0 +1,222 @@
+use std::cmp::Ordering;
+
+use super::unicode_tables::{
+    CANONICAL_DECOMPOSITIONS,
+    CASE_FOLDING_EXCEPTIONS,
+    COMBINING_CLASSES,
+    COMPATIBILITY_DECOMPOSITIONS,
+    COMPOSITIONS,
+    PUNCTUATION,
+};
+
+const HANGUL_S_BASE: u32 = 0xAC00;
+const HANGUL_L_BASE: u32 = 0x1100;
+const HANGUL_V_BASE: u32 = 0x1161;
+const HANGUL_T_BASE: u32 = 0x11A7;
+const HANGUL_L_COUNT: u32 = 19;
+const HANGUL_V_COUNT: u32 = 21;
+const HANGUL_T_COUNT: u32 = 28;
+const HANGUL_N_COUNT: u32 = HANGUL_V_COUNT * HANGUL_T_COUNT;
+const HANGUL_S_COUNT: u32 = HANGUL_L_COUNT * HANGUL_N_COUNT;
+
+pub trait Normalize {
+    fn nfc(&self) -> String;
+    fn nfkc(&self) -> String;
+    fn without_diacritics(&self) -> String;
+    fn case_folded(&self) -> String;
+    fn without_punctuation(&self) -> String;
+}
+
+fn lookup<T: Copy>(table: &[(char, T)], char: char) -> Option<T> {
+    table.binary_search_by_key(&char, |(key, _)| *key).ok().map(|index| table[index].1)
+}
+
+fn compare_range(first: char, last: char, char: char) -> Ordering {
+    if last < char {
+        Ordering::Less
+    } else if first > char {
+        Ordering::Greater
+    } else {
+        Ordering::Equal
+    }
+}
+
+fn combining_class(char: char) -> u8 {
+    COMBINING_CLASSES.binary_search_by(|(first, last, _)| compare_range(*first, *last, char))
+        .map_or(0, |index| COMBINING_CLASSES[index].2)
+}
+
+fn is_punctuation(char: char) -> bool {
+    PUNCTUATION.binary_search_by(|(first, last)| compare_range(*first, *last, char)).is_ok()
+}
+
+fn decompose_char(char: char, compatibility: bool, output: &mut Vec<char>) {
+    let code = char as u32;
+    if (HANGUL_S_BASE..HANGUL_S_BASE + HANGUL_S_COUNT).contains(&code) {
+        let index = code - HANGUL_S_BASE;
+        let to_char = |code| char::from_u32(code).unwrap();
+        output.push(to_char(HANGUL_L_BASE + index / HANGUL_N_COUNT));
+        output.push(to_char(HANGUL_V_BASE + (index % HANGUL_N_COUNT) / HANGUL_T_COUNT));
+        if !index.is_multiple_of(HANGUL_T_COUNT) {
+            output.push(to_char(HANGUL_T_BASE + index % HANGUL_T_COUNT));
+        }
+    } else if let Some(decomposition) = lookup(CANONICAL_DECOMPOSITIONS, char).or(
+        if compatibility { lookup(COMPATIBILITY_DECOMPOSITIONS, char) } else { None }
+    ) {
+        for part in decomposition {
+            decompose_char(*part, compatibility, output);
+        }
+    } else {
+        output.push(char);
+    }
+}
+
+fn decompose(value: &str, compatibility: bool) -> Vec<char> {
+    let mut chars = vec![];
+    for char in value.chars() {
+        decompose_char(char, compatibility, &mut chars);
+    }
+    let mut start = 0;
+    while start < chars.len() {
+        let end = start + chars[start..].iter().take_while(
+            |char| combining_class(**char) > 0
+        ).count();
+        chars[start..end].sort_by_key(|char| combining_class(*char));
+        start = end + 1;
+    }
+    chars
+}
+
+fn compose_pair(first: char, second: char) -> Option<char> {
+    let (first_code, second_code) = (first as u32, second as u32);
+    if (HANGUL_L_BASE..HANGUL_L_BASE + HANGUL_L_COUNT).contains(&first_code)
+        && (HANGUL_V_BASE..HANGUL_V_BASE + HANGUL_V_COUNT).contains(&second_code) {
+        char::from_u32(HANGUL_S_BASE + ((first_code - HANGUL_L_BASE) * HANGUL_V_COUNT
+            + second_code - HANGUL_V_BASE) * HANGUL_T_COUNT)
+    } else if (HANGUL_S_BASE..HANGUL_S_BASE + HANGUL_S_COUNT).contains(&first_code)
+        && (first_code - HANGUL_S_BASE).is_multiple_of(HANGUL_T_COUNT)
+        && (HANGUL_T_BASE + 1..HANGUL_T_BASE + HANGUL_T_COUNT).contains(&second_code) {
+        char::from_u32(first_code + second_code - HANGUL_T_BASE)
+    } else {
+        COMPOSITIONS.binary_search_by_key(&(first, second), |(first, second, _)| (*first, *second))
+            .ok().map(|index| COMPOSITIONS[index].2)
+    }
+}
+
+fn compose(chars: Vec<char>) -> String {
+    let mut result: Vec<char> = vec![];
+    let mut starter_index = None;
+    let mut last_class = 0;
+    for char in chars {
+        let class = combining_class(char);
+        if let Some(index) = starter_index {
+            let is_blocked = result.len() - 1 != index && (last_class == 0 || last_class >= class);
+            if !is_blocked {
+                if let Some(composite) = compose_pair(result[index], char) {
+                    result[index] = composite;
+                    continue;
+                }
+            }
+        }
+        if class == 0 {
+            starter_index = Some(result.len());
+        }
+        last_class = class;
+        result.push(char);
+    }
+    result.into_iter().collect()
+}
+
+impl Normalize for str {
+    fn nfc(&self) -> String {
+        compose(decompose(self, false))
+    }
+
+    fn nfkc(&self) -> String {
+        compose(decompose(self, true))
+    }
+
+    fn without_diacritics(&self) -> String {
+        compose(decompose(self, false).into_iter().filter(
+            |char| combining_class(*char) == 0
+        ).collect())
+    }
+
+    fn case_folded(&self) -> String {
+        self.chars().map(|char| if let Some(folded) = lookup(CASE_FOLDING_EXCEPTIONS, char) {
+            String::from(folded)
+        } else {
+            char.to_lowercase().collect()
+        }).collect()
+    }
+
+    fn without_punctuation(&self) -> String {
+        self.chars().filter(|char| !is_punctuation(*char)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latin_letters_are_composed() {
+        assert_eq!("e\u{301}".nfc(), "\u{E9}");
+        assert_eq!("\u{E9}".nfc(), "\u{E9}");
+        assert_eq!(decompose("\u{E9}", false), ['e', '\u{301}']);
+        // Singletons are replaced by their canonical equivalents.
+        assert_eq!("\u{212B}".nfc(), "\u{C5}");
+        // Characters excluded from composition stay decomposed.
+        assert_eq!("\u{958}".nfc(), "\u{915}\u{93C}");
+    }
+
+    #[test]
+    fn hangul_syllables_are_composed_and_decomposed() {
+        assert_eq!(decompose("\u{AC01}", false), ['\u{1100}', '\u{1161}', '\u{11A8}']);
+        assert_eq!(decompose("\u{AC00}", false), ['\u{1100}', '\u{1161}']);
+        assert_eq!("\u{1100}\u{1161}\u{11A8}".nfc(), "\u{AC01}");
+        assert_eq!("\u{AC00}\u{11A8}".nfc(), "\u{AC01}");
+        for code in HANGUL_S_BASE..HANGUL_S_BASE + HANGUL_S_COUNT {
+            let syllable = String::from(char::from_u32(code).unwrap());
+            assert_eq!(compose(decompose(&syllable, false)), syllable);
+        }
+    }
+
+    #[test]
+    fn combining_marks_are_reordered() {
+        assert_eq!(decompose("a\u{301}\u{323}", false), ['a', '\u{323}', '\u{301}']);
+        assert_eq!("e\u{302}\u{323}".nfc(), "\u{1EC7}");
+        assert_eq!("e\u{323}\u{302}".nfc(), "\u{1EC7}");
+        assert_eq!("a\u{301}\u{323}".nfc(), "\u{1EA1}\u{301}");
+        // The second acute accent is blocked by the first one.
+        assert_eq!("a\u{301}\u{301}".nfc(), "\u{E1}\u{301}");
+    }
+
+    #[test]
+    fn compatibility_characters_are_replaced() {
+        assert_eq!("\u{FB01}".nfkc(), "fi");
+        assert_eq!("\u{FB01}".nfc(), "\u{FB01}");
+        assert_eq!("\u{1C4}".nfkc(), "D\u{17D}");
+        assert_eq!("\u{2460}".nfkc(), "1");
+        assert_eq!("\u{FF76}\u{FF9E}".nfkc(), "\u{30AC}");
+    }
+
+    #[test]
+    fn normalization_of_decomposable_characters_is_idempotent() {
+        let decomposed = CANONICAL_DECOMPOSITIONS.iter().chain(COMPATIBILITY_DECOMPOSITIONS);
+        for char in decomposed.map(|(char, _)| *char) {
+            let nfc = char.to_string().nfc();
+            assert_eq!(nfc.nfc(), nfc, "{char:?}");
+            let nfkc = char.to_string().nfkc();
+            assert_eq!(nfkc.nfkc(), nfkc, "{char:?}");
+        }
+    }
+
+    #[test]
+    fn diacritics_punctuation_and_case_are_removed() {
+        assert_eq!("Cre\u{300}me bru\u{302}le\u{301}e".without_diacritics(), "Creme brulee");
+        assert_eq!("\u{C5}ngstr\u{F6}m".without_diacritics(), "Angstrom");
+        assert_eq!("Stra\u{DF}e \u{3A3}".case_folded(), "strasse \u{3C3}");
+        assert_eq!("a-b, (c)! 50%".without_punctuation(), "ab c 50");
+    }
+}
@@ -1,8 +1,11 @@
+use std::io::{Result, Write};
+
 const DIVIDER: &str = "----------";
 
-pub fn warn(lines: &[&str]) -> () {
-    eprintln!("{}", DIVIDER);
+pub fn write_warning(writer: &mut dyn Write, lines: &[String]) -> Result<()> {
+    writeln!(writer, "{}", DIVIDER)?;
     for line in lines {
-        eprintln!("{}", line);
+        writeln!(writer, "{}", line)?;
     }
+    Ok(())
 }